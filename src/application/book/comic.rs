@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::Command;
+
+use roxmltree::Document;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+use crate::application::book::Cover;
+use crate::error::RootsError;
+
+const COMIC_INFO: &str = "comicinfo.xml";
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["jpg", "jpeg", "png", "gif", "webp"];
+
+/// The kind of archive holding a comic's pages; CBZ, CB7 and CBR name the
+/// usual ones, but the extension is often wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Archive {
+    Zip,
+    SevenZip,
+    /// Read with `bsdtar` from libarchive, as there is no RAR decoder in
+    /// Rust.
+    Rar,
+}
+
+impl Archive {
+    /// The archive type `magic` starts with.
+    pub fn detect(magic: &[u8]) -> Option<Archive> {
+        if magic.starts_with(b"PK\x03\x04") {
+            Some(Archive::Zip)
+        } else if magic.starts_with(b"7z\xbc\xaf\x27\x1c") {
+            Some(Archive::SevenZip)
+        } else if magic.starts_with(b"Rar!\x1a\x07") {
+            Some(Archive::Rar)
+        } else {
+            None
+        }
+    }
+
+    fn open(path: &Path) -> Result<Archive, RootsError> {
+        let mut magic = Vec::new();
+        File::open(path)
+            .and_then(|file| file.take(8).read_to_end(&mut magic))
+            .map_err(|error| RootsError::io(path, error))?;
+        Archive::detect(&magic).ok_or_else(|| RootsError::corrupt(path, "not a comic book archive"))
+    }
+}
+
+impl Display for Archive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Archive::Zip => write!(f, "ZIP"),
+            Archive::SevenZip => write!(f, "7z"),
+            Archive::Rar => write!(f, "RAR"),
+        }
+    }
+}
+
+/// The fields of a `ComicInfo.xml`, the metadata file ComicRack introduced
+/// and most comic tools write.
+#[derive(Debug, Default)]
+pub struct ComicInfo {
+    fields: HashMap<String, String>,
+}
+
+impl ComicInfo {
+    pub fn parse(xml: &str) -> Result<ComicInfo, String> {
+        let document = Document::parse(xml.trim_start_matches('\u{feff}')).map_err(|error| error.to_string())?;
+        let fields = document.root_element()
+            .children()
+            .filter(|node| node.is_element())
+            .filter_map(|node| Some((node.tag_name().name().to_string(), node.text()?.trim().to_string())))
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        Ok(ComicInfo { fields })
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.fields.get(name).cloned()
+    }
+
+    /// The values of a comma separated field such as `Writer` or `Genre`.
+    pub fn list(&self, name: &str) -> Vec<String> {
+        self.fields.get(name)
+            .map(|values| values.split(',').map(str::trim).filter(|value| !value.is_empty()).map(String::from).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// The `ComicInfo.xml` of the comic at `path`, if it has one.
+pub fn comic_info(path: &Path) -> Result<Option<ComicInfo>, RootsError> {
+    let archive = Archive::open(path)?;
+    let name = match entries(path, archive)?.into_iter().find(|name| file_name(name).to_lowercase() == COMIC_INFO) {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let data = read_entry(path, archive, &name)?.unwrap_or_default();
+    let xml = String::from_utf8_lossy(&data);
+    ComicInfo::parse(&xml).map(Some).map_err(|error| RootsError::corrupt(path, format!("{}: {}", name, error)))
+}
+
+/// The first page of the comic at `path`, by file name.
+pub fn cover(path: &Path) -> Result<Option<Cover>, RootsError> {
+    let archive = Archive::open(path)?;
+    let mut images = entries(path, archive)?
+        .into_iter()
+        .filter(|name| IMAGE_EXTENSIONS.iter().any(|extension| name.to_lowercase().ends_with(&format!(".{}", extension))))
+        // scanners' and macOS' metadata files are not pages
+        .filter(|name| !name.starts_with("__MACOSX/") && !file_name(name).starts_with('.'))
+        .collect::<Vec<String>>();
+    images.sort_by_key(|name| name.to_lowercase());
+    match images.first() {
+        Some(name) => Ok(read_entry(path, archive, name)?.and_then(Cover::new)),
+        None => Ok(None),
+    }
+}
+
+fn file_name(name: &str) -> &str {
+    name.rsplit(['/', '\\']).next().unwrap_or(name)
+}
+
+/// The names of the files in the archive, directories excluded.
+fn entries(path: &Path, archive: Archive) -> Result<Vec<String>, RootsError> {
+    match archive {
+        Archive::Zip => {
+            let zip = open_zip(path)?;
+            Ok(zip.file_names().filter(|name| !name.ends_with('/')).map(String::from).collect())
+        }
+        Archive::SevenZip => {
+            let reader = SevenZReader::open(path, Password::empty()).map_err(|error| RootsError::corrupt(path, error))?;
+            Ok(reader.archive().files.iter()
+                .filter(|entry| !entry.is_directory())
+                .map(|entry| entry.name().to_string())
+                .collect())
+        }
+        Archive::Rar => {
+            let listing = bsdtar(path, "-tf", &[])?;
+            Ok(String::from_utf8_lossy(&listing).lines().filter(|name| !name.ends_with('/')).map(String::from).collect())
+        }
+    }
+}
+
+/// The content of the file `name` in the archive, none when it is missing.
+fn read_entry(path: &Path, archive: Archive, name: &str) -> Result<Option<Vec<u8>>, RootsError> {
+    match archive {
+        Archive::Zip => {
+            let mut zip = open_zip(path)?;
+            let mut entry = match zip.by_name(name) {
+                Ok(entry) => entry,
+                Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+                Err(error) => return Err(RootsError::corrupt(path, error)),
+            };
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).map_err(|error| RootsError::corrupt(path, error))?;
+            Ok(Some(data))
+        }
+        Archive::SevenZip => {
+            let mut reader = SevenZReader::open(path, Password::empty()).map_err(|error| RootsError::corrupt(path, error))?;
+            let mut data = None;
+            // entries of solid archives can only be decoded in order, so
+            // the ones before `name` are read and dropped
+            reader.for_each_entries(|entry, content| {
+                if entry.name() != name {
+                    io::copy(content, &mut io::sink())?;
+                    return Ok(true);
+                }
+                let mut bytes = Vec::new();
+                content.read_to_end(&mut bytes)?;
+                data = Some(bytes);
+                Ok(false)
+            }).map_err(|error| RootsError::corrupt(path, error))?;
+            Ok(data)
+        }
+        Archive::Rar => {
+            let data = bsdtar(path, "-xOf", &[&pattern(name)])?;
+            Ok(Some(data).filter(|data| !data.is_empty()))
+        }
+    }
+}
+
+fn open_zip(path: &Path) -> Result<ZipArchive<File>, RootsError> {
+    let file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))
+}
+
+/// The `bsdtar` pattern matching the entry `name` alone; names are matched
+/// as globs, so their wildcards are escaped.
+fn pattern(name: &str) -> String {
+    let mut pattern = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '*' | '?' | '[' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+/// The output of `bsdtar` run with `option` on the archive at `path` and
+/// the patterns `names`. Without `bsdtar` RAR archives are unsupported.
+fn bsdtar(path: &Path, option: &str, names: &[&str]) -> Result<Vec<u8>, RootsError> {
+    let output = Command::new("bsdtar")
+        .arg(option)
+        .arg(path)
+        // names starting with a dash are not options
+        .arg("--")
+        .args(names)
+        .output()
+        .map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => RootsError::UnsupportedFormat(path.to_path_buf()),
+            _ => RootsError::io(path, error),
+        })?;
+    if !output.status.success() {
+        return Err(RootsError::corrupt(path, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Write;
+
+    use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    pub const COMIC_INFO_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<ComicInfo xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance">
+  <Title>The Pit</Title>
+  <Series>Watchtower</Series>
+  <Number>3</Number>
+  <Volume>2</Volume>
+  <Summary>The tower falls.</Summary>
+  <Year>1987</Year>
+  <Month>9</Month>
+  <Writer>Ann Writer, Bo Writer</Writer>
+  <Penciller>Cy Artist</Penciller>
+  <Colorist>Di Colour</Colorist>
+  <Translator>Ed Trans</Translator>
+  <Publisher>Foghorn</Publisher>
+  <Genre>Superhero, Mystery</Genre>
+  <LanguageISO>en_us</LanguageISO>
+  <Notes> </Notes>
+</ComicInfo>"#;
+
+    pub const PAGES: [(&str, &[u8]); 3] = [
+        ("pages/002.jpg", b"\xff\xd8\xff\xe0 second"),
+        ("pages/001.png", b"\x89PNG first"),
+        ("__MACOSX/pages/._001.png", b"\x00\x05\x16\x07"),
+    ];
+
+    /// A CBZ holding `ComicInfo.xml` and `PAGES`.
+    pub fn cbz(path: &Path) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in PAGES.iter().copied().chain([("ComicInfo.xml", COMIC_INFO_XML.as_bytes())]) {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn cb7(path: &Path) {
+        let mut writer = SevenZWriter::create(path).unwrap();
+        for (name, data) in [("ComicInfo.xml", COMIC_INFO_XML.as_bytes())].into_iter().chain(PAGES.iter().copied()) {
+            let mut entry = SevenZArchiveEntry::new();
+            entry.name = name.to_string();
+            entry.has_stream = true;
+            writer.push_archive_entry(entry, Some(data)).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn comic_info_and_first_page_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip = dir.path().join("watchtower.cbz");
+        cbz(&zip);
+        let seven_zip = dir.path().join("watchtower.cb7");
+        cb7(&seven_zip);
+        for path in [zip, seven_zip] {
+            let info = comic_info(&path).unwrap().unwrap();
+            assert_eq!(Some("Watchtower".to_string()), info.get("Series"));
+            assert_eq!(vec!["Ann Writer", "Bo Writer"], info.list("Writer"));
+            assert_eq!(None, info.get("Notes"));
+            let cover = cover(&path).unwrap().unwrap();
+            assert_eq!(b"\x89PNG first".to_vec(), cover.data);
+        }
+    }
+
+    #[test]
+    fn entry_names_are_matched_literally() {
+        assert_eq!("pages/001.jpg", pattern("pages/001.jpg"));
+        assert_eq!(r"\[cover] \*\?\\.jpg", pattern(r"[cover] *?\.jpg"));
+    }
+
+    #[test]
+    fn archives_are_detected_by_magic() {
+        assert_eq!(Some(Archive::Zip), Archive::detect(b"PK\x03\x04\x14\x00"));
+        assert_eq!(Some(Archive::SevenZip), Archive::detect(b"7z\xbc\xaf\x27\x1c\x00\x04"));
+        assert_eq!(Some(Archive::Rar), Archive::detect(b"Rar!\x1a\x07\x01\x00"));
+        assert_eq!(None, Archive::detect(b"%PDF-1.4"));
+    }
+}
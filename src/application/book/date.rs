@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, SecondsFormat, Timelike, Utc};
+use regex::Regex;
+
+/// Day formats other than ISO 8601, `%B` accepts abbreviated months too.
+const DAY_FORMATS: [&str; 4] = ["%Y/%m/%d", "%B %d, %Y", "%d %B %Y", "%d %B, %Y"];
+
+/// Month formats, parsed with the first of the month before them.
+const MONTH_FORMATS: [&str; 3] = ["%d %B %Y", "%d %B, %Y", "%d %Y-%m"];
+
+/// A publication date, known to the year, the month, the day or the moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialDate {
+    Year(i32),
+    Month(i32, u32),
+    Day(NaiveDate),
+    Moment(DateTime<Utc>),
+}
+
+impl PartialDate {
+    /// The date written as `1910`, `1910-05`, `1910-05-18`, an RFC 3339 or
+    /// RFC 2822 timestamp, `May 18, 1910`, `18 May 1910` or `May 1910`.
+    /// Timestamps at midnight UTC are taken as days, as tools store days
+    /// that way.
+    pub fn parse(text: &str) -> Option<PartialDate> {
+        let text = text.trim();
+        if let Ok(moment) = DateTime::parse_from_rfc3339(text).or_else(|_| DateTime::parse_from_rfc2822(text)) {
+            return Some(PartialDate::moment(moment.with_timezone(&Utc)));
+        }
+        if let Ok(moment) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")) {
+            return Some(PartialDate::moment(moment.and_utc()));
+        }
+        if let Some(day) = ["%Y-%m-%d"].iter().chain(DAY_FORMATS.iter()).find_map(|format| NaiveDate::parse_from_str(text, format).ok()) {
+            return Some(PartialDate::Day(day));
+        }
+        let month = MONTH_FORMATS.iter().find_map(|format| NaiveDate::parse_from_str(&format!("1 {}", text), format).ok());
+        if let Some(month) = month {
+            return Some(PartialDate::Month(month.year(), month.month()));
+        }
+        Some(text).filter(|text| text.len() == 4).and_then(|year| year.parse::<i32>().ok()).map(PartialDate::Year)
+    }
+
+    /// The date in free text such as `Copyright 1910, London`, the first
+    /// plausible year when the whole text is not a date.
+    pub fn find(text: &str) -> Option<PartialDate> {
+        static YEAR: OnceLock<Regex> = OnceLock::new();
+        let year = YEAR.get_or_init(|| Regex::new(r"\b(1[5-9]\d\d|20\d\d)\b").unwrap());
+        PartialDate::parse(text)
+            .or_else(|| year.captures(text).and_then(|captures| captures[1].parse::<i32>().ok()).map(PartialDate::Year))
+    }
+
+    /// The date of `year`, `month` and `day`, as precise as the parts given.
+    pub fn from_parts(year: i32, month: Option<u32>, day: Option<u32>) -> Option<PartialDate> {
+        match (month, day) {
+            (Some(month), Some(day)) => NaiveDate::from_ymd_opt(year, month, day).map(PartialDate::Day),
+            (Some(month), None) if (1..=12).contains(&month) => Some(PartialDate::Month(year, month)),
+            (Some(_), None) => None,
+            (None, _) => Some(PartialDate::Year(year)),
+        }
+    }
+
+    fn moment(moment: DateTime<Utc>) -> PartialDate {
+        if moment.num_seconds_from_midnight() == 0 {
+            PartialDate::Day(moment.date_naive())
+        } else {
+            PartialDate::Moment(moment)
+        }
+    }
+
+    pub fn year(&self) -> i32 {
+        match self {
+            PartialDate::Year(year) | PartialDate::Month(year, _) => *year,
+            PartialDate::Day(day) => day.year(),
+            PartialDate::Moment(moment) => moment.year(),
+        }
+    }
+}
+
+/// ISO 8601 to the precision known, so that stored dates sort and compare
+/// by prefix.
+impl Display for PartialDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            PartialDate::Year(year) => write!(f, "{:04}", year),
+            PartialDate::Month(year, month) => write!(f, "{:04}-{:02}", year, month),
+            PartialDate::Day(day) => write!(f, "{}", day.format("%Y-%m-%d")),
+            PartialDate::Moment(moment) => write!(f, "{}", moment.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+
+    use super::*;
+
+    use self::rstest::*;
+
+    #[rstest]
+    #[case("1910", "1910")]
+    #[case("1910-05", "1910-05")]
+    #[case("May 1910", "1910-05")]
+    #[case("August, 2008", "2008-08")]
+    #[case("1910-05-18", "1910-05-18")]
+    #[case(" May 18, 1910 ", "1910-05-18")]
+    #[case("18 May 1910", "1910-05-18")]
+    #[case("1910/05/18", "1910-05-18")]
+    #[case("2009-03-11T00:00:00+00:00", "2009-03-11")]
+    #[case("2009-03-11T09:30:00+01:00", "2009-03-11T08:30:00Z")]
+    #[case("2009-03-11T09:30:00", "2009-03-11T09:30:00Z")]
+    #[case("Wed, 11 Mar 2009 09:30:00 +0000", "2009-03-11T09:30:00Z")]
+    fn dates_keep_their_precision(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(Some(expected.to_string()), PartialDate::parse(text).map(|date| date.to_string()));
+    }
+
+    #[rstest]
+    #[case("unknown")]
+    #[case("19100")]
+    #[case("1910-13")]
+    #[case("")]
+    fn other_text_is_no_date(#[case] text: &str) {
+        assert_eq!(None, PartialDate::parse(text));
+    }
+
+    #[test]
+    fn years_are_found_in_free_text() {
+        assert_eq!(Some(PartialDate::Year(1910)), PartialDate::find("Copyright 1910, London"));
+        assert_eq!(Some(PartialDate::Month(1910, 5)), PartialDate::find("May 1910"));
+        assert_eq!(None, PartialDate::find("ISBN 0141182131"));
+    }
+
+    #[test]
+    fn parts_give_the_precision() {
+        assert_eq!(Some(PartialDate::Year(1987)), PartialDate::from_parts(1987, None, Some(3)));
+        assert_eq!(Some(PartialDate::Month(1987, 9)), PartialDate::from_parts(1987, Some(9), None));
+        assert_eq!(Some("1987-09-03".to_string()), PartialDate::from_parts(1987, Some(9), Some(3)).map(|date| date.to_string()));
+        assert_eq!(None, PartialDate::from_parts(1987, Some(13), None));
+    }
+}
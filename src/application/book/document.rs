@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use encoding_rs::WINDOWS_1252;
+use regex::Regex;
+use serde_yaml::Value;
+
+use crate::application::book::extension;
+use crate::error::RootsError;
+
+/// The markup of a document, which has no magic bytes and is recognised by
+/// its extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Markup {
+    Text,
+    Html,
+    Markdown,
+}
+
+impl Markup {
+    pub fn from_extension(extension: &str) -> Option<Markup> {
+        match extension {
+            "txt" => Some(Markup::Text),
+            "html" | "htm" => Some(Markup::Html),
+            "md" | "markdown" => Some(Markup::Markdown),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Markup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Markup::Text => write!(f, "Plain text"),
+            Markup::Html => write!(f, "HTML"),
+            Markup::Markdown => write!(f, "Markdown"),
+        }
+    }
+}
+
+/// The metadata of a document by lower case key, as written in its sidecar
+/// file or front matter, or read from a Project Gutenberg header.
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    fields: HashMap<String, Vec<String>>,
+}
+
+impl Metadata {
+    /// The metadata of the document at `path`. The sidecar file overrides
+    /// the front matter, which overrides the Gutenberg header, key by key.
+    pub fn read(path: &Path) -> Result<Metadata, RootsError> {
+        let text = read_text(path)?;
+        let mut metadata = gutenberg(&text);
+        if let Some((front_matter, _)) = front_matter(&text) {
+            let fields = yaml(front_matter).map_err(|error| RootsError::corrupt(path, format!("front matter, {}", error)))?;
+            metadata.fields.extend(fields);
+        }
+        let sidecar = sidecar(path);
+        if sidecar.is_file() {
+            let yml = fs::read_to_string(&sidecar).map_err(|error| RootsError::io(&sidecar, error))?;
+            metadata.fields.extend(yaml(&yml).map_err(|error| RootsError::corrupt(&sidecar, error))?);
+        }
+        Ok(metadata)
+    }
+
+    /// The values of the first of `keys` the document has.
+    pub fn get(&self, keys: &[&str]) -> Vec<String> {
+        keys.iter().find_map(|key| self.fields.get(*key)).cloned().unwrap_or_default()
+    }
+
+    pub fn first(&self, keys: &[&str]) -> Option<String> {
+        self.get(keys).into_iter().next()
+    }
+}
+
+/// The text of the document at `path` without its front matter, and
+/// without its markup when it is HTML.
+pub fn text(path: &Path) -> Result<String, RootsError> {
+    let text = read_text(path)?;
+    let body = front_matter(&text).map_or(text.as_str(), |(_, body)| body);
+    match extension(path).as_deref().and_then(Markup::from_extension) {
+        Some(Markup::Html) => Ok(html_text(body)),
+        _ => Ok(body.trim().to_string()),
+    }
+}
+
+/// The text of the file at `path`, in windows-1252 when it is not UTF-8.
+fn read_text(path: &Path) -> Result<String, RootsError> {
+    let data = fs::read(path).map_err(|error| RootsError::io(path, error))?;
+    let text = String::from_utf8(data)
+        .unwrap_or_else(|error| WINDOWS_1252.decode(error.as_bytes()).0.into_owned());
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// The text of HTML, XHTML or FictionBook `markup`, without its tags,
+/// scripts and styles, a line per paragraph.
+pub fn html_text(markup: &str) -> String {
+    static PATTERNS: OnceLock<[Regex; 3]> = OnceLock::new();
+    let [hidden, breaks, tags] = PATTERNS.get_or_init(|| [
+        Regex::new(r"(?is)<head[\s>].*?</head>|<script.*?</script>|<style.*?</style>|<binary.*?</binary>").unwrap(),
+        Regex::new(r"(?i)</(p|div|h[1-6]|li|tr|title|blockquote)>|<br\s*/?>").unwrap(),
+        Regex::new(r"(?s)<[^>]*>").unwrap(),
+    ]);
+    let markup = hidden.replace_all(markup, "");
+    let markup = breaks.replace_all(&markup, "\n");
+    let text = tags.replace_all(&markup, "")
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Whether the document at `path`, beginning with `head`, has metadata of
+/// its own: a sidecar file, front matter or a Project Gutenberg header.
+/// Other text files, such as READMEs and saved web pages, are not books.
+pub fn has_metadata(path: &Path, head: &[u8]) -> bool {
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    sidecar(path).is_file()
+        || head.starts_with(b"---\n")
+        || head.starts_with(b"---\r\n")
+        || head.windows(17).any(|window| window == b"Project Gutenberg")
+}
+
+/// `<name>.roots.yml` beside the document `<name>.<extension>`.
+pub fn sidecar(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.roots.yml", stem))
+}
+
+/// The YAML between a leading `---` line and the next `---` or `...` line,
+/// and the text after it.
+fn front_matter(text: &str) -> Option<(&str, &str)> {
+    let body = text.strip_prefix("---\n").or_else(|| text.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in body.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return Some((&body[..offset], &body[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// The scalars and lists of scalars in a YAML mapping.
+fn yaml(yml: &str) -> Result<HashMap<String, Vec<String>>, String> {
+    let mapping = match serde_yaml::from_str::<Value>(yml).map_err(|error| error.to_string())? {
+        Value::Mapping(mapping) => mapping,
+        Value::Null => return Ok(HashMap::new()),
+        _ => return Err("not a mapping".to_string()),
+    };
+    let mut fields = HashMap::new();
+    for (key, value) in mapping {
+        let key = match scalar(&key) {
+            Some(key) => key.to_lowercase(),
+            None => continue,
+        };
+        let values = match value {
+            Value::Sequence(values) => values.iter().filter_map(scalar).collect(),
+            value => scalar(&value).into_iter().collect::<Vec<String>>(),
+        };
+        fields.insert(key, values.into_iter().filter(|value| !value.is_empty()).collect());
+    }
+    Ok(fields)
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.trim().to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+/// The fields of a Project Gutenberg header: the `Title:`, `Author:` and
+/// `Release Date:` lines before `*** START OF`, and the `EBook #` number.
+fn gutenberg(text: &str) -> Metadata {
+    let header = match text.find("*** START OF") {
+        Some(end) => &text[..end],
+        None => return Metadata::default(),
+    };
+    if !header.contains("Project Gutenberg") {
+        return Metadata::default();
+    }
+    let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+    let mut last: Option<String> = None;
+    for line in header.lines() {
+        // long titles continue on indented lines
+        if line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+            if let Some(value) = last.as_ref().and_then(|key| fields.get_mut(key)).and_then(|values| values.last_mut()) {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        last = None;
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) if !value.trim().is_empty() => (key.trim().to_lowercase(), value.trim()),
+            _ => continue,
+        };
+        let key = match key.as_str() {
+            "title" | "author" | "language" => key,
+            "release date" => "date".to_string(),
+            _ => continue,
+        };
+        fields.insert(key.clone(), vec![value.to_string()]);
+        last = Some(key);
+    }
+    static NUMBER: OnceLock<Regex> = OnceLock::new();
+    let number = NUMBER.get_or_init(|| Regex::new(r"(?i)\be-?book #(\d+)").unwrap());
+    if let Some(captures) = number.captures(header) {
+        fields.insert("gutenberg".to_string(), vec![captures[1].to_string()]);
+    }
+    if let Some(date) = fields.get_mut("date") {
+        // `June 1, 1998 [EBook #1342]`
+        date.iter_mut().for_each(|value| *value = value.split('[').next().unwrap_or_default().trim().to_string());
+    }
+    fields.insert("publisher".to_string(), vec!["Project Gutenberg".to_string()]);
+    Metadata { fields }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUTENBERG: &str = "\u{feff}The Project Gutenberg EBook of Pride and Prejudice, by Jane Austen
+
+This eBook is for the use of anyone anywhere at no cost.
+
+Title: Pride and Prejudice,
+       or First Impressions
+
+Author: Jane Austen
+
+Release Date: August 26, 2008 [EBook #1342]
+Last Updated: November 12, 2019
+
+Language: English
+
+*** START OF THIS PROJECT GUTENBERG EBOOK PRIDE AND PREJUDICE ***
+
+Chapter 1
+Title: not a header
+";
+
+    #[test]
+    fn gutenberg_headers_are_read() {
+        let metadata = gutenberg(GUTENBERG);
+        assert_eq!(Some("Pride and Prejudice, or First Impressions".to_string()), metadata.first(&["title"]));
+        assert_eq!(vec!["Jane Austen"], metadata.get(&["author"]));
+        assert_eq!(Some("August 26, 2008".to_string()), metadata.first(&["date"]));
+        assert_eq!(Some("1342".to_string()), metadata.first(&["gutenberg"]));
+        assert_eq!(Some("English".to_string()), metadata.first(&["language"]));
+        assert_eq!(Metadata::default(), gutenberg("Title: Notes\n\n*** START OF NOTHING"));
+    }
+
+    #[test]
+    fn sidecar_overrides_front_matter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        fs::write(&path, "---\ntitle: Field Notes\nauthors: [Ann Writer, Bo Writer]\ndate: 2019\n---\n# Field Notes\n\n---\n").unwrap();
+        let metadata = Metadata::read(&path).unwrap();
+        assert_eq!(Some("Field Notes".to_string()), metadata.first(&["title"]));
+        assert_eq!(vec!["Ann Writer", "Bo Writer"], metadata.get(&["author", "authors"]));
+        assert_eq!(Some("2019".to_string()), metadata.first(&["date"]));
+        fs::write(dir.path().join("notes.roots.yml"), "title: Field Notes, Revised\nsubject:\n  - Birds\n").unwrap();
+        let metadata = Metadata::read(&path).unwrap();
+        assert_eq!(Some("Field Notes, Revised".to_string()), metadata.first(&["title"]));
+        assert_eq!(vec!["Birds"], metadata.get(&["subject"]));
+        assert_eq!(Some("2019".to_string()), metadata.first(&["date"]));
+    }
+
+    #[test]
+    fn text_leaves_out_front_matter_and_markup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.html");
+        fs::write(&path, "---\ntitle: Notes\n---\n<html><head><title>Notes</title></head>\n<body><h1>Field  Notes</h1><p>Ducks &amp; geese<br/>fly.</p></body></html>\n").unwrap();
+        assert_eq!("Field Notes\nDucks & geese\nfly.", text(&path).unwrap());
+        let path = dir.path().join("notes.md");
+        fs::write(&path, "# Field Notes\n\n<p>kept</p>\n").unwrap();
+        assert_eq!("# Field Notes\n\n<p>kept</p>", text(&path).unwrap());
+    }
+
+    #[test]
+    fn front_matter_must_be_closed() {
+        assert_eq!(Some(("title: A\n", "text")), front_matter("---\ntitle: A\n...\ntext"));
+        assert_eq!(None, front_matter("---\ntitle: A\n"));
+        assert_eq!(None, front_matter("text\n---\ntitle: A\n---\n"));
+    }
+}
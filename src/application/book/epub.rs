@@ -0,0 +1,929 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use roxmltree::{Document, Node};
+use sha2::{Digest, Sha256};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::application::book::document::html_text;
+use crate::application::book::identifier::Identifier;
+use crate::application::book::name::{relator, Name};
+use crate::application::book::{Book, Cover};
+use crate::error::RootsError;
+use crate::filesystem;
+
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+const OPF: &str = "http://www.idpf.org/2007/opf";
+
+/// Whether an archive entry only holds metadata: the package document, the
+/// NCX table of contents and reader or store bookkeeping.
+fn is_metadata(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    lower == "mimetype"
+        || lower.starts_with("meta-inf/")
+        || lower.ends_with(".opf")
+        || lower.ends_with(".ncx")
+        || lower == "itunesmetadata.plist"
+}
+
+/// Hex encoded SHA-256 digest of the uncompressed content documents, in name
+/// order, so editing metadata or recompressing the archive keeps the hash.
+pub fn content_hash(path: &Path) -> Result<String, RootsError> {
+    let file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))?;
+    let mut names = archive.file_names()
+        .filter(|name| !name.ends_with('/') && !is_metadata(name))
+        .map(String::from)
+        .collect::<Vec<String>>();
+    names.sort();
+    let mut hasher = Sha256::new();
+    for name in names {
+        let mut entry = archive.by_name(&name).map_err(|error| RootsError::corrupt(path, error))?;
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        io::copy(&mut entry, &mut hasher).map_err(|error| RootsError::corrupt(path, error))?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// The identifiers declared in the package document, classified by their
+/// `opf:scheme`, EPUB 3 `identifier-type` refinement or URN prefix.
+pub fn identifiers(document: &Document) -> Vec<Identifier> {
+    document.descendants()
+        .filter(|node| node.tag_name().namespace() == Some(DC) && node.tag_name().name() == "identifier")
+        .filter_map(|node| {
+            let value = node.text().map(str::trim).filter(|value| !value.is_empty())?;
+            let scheme = attribute(&node, "scheme").map(String::from).or_else(|| identifier_type(document, &node));
+            Some(Identifier::parse(scheme.as_deref(), value))
+        })
+        .collect()
+}
+
+/// The creators and contributors with the MARC relator codes of their
+/// `opf:role` or EPUB 3 `role` refinement, each read from its own element
+/// with the sort name of its `file-as`. Creators without a role are authors,
+/// contributors without one `ctb`.
+pub fn creators(document: &Document) -> Vec<(Name, String)> {
+    document.descendants()
+        .filter(|node| node.tag_name().namespace() == Some(DC))
+        .filter_map(|node| {
+            let default = match node.tag_name().name() {
+                "creator" => "aut",
+                "contributor" => "ctb",
+                _ => return None,
+            };
+            let name = node.text().map(str::trim).filter(|name| !name.is_empty())?;
+            let role = creator_property(document, &node, "role").map_or(default.to_string(), |role| relator(&role));
+            Some((Name::with_sort(name, creator_property(document, &node, "file-as").as_deref()), role))
+        })
+        .collect()
+}
+
+/// The publication date as written: the `dc:date` with the `opf:event`
+/// publication, or failing that one without an event, then the original
+/// publication or creation. Modification dates are not publication dates.
+pub fn date(document: &Document) -> Option<String> {
+    let dates = document.descendants()
+        .filter(|node| node.tag_name().namespace() == Some(DC) && node.tag_name().name() == "date")
+        .filter_map(|node| {
+            let date = node.text().map(str::trim).filter(|date| !date.is_empty())?;
+            Some((attribute(&node, "event").map(str::to_lowercase), date.to_string()))
+        })
+        .collect::<Vec<(Option<String>, String)>>();
+    let preferred = [Some("publication"), None, Some("original-publication"), Some("creation")];
+    preferred.iter().find_map(|wanted| dates.iter()
+        .find(|(event, _)| event.as_deref() == *wanted)
+        .map(|(_, date)| date.clone()))
+}
+
+/// The series the book belongs to and its position in it, as text: an EPUB 3
+/// collection, unless refined as a set rather than a series, or the
+/// `calibre:series` meta of EPUB 2.
+pub fn series(document: &Document) -> Option<(String, Option<String>)> {
+    let metas = document.descendants()
+        .filter(|node| node.tag_name().name() == "meta")
+        .collect::<Vec<Node>>();
+    let text = |meta: &Node| meta.text().map(str::trim).filter(|text| !text.is_empty()).map(String::from);
+    let refinement = |id: &str, property: &str| {
+        let target = format!("#{}", id);
+        metas.iter()
+            .find(|meta| meta.attribute("refines") == Some(target.as_str()) && meta.attribute("property") == Some(property))
+            .and_then(text)
+    };
+    let collection = metas.iter()
+        .filter(|meta| meta.attribute("property") == Some("belongs-to-collection"))
+        .find(|meta| meta.attribute("id")
+            .and_then(|id| refinement(id, "collection-type"))
+            .is_none_or(|kind| kind == "series"));
+    if let Some(name) = collection.and_then(text) {
+        let position = collection.and_then(|meta| meta.attribute("id")).and_then(|id| refinement(id, "group-position"));
+        return Some((name, position));
+    }
+    let calibre = |name: &str| metas.iter()
+        .find(|meta| meta.attribute("name") == Some(name))
+        .and_then(|meta| meta.attribute("content"))
+        .map(str::trim)
+        .filter(|content| !content.is_empty())
+        .map(String::from);
+    calibre("calibre:series").map(|name| (name, calibre("calibre:series_index")))
+}
+
+/// The cover image: the manifest item with the EPUB 3 `cover-image` property,
+/// the item named by the EPUB 2 `<meta name="cover">`, or the first image on
+/// the page the guide gives as the cover.
+pub fn cover(path: &Path) -> Result<Option<Cover>, RootsError> {
+    let file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))?;
+    let package_path = package_path(&mut archive).map_err(|error| RootsError::corrupt(path, error))?;
+    let package = read_entry(&mut archive, &package_path).map_err(|error| RootsError::corrupt(path, error))?;
+    let document = Document::parse(&package).map_err(|error| RootsError::corrupt(path, error))?;
+    let items = document.descendants()
+        .filter(|node| node.tag_name().name() == "item")
+        .collect::<Vec<Node>>();
+    let property = items.iter()
+        .find(|item| item.attribute("properties").is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image")))
+        .and_then(|item| item.attribute("href"));
+    let meta = || {
+        let content = document.descendants()
+            .find(|node| node.tag_name().name() == "meta" && node.attribute("name") == Some("cover"))
+            .and_then(|node| node.attribute("content"))?;
+        items.iter()
+            .find(|item| item.attribute("id") == Some(content))
+            .and_then(|item| item.attribute("href"))
+            .or_else(|| items.iter().find_map(|item| item.attribute("href").filter(|href| *href == content)))
+    };
+    if let Some(href) = property.or_else(meta) {
+        let name = resolve_href(&package_path, href);
+        return Ok(read_bytes(&mut archive, &name).ok().and_then(Cover::new));
+    }
+    let page = document.descendants()
+        .find(|node| node.tag_name().name() == "reference" && node.attribute("type") == Some("cover"))
+        .and_then(|node| node.attribute("href"))
+        .map(|href| resolve_href(&package_path, href));
+    Ok(page.and_then(|page| page_image(&mut archive, &page)).and_then(Cover::new))
+}
+
+/// The text of the content documents in spine order, a line per paragraph.
+/// Documents missing from the archive are skipped.
+pub fn text(path: &Path) -> Result<String, RootsError> {
+    let file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))?;
+    let package_path = package_path(&mut archive).map_err(|error| RootsError::corrupt(path, error))?;
+    let package = read_entry(&mut archive, &package_path).map_err(|error| RootsError::corrupt(path, error))?;
+    let document = Document::parse(&package).map_err(|error| RootsError::corrupt(path, error))?;
+    let pages = document.descendants()
+        .filter(|node| node.tag_name().name() == "itemref")
+        .filter_map(|itemref| {
+            let idref = itemref.attribute("idref")?;
+            document.descendants()
+                .find(|node| node.tag_name().name() == "item" && node.attribute("id") == Some(idref))
+                .and_then(|item| item.attribute("href"))
+        })
+        .map(|href| resolve_href(&package_path, href))
+        .collect::<Vec<String>>();
+    Ok(pages.iter()
+        .filter_map(|page| read_entry(&mut archive, page).ok())
+        .map(|page| html_text(&page))
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+/// The first image on the XHTML page `name`, or the page itself when the
+/// guide points straight at an image.
+fn page_image(archive: &mut ZipArchive<File>, name: &str) -> Option<Vec<u8>> {
+    let data = read_bytes(archive, name).ok()?;
+    if Cover::new(data.clone()).is_some() {
+        return Some(data);
+    }
+    let page = String::from_utf8(data).ok()?;
+    let document = Document::parse(&page).ok()?;
+    let src = document.descendants().find_map(|node| match node.tag_name().name() {
+        "img" => node.attribute("src"),
+        "image" => attribute(&node, "href"),
+        _ => None,
+    })?;
+    read_bytes(archive, &resolve_href(name, src)).ok()
+}
+
+/// The archive entry `href` refers to from the entry `base`.
+fn resolve_href(base: &str, href: &str) -> String {
+    let href = percent_decode(href.split('#').next().unwrap_or(href));
+    let mut segments = base.split('/').collect::<Vec<&str>>();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes.get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// The scheme given by a `<meta refines="#id" property="identifier-type">`.
+fn identifier_type(document: &Document, identifier: &Node) -> Option<String> {
+    let id = format!("#{}", identifier.attribute("id")?);
+    let code = document.descendants()
+        .find(|node| node.attribute("refines") == Some(id.as_str()) && node.attribute("property") == Some("identifier-type"))
+        .and_then(|node| node.text())?
+        .trim();
+    // ONIX code list 5
+    match code {
+        "02" | "15" => Some("isbn".to_string()),
+        "06" => Some("doi".to_string()),
+        other if other.chars().any(|c| !c.is_ascii_digit()) => Some(other.to_lowercase()),
+        _ => None,
+    }
+}
+
+/// The `opf:<property>` attribute of a creator, or the EPUB 3 refinement of
+/// the same name.
+fn creator_property(document: &Document, creator: &Node, property: &str) -> Option<String> {
+    let refinement = || {
+        let id = format!("#{}", creator.attribute("id")?);
+        document.descendants()
+            .find(|node| node.attribute("refines") == Some(id.as_str()) && node.attribute("property") == Some(property))
+            .and_then(|node| node.text())
+    };
+    attribute(creator, property).or_else(refinement)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// An attribute by local name, whatever its namespace.
+fn attribute<'a>(node: &Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes().find(|attribute| attribute.name() == name).map(|attribute| attribute.value())
+}
+
+/// Writes the fields set in `changes` into the package document of the EPUB
+/// at `path`. Every other entry is copied without recompressing it, and the
+/// original is kept as `<name>.bak` unless an older backup exists.
+pub fn write_metadata(path: &Path, changes: &Book) -> Result<(), RootsError> {
+    let file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))?;
+    let package_path = package_path(&mut archive).map_err(|error| RootsError::corrupt(path, error))?;
+    let package = read_entry(&mut archive, &package_path).map_err(|error| RootsError::corrupt(path, error))?;
+    let package = update_package(&package, changes).map_err(|error| RootsError::corrupt(path, error))?;
+    let temporary = filesystem::with_suffix(path, ".tmp");
+    if let Err(error) = copy_archive(&mut archive, &temporary, &package_path, &package) {
+        let _ = fs::remove_file(&temporary);
+        return Err(RootsError::io(&temporary, error.into()));
+    }
+    filesystem::replace(path, &temporary).map_err(|error| RootsError::io(path, error))
+}
+
+/// Copies `archive` to `to` entry by entry, in order, replacing the package
+/// document `name` with `package`.
+fn copy_archive(archive: &mut ZipArchive<File>, to: &Path, name: &str, package: &str) -> zip::result::ZipResult<()> {
+    let mut writer = ZipWriter::new(File::create(to)?);
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        if entry.name() == name {
+            let options = FileOptions::default()
+                .compression_method(entry.compression())
+                .last_modified_time(entry.last_modified());
+            writer.start_file(name, options)?;
+            writer.write_all(package.as_bytes())?;
+        } else {
+            writer.raw_copy_file(entry)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// The package document with the fields set in `changes` written into its
+/// `<metadata>`, existing elements are changed in place and new ones added
+/// after the last. Everything else is kept as it was written.
+fn update_package(package: &str, changes: &Book) -> Result<String, String> {
+    let document = Document::parse(package).map_err(|error| error.to_string())?;
+    let mut editor = PackageEditor::new(package, &document)?;
+    if let Some(title) = &changes.title {
+        editor.set("title", title);
+    }
+    if changes.author.is_some() {
+        editor.set_authors(&changes.names());
+    }
+    if let Some(publisher) = &changes.publisher {
+        editor.set("publisher", publisher);
+    }
+    if let Some(description) = &changes.description {
+        editor.set("description", description);
+    }
+    if let Some(date) = changes.publication_date {
+        editor.set("date", &date.to_string());
+    }
+    if let Some(language) = &changes.language {
+        editor.set("language", language);
+    }
+    if let Some(subjects) = &changes.subject {
+        editor.set_all("subject", subjects);
+    }
+    if let Some(isbn) = &changes.isbn {
+        editor.set_identifier("isbn", "ISBN", isbn);
+    }
+    if let Some(asin) = &changes.asin {
+        editor.set_identifier("asin", "MOBI-ASIN", asin);
+    }
+    editor.link_cover();
+    Ok(editor.finish())
+}
+
+/// Text edits to a package document, applied together so that the byte
+/// ranges given by the parsed document stay valid.
+struct PackageEditor<'a, 'input> {
+    package: &'input str,
+    document: &'a Document<'input>,
+    metadata: Node<'a, 'input>,
+    epub3: bool,
+    dc: String,
+    opf: Option<String>,
+    ids: HashSet<String>,
+    declarations: Vec<String>,
+    edits: Vec<(Range<usize>, String)>,
+    additions: Vec<String>,
+}
+
+impl<'a, 'input> PackageEditor<'a, 'input> {
+    fn new(package: &'input str, document: &'a Document<'input>) -> Result<Self, String> {
+        let metadata = document.descendants()
+            .find(|node| node.tag_name().name() == "metadata")
+            .ok_or("no metadata element")?;
+        let mut declarations = Vec::new();
+        let dc = match metadata.lookup_prefix(DC) {
+            Some(prefix) => prefix.to_string(),
+            None => {
+                declarations.push(format!(" xmlns:dc=\"{}\"", DC));
+                "dc".to_string()
+            }
+        };
+        Ok(PackageEditor {
+            package,
+            document,
+            metadata,
+            epub3: document.root_element().attribute("version").is_some_and(|version| version.starts_with('3')),
+            dc,
+            opf: metadata.lookup_prefix(OPF).filter(|prefix| !prefix.is_empty()).map(String::from),
+            ids: document.descendants().filter_map(|node| node.attribute("id")).map(String::from).collect(),
+            declarations,
+            edits: Vec::new(),
+            additions: Vec::new(),
+        })
+    }
+
+    /// The `dc:<name>` children of `<metadata>`.
+    fn elements(&self, name: &str) -> Vec<Node<'a, 'input>> {
+        self.metadata.children()
+            .filter(|node| node.tag_name().namespace() == Some(DC) && node.tag_name().name() == name)
+            .collect()
+    }
+
+    /// The `<meta>` elements refining `node` in EPUB 3.
+    fn refinements(&self, node: &Node) -> Vec<Node<'a, 'input>> {
+        let id = match node.attribute("id") {
+            Some(id) => format!("#{}", id),
+            None => return Vec::new(),
+        };
+        self.metadata.children()
+            .filter(|meta| meta.tag_name().name() == "meta" && meta.attribute("refines") == Some(id.as_str()))
+            .collect()
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        match self.elements(name).first() {
+            Some(element) => self.set_text(element, value),
+            None => {
+                let element = self.element(name, "", value);
+                self.additions.push(element);
+            }
+        }
+    }
+
+    fn set_all(&mut self, name: &str, values: &[String]) {
+        for element in self.elements(name) {
+            self.remove(&element);
+        }
+        for value in values {
+            let element = self.element(name, "", value);
+            self.additions.push(element);
+        }
+    }
+
+    /// Replaces the authors, creators in other roles such as illustrators
+    /// are kept.
+    fn set_authors(&mut self, authors: &[Name]) {
+        for creator in self.elements("creator") {
+            if creator_property(self.document, &creator, "role").is_none_or(|role| role == "aut") {
+                for meta in self.refinements(&creator) {
+                    self.remove(&meta);
+                }
+                self.remove(&creator);
+            }
+        }
+        for author in authors {
+            if self.epub3 {
+                let id = self.new_id("creator");
+                let element = self.element("creator", &format!(" id=\"{}\"", id), &author.display);
+                self.additions.push(element);
+                self.additions.push(format!("<meta refines=\"#{}\" property=\"file-as\">{}</meta>", id, escape(&author.sort)));
+                self.additions.push(format!("<meta refines=\"#{}\" property=\"role\" scheme=\"marc:relators\">aut</meta>", id));
+            } else {
+                let opf = self.opf_prefix();
+                let attributes = format!(" {0}:file-as=\"{1}\" {0}:role=\"aut\"", opf, escape(&author.sort));
+                let element = self.element("creator", &attributes, &author.display);
+                self.additions.push(element);
+            }
+        }
+    }
+
+    /// Sets the first identifier in `scheme`, other than the package's unique
+    /// identifier which readers use to keep track of the book.
+    fn set_identifier(&mut self, scheme: &str, opf_scheme: &str, value: &str) {
+        let unique = self.document.root_element().attribute("unique-identifier");
+        let existing = self.elements("identifier").into_iter().find(|node| {
+            let declared = attribute(node, "scheme").map(String::from).or_else(|| identifier_type(self.document, node));
+            node.attribute("id").is_none_or(|id| Some(id) != unique)
+                && Identifier::parse(declared.as_deref(), node.text().unwrap_or_default()).scheme == scheme
+        });
+        let urn = format!("urn:{}:", scheme);
+        match existing {
+            Some(node) if node.text().unwrap_or_default().trim().to_lowercase().starts_with(&urn) => {
+                self.set_text(&node, &format!("{}{}", urn, value))
+            }
+            Some(node) => self.set_text(&node, value),
+            None if self.epub3 => {
+                let element = self.element("identifier", "", &format!("{}{}", urn, value));
+                self.additions.push(element);
+            }
+            None => {
+                let attributes = format!(" {}:scheme=\"{}\"", self.opf_prefix(), opf_scheme);
+                let element = self.element("identifier", &attributes, value);
+                self.additions.push(element);
+            }
+        }
+    }
+
+    /// Makes sure the cover image is referenced both ways, by the EPUB 2
+    /// `<meta name="cover">` and the EPUB 3 `cover-image` manifest property.
+    fn link_cover(&mut self) {
+        let items = self.document.descendants()
+            .filter(|node| node.tag_name().name() == "item")
+            .collect::<Vec<Node>>();
+        let meta = self.metadata.children()
+            .find(|node| node.tag_name().name() == "meta" && node.attribute("name") == Some("cover"))
+            .and_then(|node| node.attribute("content"));
+        let property = items.iter()
+            .find(|item| item.attribute("properties").is_some_and(|p| p.split_whitespace().any(|p| p == "cover-image")));
+        match (meta, property) {
+            (None, Some(item)) => {
+                if let Some(id) = item.attribute("id") {
+                    self.additions.push(format!("<meta name=\"cover\" content=\"{}\"/>", escape(id)));
+                }
+            }
+            (Some(id), None) if self.epub3 => {
+                let item = items.iter().find(|item| item.attribute("id") == Some(id) && item.attribute("properties").is_none());
+                if let Some(item) = item {
+                    let at = item.range().start + 1 + self.qualified_name(item).len();
+                    self.edits.push((at..at, " properties=\"cover-image\"".to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_text(&mut self, node: &Node, value: &str) {
+        match (node.first_child(), node.last_child()) {
+            (Some(first), Some(last)) => self.edits.push((first.range().start..last.range().end, escape(value))),
+            _ => {
+                let range = node.range();
+                let open = self.package[range.clone()].trim_end_matches('>').trim_end_matches('/').trim_end();
+                let element = format!("{}>{}</{}>", open, escape(value), self.qualified_name(node));
+                self.edits.push((range, element));
+            }
+        }
+    }
+
+    /// Removes `node` with the whitespace before it.
+    fn remove(&mut self, node: &Node) {
+        let start = node.prev_sibling()
+            .filter(|previous| previous.is_text() && previous.text().is_some_and(|text| text.trim().is_empty()))
+            .map_or(node.range().start, |previous| previous.range().start);
+        self.edits.push((start..node.range().end, String::new()));
+    }
+
+    fn element(&self, name: &str, attributes: &str, value: &str) -> String {
+        let name = if self.dc.is_empty() { name.to_string() } else { format!("{}:{}", self.dc, name) };
+        format!("<{0}{1}>{2}</{0}>", name, attributes, escape(value))
+    }
+
+    fn qualified_name(&self, node: &Node) -> &'input str {
+        let tag = &self.package[node.range().start + 1..];
+        let end = tag.find(|c: char| c.is_whitespace() || c == '/' || c == '>').unwrap_or(tag.len());
+        &tag[..end]
+    }
+
+    /// The prefix bound to the OPF namespace, declared on `<metadata>` when
+    /// the package uses it as its default namespace.
+    fn opf_prefix(&mut self) -> String {
+        if self.opf.is_none() {
+            self.declarations.push(format!(" xmlns:opf=\"{}\"", OPF));
+            self.opf = Some("opf".to_string());
+        }
+        self.opf.clone().unwrap_or_default()
+    }
+
+    fn new_id(&mut self, prefix: &str) -> String {
+        let id = (1..).map(|n| format!("{}{}", prefix, n)).find(|id| !self.ids.contains(id)).unwrap_or_default();
+        self.ids.insert(id.clone());
+        id
+    }
+
+    fn finish(mut self) -> String {
+        if !self.declarations.is_empty() {
+            let at = self.metadata.range().start + 1 + self.qualified_name(&self.metadata).len();
+            self.edits.push((at..at, self.declarations.concat()));
+        }
+        if !self.additions.is_empty() {
+            let elements = self.metadata.children().filter(Node::is_element).collect::<Vec<Node>>();
+            let indent = elements.first()
+                .and_then(|first| first.prev_sibling())
+                .and_then(|previous| previous.text())
+                .and_then(|text| text.rsplit_once('\n'))
+                .map_or("    ", |(_, indent)| indent);
+            let at = match elements.last() {
+                Some(last) => last.range().end,
+                None => {
+                    let range = self.metadata.range();
+                    self.package[range.clone()].rfind("</").map_or(range.end, |end| range.start + end)
+                }
+            };
+            let added = self.additions.iter().map(|element| format!("\n{}{}", indent, element)).collect();
+            self.edits.push((at..at, added));
+        }
+        let mut package = self.package.to_string();
+        self.edits.sort_by_key(|(range, _)| (range.start, range.end));
+        for (range, text) in self.edits.into_iter().rev() {
+            package.replace_range(range, &text);
+        }
+        package
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// The package document named by `META-INF/container.xml`.
+pub fn read_package(path: &Path) -> Result<String, RootsError> {
+    let file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut archive = ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))?;
+    let package_path = package_path(&mut archive).map_err(|error| RootsError::corrupt(path, error))?;
+    read_entry(&mut archive, &package_path).map_err(|error| RootsError::corrupt(path, error))
+}
+
+fn package_path(archive: &mut ZipArchive<File>) -> Result<String, String> {
+    let container = read_entry(archive, "META-INF/container.xml")?;
+    Document::parse(&container)
+        .map_err(|error| error.to_string())?
+        .descendants()
+        .find(|node| node.tag_name().name() == "rootfile")
+        .and_then(|node| node.attribute("full-path").map(String::from))
+        .ok_or_else(|| "no package document".to_string())
+}
+
+fn read_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String, String> {
+    let mut entry = archive.by_name(name).map_err(|error| format!("{}: {}", name, error))?;
+    let mut text = String::new();
+    entry.read_to_string(&mut text).map_err(|error| format!("{}: {}", name, error))?;
+    Ok(text)
+}
+
+fn read_bytes(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>, String> {
+    let mut entry = archive.by_name(name).map_err(|error| format!("{}: {}", name, error))?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data).map_err(|error| format!("{}: {}", name, error))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+
+    const JPEG: &[u8] = &[0xff, 0xd8, 0xff, 0xe0];
+
+    fn epub(path: &Path, opf: &str, method: CompressionMethod) {
+        epub_with(path, opf, method, &[]);
+    }
+
+    fn epub_with(path: &Path, opf: &str, method: CompressionMethod, entries: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        let options = FileOptions::default().compression_method(method);
+        zip.start_file("mimetype", FileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(CONTAINER.as_bytes()).unwrap();
+        zip.start_file("OEBPS/content.opf", options).unwrap();
+        zip.write_all(opf.as_bytes()).unwrap();
+        zip.start_file("OEBPS/chapter1.xhtml", options).unwrap();
+        zip.write_all(b"<p>Only connect.</p>").unwrap();
+        for (name, data) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn metadata_changes_keep_the_content_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let (one, two) = (dir.path().join("one.epub"), dir.path().join("two.epub"));
+        epub(&one, "<dc:title>Howards End</dc:title>", CompressionMethod::Stored);
+        epub(&two, "<dc:title>Howard's End</dc:title>", CompressionMethod::Deflated);
+        assert_eq!(content_hash(&one).unwrap(), content_hash(&two).unwrap());
+    }
+
+    #[test]
+    fn other_files_are_not_epubs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.epub");
+        std::fs::write(&path, "not a zip").unwrap();
+        assert!(matches!(content_hash(&path), Err(RootsError::CorruptFile(..))));
+    }
+
+    #[test]
+    fn text_follows_the_spine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.epub");
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf">
+  <manifest>
+    <item id="one" href="chapter1.xhtml" media-type="application/xhtml+xml"/>
+    <item id="two" href="text/chapter%202.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine><itemref idref="two"/><itemref idref="one"/><itemref idref="missing"/></spine>
+</package>"#;
+        let chapter = b"<html><head><title>II</title></head><body><h1>Chapter II</h1><p>Margaret went.</p></body></html>";
+        epub_with(&path, opf, CompressionMethod::Deflated, &[("OEBPS/text/chapter 2.xhtml", chapter)]);
+        assert_eq!("Chapter II\nMargaret went.\nOnly connect.", text(&path).unwrap());
+    }
+
+    #[test]
+    fn identifiers_are_classified() {
+        let opf = r##"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:identifier id="uid">urn:uuid:2b0a4b5c-39d6-4cd2-a8b5-6e0a6d2b1a9f</dc:identifier>
+    <dc:identifier opf:scheme="ISBN">0141439602</dc:identifier>
+    <dc:identifier id="print">9780141182131</dc:identifier>
+    <meta refines="#print" property="identifier-type" scheme="onix:codelist5">15</meta>
+    <dc:identifier opf:scheme="calibre">1234</dc:identifier>
+  </metadata>
+</package>"##;
+        let identifiers = identifiers(&Document::parse(opf).unwrap())
+            .iter()
+            .map(Identifier::to_string)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            vec![
+                "uuid:2b0a4b5c-39d6-4cd2-a8b5-6e0a6d2b1a9f",
+                "isbn:9780141439600",
+                "isbn:9780141182131",
+                "calibre:1234",
+            ],
+            identifiers
+        );
+    }
+
+    #[test]
+    fn creators_are_read_with_their_roles() {
+        let opf = r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:creator opf:file-as="Forster, E. M." opf:role="aut">Forster, E. M.</dc:creator>
+    <dc:creator id="c2">Ursula K. Le Guin</dc:creator>
+    <meta refines="#c2" property="file-as">Le Guin, Ursula K.</meta>
+    <dc:creator id="c3">Cy Artist</dc:creator>
+    <meta refines="#c3" property="role" scheme="marc:relators">ill</meta>
+    <dc:contributor opf:role="trl">Garnett, Constance</dc:contributor>
+    <dc:contributor>Ann Helper</dc:contributor>
+  </metadata>
+</package>"##;
+        let creators = creators(&Document::parse(opf).unwrap());
+        assert_eq!(Name { display: "E. M. Forster".to_string(), sort: "Forster, E. M.".to_string() }, creators[0].0);
+        assert_eq!(Name { display: "Ursula K. Le Guin".to_string(), sort: "Le Guin, Ursula K.".to_string() }, creators[1].0);
+        let credits = creators.iter()
+            .map(|(name, role)| format!("{} ({})", name.display, role))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            vec![
+                "E. M. Forster (aut)",
+                "Ursula K. Le Guin (aut)",
+                "Cy Artist (ill)",
+                "Constance Garnett (trl)",
+                "Ann Helper (ctb)",
+            ],
+            credits
+        );
+    }
+
+    #[test]
+    fn publication_events_are_preferred() {
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:date opf:event="modification">2012-01-05</dc:date>
+    <dc:date opf:event="original-publication">1910</dc:date>
+    <dc:date opf:event="Publication">2000-06</dc:date>
+  </metadata>
+</package>"#;
+        assert_eq!(Some("2000-06".to_string()), date(&Document::parse(opf).unwrap()));
+        let modified = r#"<package><metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:date event="modification">2012</dc:date></metadata></package>"#;
+        assert_eq!(None, date(&Document::parse(modified).unwrap()));
+    }
+
+    #[test]
+    fn series_are_read_from_collections_and_calibre_metas() {
+        let opfs = [
+            r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0"><metadata>
+                <meta property="belongs-to-collection" id="set">Penguin Modern Classics</meta>
+                <meta refines="#set" property="collection-type">set</meta>
+                <meta property="belongs-to-collection" id="c01">The Raj Quartet</meta>
+                <meta refines="#c01" property="collection-type">series</meta>
+                <meta refines="#c01" property="group-position">2</meta>
+            </metadata></package>"##,
+            r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0"><metadata>
+                <meta name="calibre:series" content="The Raj Quartet"/>
+                <meta name="calibre:series_index" content="2.0"/>
+            </metadata></package>"#,
+        ];
+        for opf in opfs {
+            let (name, position) = series(&Document::parse(opf).unwrap()).unwrap();
+            assert_eq!("The Raj Quartet", name);
+            assert!(position.is_some_and(|position| position.starts_with('2')));
+        }
+        assert_eq!(None, series(&Document::parse("<package><metadata/></package>").unwrap()));
+    }
+
+    #[test]
+    fn covers_are_found_by_property_meta_and_guide() {
+        let dir = tempfile::tempdir().unwrap();
+        let opfs = [
+            r#"<package xmlns="http://www.idpf.org/2007/opf"><manifest>
+                <item id="c" href="images/cover%20image.jpg" media-type="image/jpeg" properties="cover-image"/>
+            </manifest></package>"#,
+            r#"<package xmlns="http://www.idpf.org/2007/opf"><metadata><meta name="cover" content="c"/></metadata>
+                <manifest><item id="c" href="images/cover image.jpg" media-type="image/jpeg"/></manifest></package>"#,
+            r#"<package xmlns="http://www.idpf.org/2007/opf"><manifest/>
+                <guide><reference type="cover" href="text/cover.xhtml#top"/></guide></package>"#,
+        ];
+        let page = br#"<html xmlns="http://www.w3.org/1999/xhtml"><body><img src="../images/cover%20image.jpg"/></body></html>"#;
+        for (index, opf) in opfs.iter().enumerate() {
+            let path = dir.path().join(format!("{}.epub", index));
+            let entries: [(&str, &[u8]); 2] = [("OEBPS/images/cover image.jpg", JPEG), ("OEBPS/text/cover.xhtml", page)];
+            epub_with(&path, opf, CompressionMethod::Deflated, &entries);
+            assert_eq!(Some(JPEG.to_vec()), cover(&path).unwrap().map(|cover| cover.data), "{}", opf);
+        }
+        let path = dir.path().join("none.epub");
+        epub(&path, "<package><manifest/></package>", CompressionMethod::Stored);
+        assert_eq!(None, cover(&path).unwrap());
+    }
+
+    #[test]
+    fn epub2_metadata_is_changed_in_place() {
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Howard&apos;s End</dc:title>
+    <dc:creator opf:role="aut">Forster</dc:creator>
+    <dc:creator opf:role="ill">Kate Greenaway</dc:creator>
+    <dc:identifier id="uid">urn:uuid:2b0a4b5c-39d6-4cd2-a8b5-6e0a6d2b1a9f</dc:identifier>
+    <dc:subject>Fiction</dc:subject>
+  </metadata>
+  <manifest><item id="cover" href="cover.jpg" media-type="image/jpeg" properties="cover-image"/></manifest>
+</package>"#;
+        let changes = Book {
+            title: Some("Howards End".to_string()),
+            author: Some(vec!["E. M. Forster".to_string()]),
+            isbn: Some("9780141182131".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Howards End</dc:title>
+    <dc:creator opf:role="ill">Kate Greenaway</dc:creator>
+    <dc:identifier id="uid">urn:uuid:2b0a4b5c-39d6-4cd2-a8b5-6e0a6d2b1a9f</dc:identifier>
+    <dc:subject>Fiction</dc:subject>
+    <dc:creator opf:file-as="Forster, E. M." opf:role="aut">E. M. Forster</dc:creator>
+    <dc:identifier opf:scheme="ISBN">9780141182131</dc:identifier>
+    <meta name="cover" content="cover"/>
+  </metadata>
+  <manifest><item id="cover" href="cover.jpg" media-type="image/jpeg" properties="cover-image"/></manifest>
+</package>"#,
+            update_package(opf, &changes).unwrap()
+        );
+    }
+
+    #[test]
+    fn epub3_authors_are_refined() {
+        let opf = r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:creator id="creator1">Forster</dc:creator>
+<meta refines="#creator1" property="file-as">Forster</meta>
+<meta name="cover" content="img"/>
+</metadata>
+<manifest><item id="img" href="cover.jpg" media-type="image/jpeg"/></manifest>
+</package>"##;
+        let changes = Book {
+            author: Some(vec!["E. M. Forster".to_string()]),
+            isbn: Some("9780141182131".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            r##"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<meta name="cover" content="img"/>
+<dc:creator id="creator2">E. M. Forster</dc:creator>
+<meta refines="#creator2" property="file-as">Forster, E. M.</meta>
+<meta refines="#creator2" property="role" scheme="marc:relators">aut</meta>
+<dc:identifier>urn:isbn:9780141182131</dc:identifier>
+</metadata>
+<manifest><item properties="cover-image" id="img" href="cover.jpg" media-type="image/jpeg"/></manifest>
+</package>"##,
+            update_package(opf, &changes).unwrap()
+        );
+    }
+
+    #[test]
+    fn writing_keeps_the_rest_of_the_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.epub");
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:title>Howard's End</dc:title></metadata>
+</package>"#;
+        epub(&path, opf, CompressionMethod::Deflated);
+        let original = fs::read(&path).unwrap();
+        let changes = Book { title: Some("Howards End".to_string()), ..Default::default() };
+        write_metadata(&path, &changes).unwrap();
+
+        assert_eq!(original, fs::read(dir.path().join("he.epub.bak")).unwrap());
+        let raw = |path: &Path| {
+            let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+            (0..archive.len())
+                .map(|index| {
+                    let mut entry = archive.by_index_raw(index).unwrap();
+                    let mut data = Vec::new();
+                    entry.read_to_end(&mut data).unwrap();
+                    (entry.name().to_string(), entry.compression(), data)
+                })
+                .collect::<Vec<_>>()
+        };
+        let (before, after) = (raw(&dir.path().join("he.epub.bak")), raw(&path));
+        assert_eq!(before.len(), after.len());
+        for (before, after) in before.iter().zip(after.iter()) {
+            assert_eq!(before.0, after.0);
+            if before.0 != "OEBPS/content.opf" {
+                assert_eq!(before, after);
+            }
+        }
+        assert!(read_package(&path).unwrap().contains("<dc:title>Howards End</dc:title>"));
+        assert_eq!(content_hash(&dir.path().join("he.epub.bak")).unwrap(), content_hash(&path).unwrap());
+    }
+}
@@ -0,0 +1,240 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use encoding_rs::{Encoding, UTF_8};
+use regex::bytes::Regex;
+use roxmltree::{Document, Node};
+use zip::ZipArchive;
+
+use crate::application::book::document::html_text;
+use crate::application::book::name::Name;
+use crate::error::RootsError;
+
+/// A series a FictionBook belongs to, with the book's number in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence {
+    pub name: String,
+    pub number: Option<String>,
+}
+
+/// The `<description>` of a FictionBook: its `<title-info>` and
+/// `<publish-info>`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Description {
+    pub title: Option<String>,
+    pub authors: Vec<Name>,
+    pub translators: Vec<Name>,
+    pub genres: Vec<String>,
+    pub annotation: Option<String>,
+    /// The year of writing from `<title-info>`.
+    pub date: Option<String>,
+    pub sequences: Vec<Sequence>,
+    pub lang: Option<String>,
+    pub publisher: Option<String>,
+    /// The year of the edition from `<publish-info>`.
+    pub year: Option<String>,
+    pub isbn: Option<String>,
+}
+
+/// The description of the FictionBook at `path`, or of the first `.fb2`
+/// file in it when it is a zip archive.
+pub fn read(path: &Path) -> Result<Description, RootsError> {
+    description(&read_xml(path)?).map_err(|error| RootsError::corrupt(path, error))
+}
+
+/// The text of the bodies of the FictionBook at `path`, a line per
+/// paragraph.
+pub fn body_text(path: &Path) -> Result<String, RootsError> {
+    let xml = read_xml(path)?;
+    let body = xml.find("<body").ok_or_else(|| RootsError::corrupt(path, "no body"))?;
+    Ok(html_text(&xml[body..]))
+}
+
+fn read_xml(path: &Path) -> Result<String, RootsError> {
+    let mut file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(|error| RootsError::io(path, error))?;
+    if data.starts_with(b"PK\x03\x04") {
+        data = unzip(path, file)?;
+    }
+    Ok(decode(&data))
+}
+
+fn unzip(path: &Path, file: File) -> Result<Vec<u8>, RootsError> {
+    let mut archive = ZipArchive::new(file).map_err(|error| RootsError::corrupt(path, error))?;
+    let name = archive.file_names()
+        .find(|name| name.to_lowercase().ends_with(".fb2"))
+        .map(String::from)
+        .ok_or_else(|| RootsError::corrupt(path, "no .fb2 file in the archive"))?;
+    let mut data = Vec::new();
+    archive.by_name(&name)
+        .map_err(|error| RootsError::corrupt(path, error))?
+        .read_to_end(&mut data)
+        .map_err(|error| RootsError::corrupt(path, error))?;
+    Ok(data)
+}
+
+/// `data` as text in the encoding of its XML declaration, FictionBooks are
+/// often in windows-1251 or KOI8-R.
+fn decode(data: &[u8]) -> String {
+    static DECLARATION: OnceLock<Regex> = OnceLock::new();
+    let declaration = DECLARATION.get_or_init(|| {
+        Regex::new(r#"^(?:\xef\xbb\xbf)?<\?xml[^>]*encoding=["']([A-Za-z0-9._-]+)["']"#).unwrap()
+    });
+    let encoding = declaration.captures(data)
+        .and_then(|captures| Encoding::for_label(&captures[1]))
+        .unwrap_or(UTF_8);
+    let (text, _, _) = encoding.decode(data);
+    text.into_owned()
+}
+
+fn description(xml: &str) -> Result<Description, String> {
+    let xml = xml.trim_start_matches('\u{feff}');
+    let document = Document::parse(xml).map_err(|error| error.to_string())?;
+    let description = child(document.root_element(), "description").ok_or("no description")?;
+    let mut result = Description::default();
+    if let Some(info) = child(description, "title-info") {
+        result.title = child(info, "book-title").and_then(text);
+        result.authors = children(info, "author").filter_map(author).collect();
+        result.translators = children(info, "translator").filter_map(author).collect();
+        result.genres = children(info, "genre").filter_map(text).collect();
+        result.annotation = child(info, "annotation").and_then(annotation);
+        result.date = child(info, "date").and_then(|date| date.attribute("value").map(String::from).or_else(|| text(date)));
+        result.sequences = children(info, "sequence").filter_map(sequence).collect();
+        result.lang = child(info, "lang").and_then(text);
+    }
+    if let Some(info) = child(description, "publish-info") {
+        result.publisher = child(info, "publisher").and_then(text);
+        result.year = child(info, "year").and_then(text);
+        result.isbn = child(info, "isbn").and_then(text);
+        for sequence in children(info, "sequence").filter_map(sequence) {
+            if !result.sequences.contains(&sequence) {
+                result.sequences.push(sequence);
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| child.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+/// The trimmed text of `node` and its descendants, none when blank.
+fn text(node: Node) -> Option<String> {
+    let text = node.descendants()
+        .filter_map(|node| node.text().filter(|_| node.is_text()))
+        .collect::<String>();
+    Some(text.split_whitespace().collect::<Vec<&str>>().join(" ")).filter(|text| !text.is_empty())
+}
+
+/// `First Middle Last` sorted as `Last, First Middle`, or the nickname of
+/// authors without a name.
+fn author(node: Node) -> Option<Name> {
+    let part = |name| child(node, name).and_then(text);
+    let forenames = [part("first-name"), part("middle-name")].into_iter().flatten().collect::<Vec<String>>().join(" ");
+    match part("last-name") {
+        Some(last) if forenames.is_empty() => Some(Name::parse(&last)),
+        Some(last) => Some(Name { display: format!("{} {}", forenames, last), sort: format!("{}, {}", last, forenames) }),
+        None if !forenames.is_empty() => Some(Name::parse(&forenames)),
+        None => part("nickname").map(|nickname| Name::parse(&nickname)),
+    }
+}
+
+/// The paragraphs of an annotation, one per line.
+fn annotation(node: Node) -> Option<String> {
+    let paragraphs = node.children()
+        .filter(Node::is_element)
+        .filter_map(text)
+        .collect::<Vec<String>>();
+    Some(paragraphs.join("\n")).filter(|annotation| !annotation.is_empty()).or_else(|| text(node))
+}
+
+fn sequence(node: Node) -> Option<Sequence> {
+    let name = node.attribute("name").map(str::trim).filter(|name| !name.is_empty())?;
+    Some(Sequence { name: name.to_string(), number: node.attribute("number").map(|number| number.trim().to_string()) })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::io::Write;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    pub const FB2: &str = r#"<?xml version="1.0" encoding="windows-1251"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+  <description>
+    <title-info>
+      <genre>sf_social</genre>
+      <genre>prose_classic</genre>
+      <author><first-name>Arkady</first-name><last-name>Strugatsky</last-name></author>
+      <author><first-name>Boris</first-name><middle-name>N.</middle-name><last-name>Strugatsky</last-name></author>
+      <author><nickname>Editor</nickname></author>
+      <translator><first-name>Antonina</first-name><middle-name>W.</middle-name><last-name>Bouis</last-name></translator>
+      <book-title>Пикник на обочине</book-title>
+      <annotation><p>Stalkers enter <emphasis>the Zone</emphasis>.</p><p>Few return.</p></annotation>
+      <date value="1971-01-01">1971</date>
+      <lang>ru</lang>
+      <sequence name="Noon Universe" number="7"/>
+    </title-info>
+    <publish-info>
+      <publisher>Молодая гвардия</publisher>
+      <year>1980</year>
+      <isbn>5-235-00001-3</isbn>
+      <sequence name="Noon Universe" number="7"/>
+    </publish-info>
+  </description>
+  <body><section><title><p>Roadside</p></title><p>Stalkers went into <emphasis>the Zone</emphasis>.</p></section></body>
+  <binary id="cover.jpg" content-type="image/jpeg">/9j/4AAQ</binary>
+</FictionBook>"#;
+
+    /// `FB2` in its declared encoding.
+    pub fn fb2() -> Vec<u8> {
+        encoding_rs::WINDOWS_1251.encode(FB2).0.into_owned()
+    }
+
+    pub fn fb2_zip(path: &Path) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        writer.start_file("roadside.fb2", FileOptions::default()).unwrap();
+        writer.write_all(&fb2()).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn title_and_publish_info_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roadside.fb2");
+        std::fs::write(&path, fb2()).unwrap();
+        let description = read(&path).unwrap();
+        assert_eq!(Some("Пикник на обочине".to_string()), description.title);
+        let names = |f: fn(&Name) -> &String| description.authors.iter().map(f).cloned().collect::<Vec<String>>();
+        assert_eq!(vec!["Arkady Strugatsky", "Boris N. Strugatsky", "Editor"], names(|name| &name.display));
+        assert_eq!(vec!["Strugatsky, Arkady", "Strugatsky, Boris N.", "Editor"], names(|name| &name.sort));
+        assert_eq!(vec!["Antonina W. Bouis"], description.translators.iter().map(|name| name.display.clone()).collect::<Vec<String>>());
+        assert_eq!(vec!["sf_social", "prose_classic"], description.genres);
+        assert_eq!(Some("ru".to_string()), description.lang);
+        assert_eq!(Some("Stalkers enter the Zone.\nFew return.".to_string()), description.annotation);
+        assert_eq!(Some("1971-01-01".to_string()), description.date);
+        assert_eq!(vec![Sequence { name: "Noon Universe".to_string(), number: Some("7".to_string()) }], description.sequences);
+        assert_eq!(Some("Молодая гвардия".to_string()), description.publisher);
+        assert_eq!(Some("1980".to_string()), description.year);
+        assert_eq!(Some("5-235-00001-3".to_string()), description.isbn);
+    }
+
+    #[test]
+    fn zipped_books_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roadside.fb2.zip");
+        fb2_zip(&path);
+        assert_eq!(Some("Пикник на обочине".to_string()), read(&path).unwrap().title);
+    }
+}
@@ -1,7 +1,11 @@
 use std::fs::canonicalize;
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 
-use crate::application::book::loader::{EpubLoader, MobiLoader};
+use crate::application::book::loader::{
+    BookMetadata, ComicLoader, DocumentLoader, EpubLoader, Fb2Loader, MobiLoader, PdfLoader,
+};
+use crate::error::RootsError;
 
 use super::Book;
 
@@ -13,32 +17,25 @@ pub trait BookFile {
     fn book_data(self) -> Book;
 }
 
-pub struct EpubFile {
+/// An e-book file and the book read from it by the loader `L`.
+pub struct LoadedFile<L> {
     path: PathBuf,
     book_data: Book,
+    loader: PhantomData<fn() -> L>,
 }
 
-impl EpubFile {
-    pub fn new(path: &Path) -> EpubFile {
-        let book = EpubLoader::new(path).unwrap();
-        EpubFile {
+impl<L: BookMetadata> LoadedFile<L> {
+    pub fn new(path: &Path) -> Result<LoadedFile<L>, RootsError> {
+        let book = L::open(path)?;
+        Ok(LoadedFile {
             path: canonicalize(path).unwrap_or(path.to_path_buf()),
-            book_data: Book {
-                title: book.get_title(),
-                author: book.get_author(),
-                publisher: book.get_publisher(),
-                publication_date: book.get_publish_date(),
-                imprint: book.get_imprint(),
-                description: book.get_description(),
-                subject: book.get_subject(),
-                asin: book.get_asin(),
-                isbn: book.get_isbn(),
-            },
-        }
+            book_data: book.book(),
+            loader: PhantomData,
+        })
     }
 }
 
-impl BookFile for EpubFile {
+impl<L> BookFile for LoadedFile<L> {
     fn as_book(&self) -> &Book {
         &self.book_data
     }
@@ -52,45 +49,17 @@ impl BookFile for EpubFile {
     }
 }
 
-#[derive(Debug)]
-pub struct MobiFile {
-    path: PathBuf,
-    book_data: Book,
-}
+pub type EpubFile = LoadedFile<EpubLoader>;
 
-impl MobiFile {
-    pub fn new(path: &Path) -> MobiFile {
-        let book = MobiLoader::new(path).unwrap();
-        MobiFile {
-            path: canonicalize(path).unwrap_or(path.to_path_buf()),
-            book_data: Book {
-                title: book.get_title(),
-                author: book.get_author(),
-                publisher: book.get_publisher(),
-                publication_date: book.get_publish_date(),
-                imprint: book.get_imprint(),
-                description: book.get_description(),
-                subject: book.get_subject(),
-                asin: book.get_asin(),
-                isbn: book.get_isbn(),
-            },
-        }
-    }
-}
+pub type MobiFile = LoadedFile<MobiLoader>;
 
-impl BookFile for MobiFile {
-    fn as_book(&self) -> &Book {
-        &self.book_data
-    }
+pub type PdfFile = LoadedFile<PdfLoader>;
 
-    fn path(&self) -> &Path {
-        self.path.as_path()
-    }
+pub type ComicFile = LoadedFile<ComicLoader>;
 
-    fn book_data(self) -> Book {
-        self.book_data
-    }
-}
+pub type Fb2File = LoadedFile<Fb2Loader>;
+
+pub type DocumentFile = LoadedFile<DocumentLoader>;
 
 #[cfg(test)]
 mod tests {
@@ -98,13 +67,13 @@ mod tests {
 
     #[test]
     fn epub_path_returns_path() {
-        let book = EpubFile::new(Path::new("var/cache/pg98.epub"));
+        let book = EpubFile::new(Path::new("var/cache/pg98.epub")).unwrap();
         assert!(book.path().ends_with(Path::new("var/cache/pg98.epub")));
     }
 
     #[test]
     fn mobi_path_returns_path() {
-        let book = MobiFile::new(Path::new("var/cache/pg98.mobi"));
+        let book = MobiFile::new(Path::new("var/cache/pg98.mobi")).unwrap();
         assert!(book.path().ends_with(Path::new("var/cache/pg98.mobi")));
     }
 }
\ No newline at end of file
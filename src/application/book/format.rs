@@ -0,0 +1,391 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::application::book::comic::{self, Archive};
+use crate::application::book::document::{self, Markup};
+use crate::application::book::file::{BookFile, ComicFile, DocumentFile, EpubFile, Fb2File, MobiFile, PdfFile};
+use crate::application::book::palm::{self, MobiWriter};
+use crate::application::book::pdf::{self, PdfHeader, PdfMetadata};
+use crate::application::book::{epub, extension, fb2, Book, Cover};
+use crate::error::RootsError;
+
+/// The format registry: every supported format, in the order files are
+/// sniffed, EPUBs and zipped FictionBooks before other zip archives, and
+/// documents, which have no magic bytes, last. Formats are found through
+/// `formats`, `by_extension` and `loader` only.
+static FORMATS: &[&dyn FormatLoader] =
+    &[&MobiFormat, &PdfFormat, &EpubFormat, &Fb2Format, &ComicFormat, &DocumentFormat];
+
+/// How many bytes of a file are read to sniff its format.
+const MAGIC_LENGTH: u64 = 1024;
+
+/// The container of an e-book, recognised by its magic bytes rather than
+/// its extension, as named by the first of `FORMATS` to sniff it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Format {
+    /// The name of the format, such as `MOBI`.
+    pub name: &'static str,
+    /// What the file's headers tell of it, such as `KF8 (MOBI version 8)`.
+    pub description: String,
+}
+
+impl Format {
+    /// The format of the file at `p`, none when it is not an e-book.
+    pub fn detect(p: &Path) -> Result<Option<Format>, RootsError> {
+        Ok(loader(p)?.map(|(format, _)| format))
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.description)
+    }
+}
+
+/// An e-book format: how its files are recognised and what can be read from
+/// or written to them. A new format implements it and is added to
+/// `FORMATS`, nothing else names the formats.
+pub trait FormatLoader: Sync {
+    /// The name of the format, such as `MOBI`.
+    fn name(&self) -> &'static str;
+
+    /// The extensions of the format in lower case, without the dot.
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// A description of the file at `path`, whose first bytes are `magic`,
+    /// such as its version, none when it is in another format.
+    fn sniff(&self, path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError>;
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError>;
+
+    /// The cover image stored in the file, if the format has one.
+    fn cover(&self, _path: &Path) -> Result<Option<Cover>, RootsError> {
+        Ok(None)
+    }
+
+    /// The text of the book, none when the format's text cannot be read.
+    fn text(&self, _path: &Path) -> Result<Option<String>, RootsError> {
+        Ok(None)
+    }
+
+    /// A hash of the book's content that ignores its metadata, for formats
+    /// that keep the two apart.
+    fn content_hash(&self, _path: &Path) -> Result<Option<String>, RootsError> {
+        Ok(None)
+    }
+
+    /// Facts about the file itself rather than the book, as labels and
+    /// values for `info` to show.
+    fn properties(&self, _path: &Path) -> Result<Vec<(&'static str, String)>, RootsError> {
+        Ok(Vec::new())
+    }
+
+    /// Writes the fields set in `changes` into the file, false when the
+    /// format does not support writing metadata.
+    fn write_metadata(&self, _path: &Path, _changes: &Book) -> Result<bool, RootsError> {
+        Ok(false)
+    }
+}
+
+/// The format of the file at `p` and its loader, from the first of
+/// `FORMATS` recognising the file.
+pub fn loader(p: &Path) -> Result<Option<(Format, &'static dyn FormatLoader)>, RootsError> {
+    let mut magic = Vec::new();
+    File::open(p)
+        .and_then(|file| file.take(MAGIC_LENGTH).read_to_end(&mut magic))
+        .map_err(|error| RootsError::io(p, error))?;
+    for loader in formats().iter().copied() {
+        if let Some(description) = loader.sniff(p, &magic)? {
+            return Ok(Some((Format { name: loader.name(), description }, loader)));
+        }
+    }
+    Ok(None)
+}
+
+/// Every registered format, in the order files are sniffed.
+pub fn formats() -> &'static [&'static dyn FormatLoader] {
+    FORMATS
+}
+
+/// The format whose files are named with `extension`, in lower case and
+/// without the dot.
+pub fn by_extension(extension: &str) -> Option<&'static dyn FormatLoader> {
+    formats().iter().copied().find(|format| format.extensions().contains(&extension))
+}
+
+/// The extensions of every registered format.
+pub fn supported_extensions() -> Vec<&'static str> {
+    formats().iter().flat_map(|format| format.extensions()).copied().collect()
+}
+
+/// The name of a zip's first file, which follows its 30 byte local header.
+fn first_zip_entry(magic: &[u8]) -> String {
+    magic.get(26..28)
+        .map(|length| u16::from_le_bytes([length[0], length[1]]) as usize)
+        .and_then(|length| magic.get(30..30 + length))
+        .map(String::from_utf8_lossy)
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+pub struct MobiFormat;
+
+impl FormatLoader for MobiFormat {
+    fn name(&self) -> &'static str {
+        "MOBI"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["mobi", "azw", "azw3"]
+    }
+
+    fn sniff(&self, path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError> {
+        if magic.get(60..68) != Some(b"BOOKMOBI") {
+            return Ok(None);
+        }
+        Ok(Some(palm::read_header(path, magic)?.to_string()))
+    }
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError> {
+        Ok(MobiFile::new(path)?.book_data())
+    }
+
+    fn cover(&self, path: &Path) -> Result<Option<Cover>, RootsError> {
+        Ok(palm::read_cover(path)?.and_then(Cover::new))
+    }
+
+    fn write_metadata(&self, path: &Path, changes: &Book) -> Result<bool, RootsError> {
+        MobiWriter::new(path)?.write(changes).map(|_| true)
+    }
+}
+
+pub struct PdfFormat;
+
+impl FormatLoader for PdfFormat {
+    fn name(&self) -> &'static str {
+        "PDF"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn sniff(&self, _path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError> {
+        Ok(PdfHeader::parse(magic).map(|header| header.to_string()))
+    }
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError> {
+        Ok(PdfFile::new(path)?.book_data())
+    }
+
+    fn text(&self, path: &Path) -> Result<Option<String>, RootsError> {
+        pdf::text(path).map(Some)
+    }
+
+    fn properties(&self, path: &Path) -> Result<Vec<(&'static str, String)>, RootsError> {
+        Ok(PdfMetadata::read(path)?.producer().map(|producer| ("Producer", producer)).into_iter().collect())
+    }
+}
+
+/// EPUBs are zip archives beginning with the EPUB mimetype, or failing that
+/// named `.epub`.
+pub struct EpubFormat;
+
+impl FormatLoader for EpubFormat {
+    fn name(&self) -> &'static str {
+        "EPUB"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["epub"]
+    }
+
+    fn sniff(&self, path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError> {
+        let epub = Archive::detect(magic) == Some(Archive::Zip)
+            && (magic.get(30..).is_some_and(|name| name.starts_with(b"mimetypeapplication/epub+zip"))
+                || extension(path).as_deref() == Some("epub"));
+        Ok(Some(self.name().to_string()).filter(|_| epub))
+    }
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError> {
+        Ok(EpubFile::new(path)?.book_data())
+    }
+
+    fn cover(&self, path: &Path) -> Result<Option<Cover>, RootsError> {
+        epub::cover(path)
+    }
+
+    fn text(&self, path: &Path) -> Result<Option<String>, RootsError> {
+        epub::text(path).map(Some)
+    }
+
+    fn content_hash(&self, path: &Path) -> Result<Option<String>, RootsError> {
+        epub::content_hash(path).map(Some)
+    }
+
+    fn write_metadata(&self, path: &Path, changes: &Book) -> Result<bool, RootsError> {
+        epub::write_metadata(path, changes).map(|_| true)
+    }
+}
+
+/// FictionBooks are XML with a `<FictionBook>` root, or zip archives whose
+/// first file is one.
+pub struct Fb2Format;
+
+impl FormatLoader for Fb2Format {
+    fn name(&self) -> &'static str {
+        "FictionBook 2"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["fb2", "fb2.zip"]
+    }
+
+    fn sniff(&self, _path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError> {
+        if magic.windows(12).any(|window| window == b"<FictionBook") {
+            return Ok(Some(self.name().to_string()));
+        }
+        let zipped = Archive::detect(magic) == Some(Archive::Zip) && first_zip_entry(magic).ends_with(".fb2");
+        Ok(Some(format!("{}, zipped", self.name())).filter(|_| zipped))
+    }
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError> {
+        Ok(Fb2File::new(path)?.book_data())
+    }
+
+    fn text(&self, path: &Path) -> Result<Option<String>, RootsError> {
+        fb2::body_text(path).map(Some)
+    }
+}
+
+/// Zip archives are comics when named like one, 7z and RAR archives always
+/// are.
+pub struct ComicFormat;
+
+impl FormatLoader for ComicFormat {
+    fn name(&self) -> &'static str {
+        "Comic book archive"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["cbz", "cbr", "cb7"]
+    }
+
+    fn sniff(&self, path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError> {
+        match Archive::detect(magic) {
+            Some(Archive::Zip) if !self.extensions().contains(&extension(path).unwrap_or_default().as_str()) => Ok(None),
+            archive => Ok(archive.map(|archive| format!("{} ({})", self.name(), archive))),
+        }
+    }
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError> {
+        Ok(ComicFile::new(path)?.book_data())
+    }
+
+    fn cover(&self, path: &Path) -> Result<Option<Cover>, RootsError> {
+        comic::cover(path)
+    }
+}
+
+/// Documents are text files named like one, with metadata of their own.
+pub struct DocumentFormat;
+
+impl FormatLoader for DocumentFormat {
+    fn name(&self) -> &'static str {
+        "Document"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "html", "htm", "md", "markdown"]
+    }
+
+    fn sniff(&self, path: &Path, magic: &[u8]) -> Result<Option<String>, RootsError> {
+        let markup = Markup::from_extension(&extension(path).unwrap_or_default());
+        let document = !magic.contains(&0) && document::has_metadata(path, magic);
+        Ok(markup.filter(|_| document).map(|markup| markup.to_string()))
+    }
+
+    fn book(&self, path: &Path) -> Result<Book, RootsError> {
+        Ok(DocumentFile::new(path)?.book_data())
+    }
+
+    fn text(&self, path: &Path) -> Result<Option<String>, RootsError> {
+        document::text(path).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, Object};
+
+    use super::*;
+
+    #[test]
+    fn formats_are_detected_by_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let mobi = dir.path().join("he.bin");
+        std::fs::write(&mobi, palm::tests::mobi(&[])).unwrap();
+        let detect = |path: &Path| Format::detect(path).unwrap().map(|format| format.to_string());
+        assert_eq!(Some("MOBI"), Format::detect(&mobi).unwrap().map(|format| format.name));
+        let epub = dir.path().join("he.zip");
+        let mut data = b"PK\x03\x04".to_vec();
+        data.resize(30, 0);
+        data.extend_from_slice(b"mimetypeapplication/epub+zip");
+        std::fs::write(&epub, data).unwrap();
+        assert_eq!(Some("EPUB".to_string()), detect(&epub));
+        let zip = dir.path().join("comic.cbz");
+        std::fs::write(&zip, b"PK\x03\x04").unwrap();
+        assert_eq!(Some("Comic book archive (ZIP)".to_string()), detect(&zip));
+        let zip = dir.path().join("comic.zip");
+        std::fs::write(&zip, b"PK\x03\x04").unwrap();
+        assert_eq!(None, detect(&zip));
+        let pdf = dir.path().join("he.bin");
+        std::fs::write(&pdf, b"%PDF-1.4\n").unwrap();
+        assert_eq!(Some("PDF 1.4".to_string()), detect(&pdf));
+        let fb2 = dir.path().join("roadside.xml");
+        std::fs::write(&fb2, fb2::tests::fb2()).unwrap();
+        assert_eq!(Some("FictionBook 2".to_string()), detect(&fb2));
+        let zipped = dir.path().join("roadside.zip");
+        fb2::tests::fb2_zip(&zipped);
+        assert_eq!(Some("FictionBook 2, zipped".to_string()), detect(&zipped));
+    }
+
+    #[test]
+    fn formats_are_found_by_extension() {
+        assert_eq!(Some("FictionBook 2"), by_extension("fb2.zip").map(|format| format.name()));
+        assert_eq!(Some("MOBI"), by_extension("azw3").map(|format| format.name()));
+        assert!(by_extension("odt").is_none());
+    }
+
+    #[test]
+    fn every_extension_belongs_to_one_format() {
+        let extensions = supported_extensions();
+        assert!(extensions.iter().all(|extension| extensions.iter().filter(|other| other == &extension).count() == 1));
+        assert!(extensions.contains(&"azw3") && extensions.contains(&"fb2.zip") && extensions.contains(&"markdown"));
+    }
+
+    #[test]
+    fn text_is_read_where_the_format_allows() {
+        let dir = tempfile::tempdir().unwrap();
+        let fb2 = dir.path().join("roadside.fb2");
+        std::fs::write(&fb2, fb2::tests::fb2()).unwrap();
+        let (_, format) = loader(&fb2).unwrap().unwrap();
+        assert_eq!(Some("Roadside\nStalkers went into the Zone.".to_string()), format.text(&fb2).unwrap());
+        let mobi = dir.path().join("he.mobi");
+        std::fs::write(&mobi, palm::tests::mobi(&[])).unwrap();
+        let (_, format) = loader(&mobi).unwrap().unwrap();
+        assert_eq!(None, format.text(&mobi).unwrap());
+    }
+
+    #[test]
+    fn pdf_producers_are_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.pdf");
+        let info = dictionary! { "Producer" => Object::string_literal("pdfTeX-1.40.25") };
+        pdf::tests::pdf(&path, info, None, "Howards End");
+        let (_, format) = loader(&path).unwrap().unwrap();
+        assert_eq!(vec![("Producer", "pdfTeX-1.40.25".to_string())], format.properties(&path).unwrap());
+    }
+}
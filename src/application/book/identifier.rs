@@ -0,0 +1,143 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// An identifier of a book in some scheme, e.g. `isbn`, `asin`, `uuid`,
+/// `doi`, `calibre` or `google`. Valid ISBNs are stored as ISBN-13.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identifier {
+    pub scheme: String,
+    pub value: String,
+}
+
+impl Identifier {
+    pub fn new(scheme: &str, value: &str) -> Identifier {
+        let scheme = scheme.trim().to_lowercase();
+        let value = value.trim();
+        match scheme.as_str() {
+            "isbn" => Identifier { value: isbn13(value).unwrap_or(value.to_string()), scheme },
+            _ => Identifier { scheme, value: value.to_string() },
+        }
+    }
+
+    /// Classifies an identifier by its declared scheme, or by a URN or other
+    /// well known prefix when it has none.
+    pub fn parse(scheme: Option<&str>, value: &str) -> Identifier {
+        let value = value.trim();
+        let lower = value.to_lowercase();
+        let scheme = scheme.map(str::trim).filter(|s| !s.is_empty()).map(str::to_lowercase);
+        let prefixed = [
+            ("urn:isbn:", "isbn"),
+            ("isbn:", "isbn"),
+            ("urn:uuid:", "uuid"),
+            ("uuid:", "uuid"),
+            ("urn:doi:", "doi"),
+            ("doi:", "doi"),
+            ("https://doi.org/", "doi"),
+            ("calibre:", "calibre"),
+            ("urn:calibre:", "calibre"),
+            ("amazon:", "asin"),
+            ("urn:asin:", "asin"),
+            ("google:", "google"),
+        ];
+        if let Some((prefix, kind)) = prefixed.iter().find(|(prefix, _)| lower.starts_with(prefix)) {
+            return Identifier::new(kind, &value[prefix.len()..]);
+        }
+        match scheme.as_deref() {
+            Some("amazon") | Some("mobi-asin") | Some("asin") => Identifier::new("asin", value),
+            Some(scheme) => Identifier::new(scheme, value),
+            None if isbn13(value).is_some() => Identifier::new("isbn", value),
+            None if is_uuid(&lower) => Identifier::new("uuid", value),
+            None => Identifier::new("unknown", value),
+        }
+    }
+
+    /// The ISBN-13, for identifiers that hold a valid ISBN.
+    pub fn isbn(&self) -> Option<String> {
+        Some(&self.value).filter(|_| self.scheme == "isbn").and_then(|value| isbn13(value))
+    }
+}
+
+impl Display for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}:{}", self.scheme, self.value)
+    }
+}
+
+/// The ISBN-13 form of a valid ISBN-10 or ISBN-13, ignoring hyphens and spaces.
+pub fn isbn13(value: &str) -> Option<String> {
+    let digits = value.chars()
+        .filter(|c| !matches!(c, '-' | ' '))
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<String>();
+    match digits.len() {
+        10 if valid_isbn10(&digits) => {
+            let body = format!("978{}", &digits[..9]);
+            Some(format!("{}{}", body, isbn13_check(&body)))
+        }
+        13 if digits.chars().all(|c| c.is_ascii_digit()) && isbn13_check(&digits[..12]) == digits[12..] => Some(digits),
+        _ => None,
+    }
+}
+
+fn valid_isbn10(digits: &str) -> bool {
+    let sum = digits.chars().enumerate().try_fold(0, |sum, (i, c)| {
+        let value = match c {
+            'X' if i == 9 => 10,
+            c => c.to_digit(10)?,
+        };
+        Some(sum + value * (10 - i as u32))
+    });
+    matches!(sum, Some(sum) if sum % 11 == 0)
+}
+
+fn isbn13_check(body: &str) -> String {
+    let sum = body.chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d } else { d * 3 })
+        .sum::<u32>();
+    ((10 - sum % 10) % 10).to_string()
+}
+
+fn is_uuid(value: &str) -> bool {
+    let groups = value.split('-').map(str::len).collect::<Vec<usize>>();
+    groups == [8, 4, 4, 4, 12] && value.chars().all(|c| c == '-' || c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+
+    use super::*;
+
+    use self::rstest::*;
+
+    #[rstest]
+    #[case::isbn13("978-0-14-143960-0", Some("9780141439600"))]
+    #[case::isbn10("0-14-143960-2", Some("9780141439600"))]
+    #[case::isbn10_x("0-8044-2957-X", Some("9780804429573"))]
+    #[case::bad_checksum("978-0-14-143960-1", None)]
+    #[case::bad_isbn10("0-14-143960-5", None)]
+    #[case::not_isbn("B000FC1PJI", None)]
+    fn isbns_are_validated(#[case] value: &str, #[case] expected: Option<&str>) {
+        assert_eq!(expected.map(String::from), isbn13(value));
+    }
+
+    #[rstest]
+    #[case::scheme(Some("ISBN"), "0141439602", "isbn", "9780141439600")]
+    #[case::urn(None, "urn:isbn:9780141439600", "isbn", "9780141439600")]
+    #[case::bare_isbn(None, "9780141439600", "isbn", "9780141439600")]
+    #[case::uuid(None, "urn:uuid:2b0a4b5c-39d6-4cd2-a8b5-6e0a6d2b1a9f", "uuid", "2b0a4b5c-39d6-4cd2-a8b5-6e0a6d2b1a9f")]
+    #[case::calibre(Some("calibre"), "1234", "calibre", "1234")]
+    #[case::doi(None, "doi:10.1000/182", "doi", "10.1000/182")]
+    #[case::mobi_asin(Some("MOBI-ASIN"), "B000FC1PJI", "asin", "B000FC1PJI")]
+    #[case::unknown(None, "gutenberg 98", "unknown", "gutenberg 98")]
+    fn identifiers_are_classified(
+        #[case] scheme: Option<&str>,
+        #[case] value: &str,
+        #[case] expected_scheme: &str,
+        #[case] expected_value: &str,
+    ) {
+        let identifier = Identifier::parse(scheme, value);
+        assert_eq!((expected_scheme, expected_value), (identifier.scheme.as_str(), identifier.value.as_str()));
+    }
+}
@@ -0,0 +1,176 @@
+/// A language with its ISO 639-1 code, the ISO 639-2 bibliographic and
+/// terminology codes, and its English and native names.
+struct Language {
+    code: &'static str,
+    bibliographic: &'static str,
+    terminology: &'static str,
+    english: &'static str,
+    native: &'static str,
+}
+
+const fn language(
+    code: &'static str,
+    bibliographic: &'static str,
+    terminology: &'static str,
+    english: &'static str,
+    native: &'static str,
+) -> Language {
+    Language { code, bibliographic, terminology, english, native }
+}
+
+const LANGUAGES: [Language; 36] = [
+    language("ar", "ara", "ara", "Arabic", "العربية"),
+    language("bg", "bul", "bul", "Bulgarian", "български"),
+    language("ca", "cat", "cat", "Catalan", "català"),
+    language("cs", "cze", "ces", "Czech", "čeština"),
+    language("cy", "wel", "cym", "Welsh", "Cymraeg"),
+    language("da", "dan", "dan", "Danish", "dansk"),
+    language("de", "ger", "deu", "German", "Deutsch"),
+    language("el", "gre", "ell", "Greek", "ελληνικά"),
+    language("en", "eng", "eng", "English", "English"),
+    language("eo", "epo", "epo", "Esperanto", "Esperanto"),
+    language("es", "spa", "spa", "Spanish", "español"),
+    language("fa", "per", "fas", "Persian", "فارسی"),
+    language("fi", "fin", "fin", "Finnish", "suomi"),
+    language("fr", "fre", "fra", "French", "français"),
+    language("ga", "gle", "gle", "Irish", "Gaeilge"),
+    language("he", "heb", "heb", "Hebrew", "עברית"),
+    language("hi", "hin", "hin", "Hindi", "हिन्दी"),
+    language("hr", "hrv", "hrv", "Croatian", "hrvatski"),
+    language("hu", "hun", "hun", "Hungarian", "magyar"),
+    language("is", "ice", "isl", "Icelandic", "íslenska"),
+    language("it", "ita", "ita", "Italian", "italiano"),
+    language("ja", "jpn", "jpn", "Japanese", "日本語"),
+    language("ko", "kor", "kor", "Korean", "한국어"),
+    language("la", "lat", "lat", "Latin", "Latina"),
+    language("nl", "dut", "nld", "Dutch", "Nederlands"),
+    language("no", "nor", "nor", "Norwegian", "norsk"),
+    language("pl", "pol", "pol", "Polish", "polski"),
+    language("pt", "por", "por", "Portuguese", "português"),
+    language("ro", "rum", "ron", "Romanian", "română"),
+    language("ru", "rus", "rus", "Russian", "русский"),
+    language("sk", "slo", "slk", "Slovak", "slovenčina"),
+    language("sv", "swe", "swe", "Swedish", "svenska"),
+    language("tr", "tur", "tur", "Turkish", "Türkçe"),
+    language("uk", "ukr", "ukr", "Ukrainian", "українська"),
+    language("vi", "vie", "vie", "Vietnamese", "Tiếng Việt"),
+    language("zh", "chi", "zho", "Chinese", "中文"),
+];
+
+/// Common words of the languages `detect` recognises, chosen to be rare in
+/// the other languages listed.
+const STOP_WORDS: [(&str, [&str; 12]); 8] = [
+    ("en", ["the", "and", "of", "to", "that", "was", "with", "his", "her", "you", "which", "have"]),
+    ("fr", ["le", "les", "et", "des", "est", "dans", "qui", "pas", "elle", "pour", "avec", "je"]),
+    ("de", ["der", "die", "das", "und", "ist", "nicht", "ein", "ich", "sie", "zu", "mit", "sich"]),
+    ("es", ["el", "los", "las", "y", "que", "es", "una", "por", "con", "para", "del", "lo"]),
+    ("it", ["il", "di", "che", "non", "un", "una", "per", "della", "sono", "gli", "è", "ma"]),
+    ("pt", ["o", "os", "que", "não", "um", "uma", "para", "com", "do", "da", "em", "é"]),
+    ("nl", ["de", "het", "een", "en", "van", "niet", "dat", "ik", "zijn", "op", "met", "voor"]),
+    ("sv", ["och", "att", "det", "som", "är", "inte", "på", "för", "med", "jag", "han", "hon"]),
+];
+
+/// How many stop words `detect` needs to find before it trusts the count.
+const MINIMUM_STOP_WORDS: usize = 10;
+
+/// The BCP 47 tag for `language` written as a tag, `en-GB` or `en_GB`, an
+/// ISO 639-2 code, `eng` or `fre`, or an English or native name, `English`
+/// or `français`. Well-formed tags of unlisted languages are kept.
+pub fn normalise(language: &str) -> Option<String> {
+    let language = language.trim();
+    let name = language.to_lowercase();
+    if let Some(listed) = LANGUAGES.iter().find(|listed| listed.english.to_lowercase() == name || listed.native.to_lowercase() == name) {
+        return Some(listed.code.to_string());
+    }
+    let mut subtags = language.split(['-', '_']);
+    let primary = subtags.next()?.to_ascii_lowercase();
+    if !(2..=3).contains(&primary.len()) || !primary.chars().all(|c| c.is_ascii_alphabetic()) || ["und", "zxx"].contains(&primary.as_str()) {
+        return None;
+    }
+    let primary = LANGUAGES.iter()
+        .find(|listed| listed.bibliographic == primary || listed.terminology == primary)
+        .map(|listed| listed.code.to_string())
+        .unwrap_or(primary);
+    let mut tag = vec![primary];
+    for subtag in subtags {
+        let subtag = match subtag.len() {
+            4 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                subtag[..1].to_ascii_uppercase() + &subtag[1..].to_ascii_lowercase()
+            }
+            2 if subtag.chars().all(|c| c.is_ascii_alphabetic()) => subtag.to_ascii_uppercase(),
+            3 if subtag.chars().all(|c| c.is_ascii_digit()) => subtag.to_string(),
+            1..=8 if subtag.chars().all(|c| c.is_ascii_alphanumeric()) => subtag.to_ascii_lowercase(),
+            _ => return None,
+        };
+        tag.push(subtag);
+    }
+    Some(tag.join("-"))
+}
+
+/// The language of `text` judged by its most common words, none when the
+/// text is too short or too mixed to tell.
+pub fn detect(text: &str) -> Option<String> {
+    let words = text.split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .take(5000)
+        .map(str::to_lowercase)
+        .collect::<Vec<String>>();
+    let mut counts = STOP_WORDS.iter()
+        .map(|(code, stop_words)| (*code, words.iter().filter(|word| stop_words.contains(&word.as_str())).count()))
+        .collect::<Vec<(&str, usize)>>();
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let (code, count) = counts[0];
+    // the runner up should be well behind, since the lists share a few words
+    Some(code.to_string()).filter(|_| count >= MINIMUM_STOP_WORDS && count >= counts[1].1 * 2)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+
+    use super::*;
+
+    use self::rstest::*;
+
+    #[rstest]
+    #[case("en", "en")]
+    #[case("eng", "en")]
+    #[case("English", "en")]
+    #[case(" en-gb ", "en-GB")]
+    #[case("en_US", "en-US")]
+    #[case("fre", "fr")]
+    #[case("fra", "fr")]
+    #[case("Français", "fr")]
+    #[case("zh-hant-tw", "zh-Hant-TW")]
+    #[case("es-419", "es-419")]
+    #[case("gsw", "gsw")]
+    fn languages_are_bcp_47_tags(#[case] language: &str, #[case] expected: &str) {
+        assert_eq!(Some(expected.to_string()), normalise(language));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("und")]
+    #[case("Klingonese")]
+    #[case("en-")]
+    #[case("e")]
+    fn other_values_are_no_language(#[case] language: &str) {
+        assert_eq!(None, normalise(language));
+    }
+
+    #[test]
+    fn languages_are_detected_from_text() {
+        let english = "It was the best of times, it was the worst of times, it was the age of wisdom, it was the age \
+            of foolishness, and the spring of hope, and the winter of despair; we had everything before us, and we \
+            had nothing before us, and he said that you would have seen it with his own eyes.";
+        let french = "Longtemps, je me suis couché de bonne heure. Parfois, à peine ma bougie éteinte, mes yeux se \
+            fermaient si vite que je n'avais pas le temps de me dire: je m'endors. Et, une demi-heure après, la pensée \
+            qu'il était temps de chercher le sommeil m'éveillait; je voulais poser le volume que je croyais avoir dans \
+            les mains et souffler ma lumière; je n'avais pas cessé en dormant de faire des réflexions sur ce que je \
+            venais de lire, mais ces réflexions avaient pris un tour un peu particulier; il me semblait que j'étais \
+            moi-même ce dont parlait l'ouvrage: une église, un quatuor, la rivalité de François Ier et de Charles-Quint.";
+        assert_eq!(Some("en".to_string()), detect(english));
+        assert_eq!(Some("fr".to_string()), detect(french));
+        assert_eq!(None, detect("The end."));
+    }
+}
@@ -1,121 +1,616 @@
-use std::io;
 use std::fs;
 use std::path::Path;
 
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use epub::doc::EpubDoc;
 use mobi::Mobi as MobiDoc;
+use roxmltree::Document;
 
-fn convert(published_date: Option<String>) -> Option<DateTime<Utc>> {
-    match published_date {
-        Some(date_str) => {
-            NaiveDate::parse_from_str(date_str.as_str(), "%Y-%m-%d")
-                .ok()
-                .map(|date_component| {
-                    let time_component: NaiveTime = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
-                    let naive_date_time: NaiveDateTime = NaiveDateTime::new(date_component, time_component);
-                    DateTime::from_naive_utc_and_offset(naive_date_time, Utc)
-                })
-        },
-        None => None
+use crate::application::book::comic::{self, ComicInfo};
+use crate::application::book::date::PartialDate;
+use crate::application::book::document::Metadata;
+use crate::application::book::fb2::{self, Description};
+use crate::application::book::identifier::{isbn13, Identifier};
+use crate::application::book::language;
+use crate::application::book::name::{Contributor, Name, ROLES};
+use crate::application::book::palm::{self, EXTH_ASIN, EXTH_AUTHOR, EXTH_CDE_ASIN, EXTH_CONTRIBUTOR, EXTH_ISBN, EXTH_SOURCE};
+use crate::application::book::pdf::{self, PdfMetadata};
+use crate::application::book::Book;
+use crate::error::RootsError;
+
+/// A position in a series such as `2` or `2.5`.
+fn series_index(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok().filter(|index| index.is_finite() && *index >= 0.0)
+}
+
+/// A contributor written as `Name (role)`, such as `Constance Garnett
+/// (translator)`, or without a role as a plain contributor.
+fn credit(text: &str) -> Contributor {
+    let role = text.trim().strip_suffix(')')
+        .and_then(|text| text.rsplit_once('('))
+        .filter(|(_, role)| ROLES.iter().any(|(code, name)| role.trim().eq_ignore_ascii_case(name) || role.trim() == *code));
+    match role {
+        Some((name, role)) => Contributor::new(name, role),
+        None => Contributor::new(text, "ctb"),
+    }
+}
+
+/// The ISBN-13 of the first valid ISBN among `identifiers`.
+fn first_isbn(identifiers: &[Identifier]) -> Option<String> {
+    identifiers.iter().find_map(Identifier::isbn)
+}
+
+/// The metadata of one e-book file, read into the fields of `Book`.
+pub trait BookMetadata: Sized {
+    fn open(path: &Path) -> Result<Self, RootsError>;
+
+    /// Every identifier read, including ISBNs and ASINs.
+    fn identifiers(&self) -> &[Identifier];
+
+    /// The authors in credit order.
+    fn get_author(&self) -> Option<Vec<Name>>;
+
+    fn get_title(&self) -> Option<String>;
+
+    fn get_publisher(&self) -> Option<String>;
+
+    fn get_publish_date(&self) -> Option<PartialDate>;
+
+    fn get_imprint(&self) -> Option<String> {
+        None
+    }
+
+    fn get_description(&self) -> Option<String>;
+
+    fn get_subject(&self) -> Option<Vec<String>>;
+
+    /// The language as written, normalised to a BCP 47 tag by `book`.
+    fn get_language(&self) -> Option<String> {
+        None
+    }
+
+    fn get_contributors(&self) -> Option<Vec<Contributor>> {
+        None
+    }
+
+    fn get_series(&self) -> Option<String> {
+        None
+    }
+
+    fn get_series_index(&self) -> Option<f64> {
+        None
+    }
+
+    fn get_asin(&self) -> Option<String> {
+        self.identifiers().iter().find(|i| i.scheme == "asin").map(|i| i.value.clone())
+    }
+
+    fn get_isbn(&self) -> Option<String> {
+        first_isbn(self.identifiers())
+    }
+
+    fn get_identifiers(&self) -> Option<Vec<Identifier>> {
+        Some(self.identifiers().to_vec()).filter(|identifiers| !identifiers.is_empty())
+    }
+
+    fn book(&self) -> Book {
+        let names = self.get_author();
+        Book {
+            title: self.get_title(),
+            author: names.as_ref().map(|names| names.iter().map(|name| name.display.clone()).collect()),
+            author_sort: names.map(|names| names.into_iter().map(|name| name.sort).collect()),
+            contributors: self.get_contributors(),
+            publisher: self.get_publisher(),
+            publication_date: self.get_publish_date(),
+            imprint: self.get_imprint(),
+            description: self.get_description(),
+            language: self.get_language().and_then(|language| language::normalise(&language)),
+            subject: self.get_subject(),
+            series: self.get_series(),
+            series_index: self.get_series_index(),
+            asin: self.get_asin(),
+            isbn: self.get_isbn(),
+            identifiers: self.get_identifiers(),
+        }
     }
 }
 
 pub struct EpubLoader {
     data: EpubDoc<fs::File>,
+    identifiers: Vec<Identifier>,
+    series: Option<(String, Option<String>)>,
+    creators: Vec<(Name, String)>,
+    date: Option<String>,
 }
 
-impl EpubLoader {
-    pub fn new(path: &Path) -> Result<EpubLoader, io::Error> {
-        Ok(EpubLoader { data: EpubDoc::new(path).unwrap() })
+impl BookMetadata for EpubLoader {
+    fn open(path: &Path) -> Result<EpubLoader, RootsError> {
+        let data = EpubDoc::new(path).map_err(|error| RootsError::corrupt(path, error))?;
+        // the epub crate keeps neither refinements nor attributes, so the
+        // package is parsed again, once, for the fields that need them
+        let package = super::epub::read_package(path).ok();
+        let document = package.as_deref().and_then(|package| Document::parse(package).ok());
+        let identifiers = document.as_ref().map(super::epub::identifiers).unwrap_or_else(|| {
+            data.metadata.get("identifier")
+                .into_iter()
+                .flatten()
+                .map(|value| Identifier::parse(None, value))
+                .collect()
+        });
+        let series = document.as_ref().and_then(super::epub::series);
+        let creators = document.as_ref().map(super::epub::creators).unwrap_or_else(|| {
+            data.metadata.get("creator").into_iter().flatten().map(|name| (Name::parse(name), "aut".to_string())).collect()
+        });
+        let date = document.as_ref().and_then(super::epub::date)
+            .or_else(|| data.metadata.get("date").and_then(|dates| dates.first().cloned()));
+        Ok(EpubLoader { data, identifiers, series, creators, date })
     }
 
-    pub fn get_author(&self) -> Option<Vec<String>> {
-        self.data.mdata("creator")
-            .map(|string| string.split(',')
-                .map(|str| str.trim())
-                .map(|str| str.to_string())
-                .collect::<Vec<_>>())
+    fn get_author(&self) -> Option<Vec<Name>> {
+        let authors = self.creators.iter()
+            .filter(|(_, role)| role == "aut")
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<Name>>();
+        Some(authors).filter(|authors| !authors.is_empty())
     }
 
-    pub fn get_title(&self) -> Option<String> {
+    fn get_contributors(&self) -> Option<Vec<Contributor>> {
+        let contributors = self.creators.iter()
+            .filter(|(_, role)| role != "aut")
+            .map(|(name, role)| Contributor { name: name.display.clone(), role: role.clone() })
+            .collect::<Vec<Contributor>>();
+        Some(contributors).filter(|contributors| !contributors.is_empty())
+    }
+
+    fn get_title(&self) -> Option<String> {
         self.data.mdata("title")
     }
 
-    pub fn get_publisher(&self) -> Option<String> {
+    fn get_publisher(&self) -> Option<String> {
         self.data.mdata("publisher")
     }
 
-    pub fn get_publish_date(&self) -> Option<DateTime<Utc>> {
-        let published_date: Option<String> = self.data.metadata.get("date")
-            .and_then(|dates| dates.first().cloned());
-        convert(published_date)
+    fn get_publish_date(&self) -> Option<PartialDate> {
+        self.date.as_deref().and_then(PartialDate::parse)
     }
 
-    pub fn get_imprint(&self) -> Option<String> {
-        None
+    fn get_description(&self) -> Option<String> {
+        self.data.mdata("description")
     }
 
-    pub fn get_description(&self) -> Option<String> {
-        self.data.mdata("description")
+    fn get_language(&self) -> Option<String> {
+        self.data.mdata("language")
     }
 
-    pub fn get_subject(&self) -> Option<Vec<String>> {
+    fn get_subject(&self) -> Option<Vec<String>> {
         self.data.metadata.get("subject").cloned()
     }
 
-    pub fn get_asin(&self) -> Option<String> {
-        None
+    fn get_series(&self) -> Option<String> {
+        self.series.as_ref().map(|(name, _)| name.clone())
     }
 
-    pub fn get_isbn(&self) -> Option<String> {
-        None
+    fn get_series_index(&self) -> Option<f64> {
+        self.series.as_ref().and_then(|(_, index)| index.as_deref()).and_then(series_index)
+    }
+
+    fn identifiers(&self) -> &[Identifier] {
+        &self.identifiers
     }
 }
 
 pub struct MobiLoader {
     data: MobiDoc,
+    identifiers: Vec<Identifier>,
+    authors: Vec<Name>,
+    contributors: Vec<Contributor>,
+    language: Option<String>,
 }
 
-impl MobiLoader {
-    pub fn new(path: &Path) -> Result<MobiLoader, io::Error> {
-        Ok(MobiLoader { data: MobiDoc::from_path(path).unwrap() })
+impl BookMetadata for MobiLoader {
+    fn open(path: &Path) -> Result<MobiLoader, RootsError> {
+        let data = MobiDoc::from_path(path).map_err(|error| RootsError::corrupt(path, error))?;
+        let mut identifiers: Vec<Identifier> = Vec::new();
+        let mut authors: Vec<Name> = Vec::new();
+        let mut contributors: Vec<Contributor> = Vec::new();
+        // the mobi crate does not expose every EXTH record
+        let metadata = palm::read_metadata(path)?;
+        for record in &metadata.records {
+            let identifier = match record.kind {
+                // an author record each, or several joined by `&` or `;`
+                EXTH_AUTHOR => {
+                    authors.extend(split(&record.text(), &['&', ';']).iter().map(|name| Name::parse(name)));
+                    continue;
+                }
+                // calibre and kindlegen record themselves as contributors
+                EXTH_CONTRIBUTOR => {
+                    let text = record.text();
+                    if !["calibre", "kindlegen"].iter().any(|tool| text.to_lowercase().contains(tool)) {
+                        contributors.extend(split(&text, &['&', ';']).iter().map(|name| credit(name)));
+                    }
+                    continue;
+                }
+                EXTH_ISBN => Identifier::new("isbn", &record.text()),
+                EXTH_ASIN | EXTH_CDE_ASIN => Identifier::new("asin", &record.text()),
+                EXTH_SOURCE => Identifier::parse(None, &record.text()),
+                _ => continue,
+            };
+            if !identifier.value.is_empty() && !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+        let language = metadata.language();
+        Ok(MobiLoader { data, identifiers, authors, contributors, language })
+    }
+
+    fn get_author(&self) -> Option<Vec<Name>> {
+        Some(self.authors.clone())
+            .filter(|authors| !authors.is_empty())
+            .or_else(|| self.data.author().map(|author| vec![Name::parse(&author)]))
     }
 
-    pub fn get_author(&self) -> Option<Vec<String>> {
-        self.data.author().map(|str| vec![str])
+    fn get_contributors(&self) -> Option<Vec<Contributor>> {
+        Some(self.contributors.clone()).filter(|contributors| !contributors.is_empty())
     }
 
-    pub fn get_title(&self) -> Option<String> {
+    fn get_title(&self) -> Option<String> {
         Some(self.data.title())
     }
 
-    pub fn get_publisher(&self) -> Option<String> {
+    fn get_publisher(&self) -> Option<String> {
         self.data.publisher()
     }
 
-    pub fn get_publish_date(&self) -> Option<DateTime<Utc>> {
-        let published_date: Option<String> = self.data.publish_date();
-        convert(published_date)
+    fn get_publish_date(&self) -> Option<PartialDate> {
+        self.data.publish_date().and_then(|date| PartialDate::find(&date))
     }
 
-    pub fn get_imprint(&self) -> Option<String> {
-        None
+    fn get_description(&self) -> Option<String> {
+        self.data.description()
     }
 
-    pub fn get_description(&self) -> Option<String> {
-        self.data.description()
+    fn get_language(&self) -> Option<String> {
+        self.language.clone()
     }
 
-    pub fn get_subject(&self) -> Option<Vec<String>> {
+    fn get_subject(&self) -> Option<Vec<String>> {
         self.data.metadata.subjects()
     }
 
-    pub fn get_asin(&self) -> Option<String> {
-        None
+    fn get_isbn(&self) -> Option<String> {
+        first_isbn(&self.identifiers).or_else(|| self.data.isbn().and_then(|isbn| isbn13(&isbn)))
+    }
+
+    fn identifiers(&self) -> &[Identifier] {
+        &self.identifiers
+    }
+}
+
+pub struct PdfLoader {
+    data: PdfMetadata,
+    identifiers: Vec<Identifier>,
+}
+
+/// XMP metadata is preferred to the document information dictionary, which
+/// PDF 2.0 deprecates and which tools often fill with file names.
+impl BookMetadata for PdfLoader {
+    fn open(path: &Path) -> Result<PdfLoader, RootsError> {
+        let data = PdfMetadata::read(path)?;
+        let mut identifiers: Vec<Identifier> = Vec::new();
+        let declared = data.xmp("dc:identifier").iter()
+            .map(|value| Identifier::parse(None, value))
+            .chain(data.xmp("prism:isbn").iter().map(|value| Identifier::new("isbn", value)))
+            .collect::<Vec<Identifier>>();
+        // the copyright page is searched for ISBNs the metadata does not give
+        let printed = pdf::isbns(&data.text).into_iter().map(|isbn| Identifier::new("isbn", &isbn));
+        for identifier in declared.into_iter().chain(printed) {
+            if !identifier.value.is_empty() && !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+        Ok(PdfLoader { data, identifiers })
+    }
+
+    fn get_author(&self) -> Option<Vec<Name>> {
+        Some(self.data.xmp("dc:creator"))
+            .filter(|authors| !authors.is_empty())
+            .or_else(|| self.data.info("Author").map(|authors| split(&authors, &[';'])))
+            .map(|authors| authors.iter().map(|author| Name::parse(author)).collect())
+    }
+
+    fn get_title(&self) -> Option<String> {
+        self.data.xmp("dc:title").into_iter().next().or_else(|| self.data.info("Title"))
+    }
+
+    fn get_publisher(&self) -> Option<String> {
+        self.data.xmp("dc:publisher").into_iter().next()
+    }
+
+    fn get_publish_date(&self) -> Option<PartialDate> {
+        self.data.xmp("xmp:CreateDate").iter()
+            .chain(self.data.xmp("dc:date").iter())
+            .chain(self.data.info("CreationDate").iter())
+            .find_map(|date| pdf::parse_date(date))
+    }
+
+    fn get_description(&self) -> Option<String> {
+        self.data.xmp("dc:description").into_iter().next().or_else(|| self.data.info("Subject"))
+    }
+
+    fn get_language(&self) -> Option<String> {
+        self.data.xmp("dc:language").into_iter().next()
+    }
+
+    fn get_subject(&self) -> Option<Vec<String>> {
+        Some(self.data.xmp("dc:subject"))
+            .filter(|subjects| !subjects.is_empty())
+            .or_else(|| self.data.xmp("pdf:Keywords").into_iter().next().or_else(|| self.data.info("Keywords"))
+                .map(|keywords| split(&keywords, &[',', ';'])))
+    }
+
+    fn identifiers(&self) -> &[Identifier] {
+        &self.identifiers
+    }
+}
+
+pub struct ComicLoader {
+    data: ComicInfo,
+    identifiers: Vec<Identifier>,
+}
+
+/// Comics without a `ComicInfo.xml` are left to be named by their file.
+impl BookMetadata for ComicLoader {
+    fn open(path: &Path) -> Result<ComicLoader, RootsError> {
+        let data = comic::comic_info(path)?.unwrap_or_default();
+        // GTIN is the barcode, an ISBN-13 for books
+        let identifiers = data.get("GTIN")
+            .filter(|gtin| isbn13(gtin).is_some())
+            .map(|gtin| Identifier::new("isbn", &gtin))
+            .into_iter()
+            .collect();
+        Ok(ComicLoader { data, identifiers })
     }
 
-    pub fn get_isbn(&self) -> Option<String> {
-        self.data.isbn()
+    /// Writers before pencillers, each credited once.
+    fn get_author(&self) -> Option<Vec<Name>> {
+        let mut authors: Vec<Name> = Vec::new();
+        for author in self.data.list("Writer").into_iter().chain(self.data.list("Penciller")) {
+            let name = Name::parse(&author);
+            if !authors.iter().any(|other| other.key() == name.key()) {
+                authors.push(name);
+            }
+        }
+        Some(authors).filter(|authors| !authors.is_empty())
     }
+
+    /// Artists other than pencillers as illustrators, with editors and
+    /// translators.
+    fn get_contributors(&self) -> Option<Vec<Contributor>> {
+        let roles = [
+            ("Inker", "ill"),
+            ("Colorist", "ill"),
+            ("Letterer", "ill"),
+            ("CoverArtist", "ill"),
+            ("Editor", "edt"),
+            ("Translator", "trl"),
+        ];
+        let mut contributors: Vec<Contributor> = Vec::new();
+        for (field, role) in roles {
+            for name in self.data.list(field) {
+                let contributor = Contributor::new(&name, role);
+                if !contributors.contains(&contributor) {
+                    contributors.push(contributor);
+                }
+            }
+        }
+        Some(contributors).filter(|contributors| !contributors.is_empty())
+    }
+
+    /// The title of the issue, or `Series Vol. 2 #3` for issues without one.
+    fn get_title(&self) -> Option<String> {
+        let issue = self.get_series().map(|series| {
+            let number = self.data.get("Number").map(|number| format!(" #{}", number)).unwrap_or_default();
+            format!("{}{}", series, number)
+        });
+        self.data.get("Title").or(issue)
+    }
+
+    fn get_publisher(&self) -> Option<String> {
+        self.data.get("Publisher")
+    }
+
+    fn get_publish_date(&self) -> Option<PartialDate> {
+        let part = |name: &str| self.data.get(name).and_then(|value| value.parse::<u32>().ok());
+        PartialDate::from_parts(part("Year")? as i32, part("Month"), part("Day"))
+    }
+
+    fn get_imprint(&self) -> Option<String> {
+        self.data.get("Imprint")
+    }
+
+    fn get_description(&self) -> Option<String> {
+        self.data.get("Summary")
+    }
+
+    fn get_language(&self) -> Option<String> {
+        self.data.get("LanguageISO")
+    }
+
+    fn get_subject(&self) -> Option<Vec<String>> {
+        Some(self.data.list("Genre")).filter(|genres| !genres.is_empty())
+    }
+
+    /// The series with its volume, as issues restart their numbering with
+    /// each volume.
+    fn get_series(&self) -> Option<String> {
+        self.data.get("Series").map(|series| match self.data.get("Volume") {
+            Some(volume) => format!("{} Vol. {}", series, volume),
+            None => series,
+        })
+    }
+
+    fn get_series_index(&self) -> Option<f64> {
+        self.data.get("Number").as_deref().and_then(series_index)
+    }
+
+    fn identifiers(&self) -> &[Identifier] {
+        &self.identifiers
+    }
+}
+
+pub struct Fb2Loader {
+    data: Description,
+    identifiers: Vec<Identifier>,
+}
+
+impl BookMetadata for Fb2Loader {
+    fn open(path: &Path) -> Result<Fb2Loader, RootsError> {
+        let data = fb2::read(path)?;
+        let identifiers = data.isbn.iter().map(|isbn| Identifier::new("isbn", isbn)).collect();
+        Ok(Fb2Loader { data, identifiers })
+    }
+
+    fn get_author(&self) -> Option<Vec<Name>> {
+        Some(self.data.authors.clone()).filter(|authors| !authors.is_empty())
+    }
+
+    fn get_contributors(&self) -> Option<Vec<Contributor>> {
+        let translators = self.data.translators.iter()
+            .map(|name| Contributor { name: name.display.clone(), role: "trl".to_string() })
+            .collect::<Vec<Contributor>>();
+        Some(translators).filter(|translators| !translators.is_empty())
+    }
+
+    fn get_title(&self) -> Option<String> {
+        self.data.title.clone()
+    }
+
+    fn get_publisher(&self) -> Option<String> {
+        self.data.publisher.clone()
+    }
+
+    /// The year of the edition, or failing that the date of writing.
+    fn get_publish_date(&self) -> Option<PartialDate> {
+        self.data.year.as_deref()
+            .and_then(|year| year.get(..4))
+            .and_then(PartialDate::parse)
+            .or_else(|| self.data.date.as_deref().and_then(PartialDate::parse))
+    }
+
+    fn get_description(&self) -> Option<String> {
+        self.data.annotation.clone()
+    }
+
+    fn get_language(&self) -> Option<String> {
+        self.data.lang.clone()
+    }
+
+    fn get_subject(&self) -> Option<Vec<String>> {
+        Some(self.data.genres.clone()).filter(|genres| !genres.is_empty())
+    }
+
+    /// The first sequence, the others are usually the publisher's.
+    fn get_series(&self) -> Option<String> {
+        self.data.sequences.first().map(|sequence| sequence.name.clone())
+    }
+
+    fn get_series_index(&self) -> Option<f64> {
+        self.data.sequences.first().and_then(|sequence| sequence.number.as_deref()).and_then(series_index)
+    }
+
+    fn identifiers(&self) -> &[Identifier] {
+        &self.identifiers
+    }
+}
+
+pub struct DocumentLoader {
+    data: Metadata,
+    identifiers: Vec<Identifier>,
+}
+
+/// Keys are matched in order, so that front matter written for other tools,
+/// such as `authors` or `tags`, is understood too.
+impl BookMetadata for DocumentLoader {
+    fn open(path: &Path) -> Result<DocumentLoader, RootsError> {
+        let data = Metadata::read(path)?;
+        let mut identifiers: Vec<Identifier> = Vec::new();
+        let declared = data.get(&["identifier", "identifiers"]).iter()
+            .map(|value| Identifier::parse(None, value))
+            .chain(data.get(&["isbn"]).iter().map(|value| Identifier::new("isbn", value)))
+            .chain(data.get(&["asin"]).iter().map(|value| Identifier::new("asin", value)))
+            .chain(data.get(&["gutenberg"]).iter().map(|value| Identifier::new("gutenberg", value)))
+            .collect::<Vec<Identifier>>();
+        for identifier in declared {
+            if !identifier.value.is_empty() && !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+        Ok(DocumentLoader { data, identifiers })
+    }
+
+    fn get_author(&self) -> Option<Vec<Name>> {
+        Some(self.data.get(&["author", "authors", "creator"]))
+            .filter(|authors| !authors.is_empty())
+            .map(|authors| authors.iter().map(|author| Name::parse(author)).collect())
+    }
+
+    /// Keys named after a role, such as `translator`, or `contributors`
+    /// written as `Name (role)`.
+    fn get_contributors(&self) -> Option<Vec<Contributor>> {
+        let mut contributors: Vec<Contributor> = Vec::new();
+        for role in ["editor", "illustrator", "narrator", "translator"] {
+            for name in self.data.get(&[role, &format!("{}s", role)]) {
+                contributors.push(Contributor::new(&name, role));
+            }
+        }
+        contributors.extend(self.data.get(&["contributor", "contributors"]).iter().map(|text| credit(text)));
+        Some(contributors).filter(|contributors| !contributors.is_empty())
+    }
+
+    fn get_title(&self) -> Option<String> {
+        self.data.first(&["title"])
+    }
+
+    fn get_publisher(&self) -> Option<String> {
+        self.data.first(&["publisher"])
+    }
+
+    fn get_publish_date(&self) -> Option<PartialDate> {
+        self.data.first(&["date", "published", "year"]).and_then(|date| PartialDate::parse(&date))
+    }
+
+    fn get_imprint(&self) -> Option<String> {
+        self.data.first(&["imprint"])
+    }
+
+    fn get_description(&self) -> Option<String> {
+        self.data.first(&["description", "summary", "abstract"])
+    }
+
+    fn get_language(&self) -> Option<String> {
+        self.data.first(&["language", "lang"])
+    }
+
+    fn get_subject(&self) -> Option<Vec<String>> {
+        Some(self.data.get(&["subject", "subjects", "tags", "keywords"])).filter(|subjects| !subjects.is_empty())
+    }
+
+    fn get_series(&self) -> Option<String> {
+        self.data.first(&["series"])
+    }
+
+    fn get_series_index(&self) -> Option<f64> {
+        self.data.first(&["series_index", "series_position"]).as_deref().and_then(series_index)
+    }
+
+    fn identifiers(&self) -> &[Identifier] {
+        &self.identifiers
+    }
+}
+
+fn split(values: &str, separators: &[char]) -> Vec<String> {
+    values.split(separators)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
 }
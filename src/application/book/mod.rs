@@ -1,37 +1,477 @@
 use std::ffi::OsStr;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use chrono::{DateTime, Utc};
-
-use crate::application::book::file::{BookFile, EpubFile, MobiFile};
+use crate::application::book::date::PartialDate;
+use crate::application::book::document::Markup;
+use crate::application::book::identifier::{isbn13, Identifier};
+use crate::application::book::name::{Contributor, Name};
+use crate::application::query::Field;
+use crate::error::RootsError;
+use crate::filesystem;
 
+mod comic;
+pub mod date;
+mod document;
+mod epub;
+mod fb2;
 pub mod file;
+mod format;
+pub mod identifier;
+pub mod language;
 mod loader;
+pub mod name;
+mod palm;
+mod pdf;
+
+pub use crate::application::book::document::sidecar;
+pub use crate::application::book::format::{by_extension, loader, supported_extensions, Format, FormatLoader};
+
+/// The extension of `p` in lower case, `fb2.zip` for zipped FictionBooks.
+pub fn extension(p: &Path) -> Option<String> {
+    let name = p.file_name().and_then(OsStr::to_str)?.to_lowercase();
+    supported_extensions().into_iter()
+        .filter(|extension| extension.contains('.'))
+        .find(|extension| name.ends_with(&format!(".{}", extension)))
+        .map(|extension| extension.to_string())
+        .or_else(|| p.extension().and_then(OsStr::to_str).map(str::to_lowercase))
+}
+
+/// The e-books below `root`: the files with a supported extension, less
+/// text files without metadata of their own, such as READMEs.
+pub fn find_books(root: &Path) -> Vec<PathBuf> {
+    filesystem::find_files(root, &supported_extensions())
+        .into_iter()
+        .filter(|path| {
+            extension(path).as_deref().and_then(Markup::from_extension).is_none()
+                || Format::detect(path).is_ok_and(|format| format.is_some())
+        })
+        .collect()
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Book {
     pub title: Option<String>,
     pub author: Option<Vec<String>>,
+    /// The sort names of `author`, in the same order, such as the
+    /// `opf:file-as` of EPUBs; derived from the names when not given.
+    pub author_sort: Option<Vec<String>>,
+    /// Translators, editors, illustrators and others credited in the book.
+    pub contributors: Option<Vec<Contributor>>,
     pub publisher: Option<String>,
-    pub publication_date: Option<DateTime<Utc>>,
+    pub publication_date: Option<PartialDate>,
     pub imprint: Option<String>,
     pub description: Option<String>,
+    /// The BCP 47 tag of the book's language, such as `en` or `en-GB`.
+    pub language: Option<String>,
     pub subject: Option<Vec<String>>,
+    pub series: Option<String>,
+    /// The book's position in its series, fractional for books published
+    /// between two others.
+    pub series_index: Option<f64>,
     pub asin: Option<String>,
     pub isbn: Option<String>,
+    /// Every identifier found for the book, including its ISBNs and ASIN.
+    pub identifiers: Option<Vec<Identifier>>,
 }
 
 impl Book {
-    pub fn new(p: &Path) -> Book {
-        match p.extension().and_then(OsStr::to_str) {
-            Some("mobi") => MobiFile::new(p).book_data(),
-            Some("epub") => EpubFile::new(p).book_data(),
-            _ => panic!("oops")
+    pub fn new(p: &Path) -> Result<Book, RootsError> {
+        if !p.is_file() {
+            return Err(RootsError::io(p, io::Error::from(io::ErrorKind::NotFound)));
+        }
+        match loader(p)? {
+            Some((_, format)) => format.book(p),
+            None if by_extension(&extension(p).unwrap_or_default()).is_some() => {
+                Err(RootsError::corrupt(p, "unrecognised content"))
+            }
+            None => Err(RootsError::UnsupportedFormat(p.to_path_buf())),
+        }
+    }
+
+    /// A hash of the book's content that ignores its metadata, for formats
+    /// that keep the two apart.
+    pub fn content_hash(p: &Path) -> Result<Option<String>, RootsError> {
+        match loader(p)? {
+            Some((_, format)) => format.content_hash(p),
+            None => Ok(None),
         }
     }
+
+    /// The cover image stored in the file at `p`, if it has one.
+    pub fn cover(p: &Path) -> Result<Option<Cover>, RootsError> {
+        match loader(p)? {
+            Some((_, format)) => format.cover(p),
+            None => Ok(None),
+        }
+    }
+
+    /// The text of the book in the file at `p`, none when its format's text
+    /// cannot be read.
+    pub fn text(p: &Path) -> Result<Option<String>, RootsError> {
+        match loader(p)? {
+            Some((_, format)) => format.text(p),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes the fields set in `changes` into the file at `p`, false when
+    /// the format does not support writing metadata.
+    pub fn write_metadata(p: &Path, changes: &Book) -> Result<bool, RootsError> {
+        match loader(p)? {
+            Some((_, format)) => format.write_metadata(p, changes),
+            None => Ok(false),
+        }
+    }
+
+    /// The values of `field`, empty when the book has none or `field` is
+    /// stored with the book's files rather than the book.
+    pub fn values(&self, field: Field) -> Vec<String> {
+        match field {
+            Field::Title => self.title.iter().cloned().collect(),
+            Field::Author => self.author.clone().unwrap_or_default(),
+            Field::Contributor => self.contributors.iter().flatten().map(|contributor| contributor.name.clone()).collect(),
+            Field::Editor | Field::Illustrator | Field::Narrator | Field::Translator => self.contributors.iter()
+                .flatten()
+                .filter(|contributor| field.role() == Some(contributor.role.as_str()))
+                .map(|contributor| contributor.name.clone())
+                .collect(),
+            Field::Subject => self.subject.clone().unwrap_or_default(),
+            Field::Series => self.series.iter().cloned().collect(),
+            Field::SeriesIndex => self.series_index.iter().map(f64::to_string).collect(),
+            Field::Publisher => self.publisher.iter().cloned().collect(),
+            Field::Imprint => self.imprint.iter().cloned().collect(),
+            Field::Description => self.description.iter().cloned().collect(),
+            Field::Language => self.language.iter().cloned().collect(),
+            Field::Date => self.publication_date.iter().map(PartialDate::to_string).collect(),
+            Field::Year => self.publication_date.iter().map(|date| format!("{:04}", date.year())).collect(),
+            Field::Asin => self.asin.iter().cloned().collect(),
+            Field::Isbn => self.isbn.iter().cloned().collect(),
+            Field::Identifier => self.identifiers.iter().flatten().map(Identifier::to_string).collect(),
+            Field::Path | Field::Format | Field::Hash | Field::Imported => Vec::new(),
+        }
+    }
+
+    /// The authors with their sort names, derived for authors without one.
+    pub fn names(&self) -> Vec<Name> {
+        let sorts = self.author_sort.as_deref().unwrap_or_default();
+        self.author.iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, author)| Name::with_sort(author, sorts.get(index).map(String::as_str)))
+            .collect()
+    }
+}
+
+/// A cover image as stored in an e-book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cover {
+    pub data: Vec<u8>,
+    extension: &'static str,
+}
+
+impl Cover {
+    /// The image in `data`, if it is a JPEG, PNG or GIF.
+    pub fn new(data: Vec<u8>) -> Option<Cover> {
+        let extension = if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            "jpg"
+        } else if data.starts_with(b"\x89PNG") {
+            "png"
+        } else if data.starts_with(b"GIF8") {
+            "gif"
+        } else {
+            return None;
+        };
+        Some(Cover { data, extension })
+    }
+
+    pub fn extension(&self) -> &'static str {
+        self.extension
+    }
+}
+
+/// The highest score `book_comparator` gives.
+pub const MAX_SCORE: usize = 100;
+
+const ISBN_WEIGHT: usize = 55;
+const TITLE_WEIGHT: usize = 20;
+const AUTHOR_WEIGHT: usize = 15;
+const YEAR_WEIGHT: usize = 10;
+
+/// Scores how likely two books are to be the same, from 0 to `MAX_SCORE`.
+///
+/// The weights keep the matching order: an ISBN beats title, authors and year
+/// together, authors and title beat year and title, then title alone, then
+/// authors alone.
+pub fn book_comparator(l: &Book, r: &Book) -> usize {
+    let isbn = match (normalise_isbn(&l.isbn), normalise_isbn(&r.isbn)) {
+        (Some(l), Some(r)) if l == r => ISBN_WEIGHT,
+        _ if shared_identifier(l, r) => ISBN_WEIGHT,
+        _ => 0,
+    };
+    let title = match (normalise(&l.title), normalise(&r.title)) {
+        (Some(l), Some(r)) if l == r => TITLE_WEIGHT,
+        (Some(l), Some(r)) if main_title(&l) == main_title(&r) => TITLE_WEIGHT * 4 / 5,
+        (Some(l), Some(r)) if l.contains(&r) || r.contains(&l) => TITLE_WEIGHT * 3 / 5,
+        _ => 0,
+    };
+    let authors = author_overlap(&l.author, &r.author);
+    let year = match (l.publication_date, r.publication_date) {
+        (Some(l), Some(r)) if l.year() == r.year() && title > 0 => YEAR_WEIGHT,
+        _ => 0,
+    };
+    isbn + title + (AUTHOR_WEIGHT as f64 * authors).round() as usize + year
+}
+
+fn normalise(value: &Option<String>) -> Option<String> {
+    value.as_ref()
+        .map(|v| v.to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() || c == ':' { c } else { ' ' })
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join(" ")
+            .replace(" :", ":"))
+        .filter(|v| !v.is_empty())
+}
+
+/// The title without any subtitle.
+fn main_title(title: &str) -> &str {
+    title.split(':').next().unwrap_or(title).trim()
+}
+
+/// The ISBN-13 of valid ISBNs, other values stripped of punctuation.
+fn normalise_isbn(isbn: &Option<String>) -> Option<String> {
+    isbn.as_ref()
+        .map(|i| isbn13(i).unwrap_or(i.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_uppercase()))
+        .filter(|i| !i.is_empty())
+}
+
+/// Whether the books share an identifier, such as an ASIN or a second ISBN.
+fn shared_identifier(l: &Book, r: &Book) -> bool {
+    let identifiers = |book: &Book| -> Vec<Identifier> {
+        let mut identifiers = book.identifiers.clone().unwrap_or_default();
+        identifiers.extend(book.asin.iter().map(|asin| Identifier::new("asin", asin)));
+        identifiers
+    };
+    let r = identifiers(r);
+    identifiers(l).iter().any(|identifier| identifier.scheme != "unknown" && r.contains(identifier))
+}
+
+/// The fraction of authors, compared by surname, the two books share.
+fn author_overlap(l: &Option<Vec<String>>, r: &Option<Vec<String>>) -> f64 {
+    let surnames = |authors: &Option<Vec<String>>| -> Vec<String> {
+        authors.iter()
+            .flatten()
+            .filter_map(|name| match name.split_once(',') {
+                Some((surname, _)) => normalise(&Some(surname.to_string())),
+                None => normalise(&Some(name.clone()))
+                    .and_then(|n| n.split(' ').last().map(String::from)),
+            })
+            .collect()
+    };
+    let (l, r) = (surnames(l), surnames(r));
+    if l.is_empty() || r.is_empty() {
+        return 0.0;
+    }
+    let shared = l.iter().filter(|name| r.contains(name)).count();
+    shared as f64 / l.len().max(r.len()) as f64
 }
 
-#[allow(dead_code)]
-pub fn book_comparator(_l: &Book, _r: &Book) -> usize {
-    unimplemented!()
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+
+    use lopdf::{dictionary, Object};
+
+    use super::*;
+
+    use self::rstest::*;
+
+    fn book(title: &str, author: &str, year: i32, isbn: Option<&str>) -> Book {
+        Book {
+            title: Some(title.to_string()),
+            author: Some(vec![author.to_string()]),
+            publication_date: Some(PartialDate::Year(year)),
+            isbn: isbn.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[fixture]
+    fn target() -> Book {
+        book("A Tale of Two Cities", "Charles Dickens", 1859, Some("978-0-14-143960-0"))
+    }
+
+    #[rstest]
+    #[case::identical(book("A Tale of Two Cities", "Charles Dickens", 1859, Some("9780141439600")), MAX_SCORE)]
+    #[case::isbn_only(book("Tale", "Someone", 2000, Some("9780141439600")), 67)]
+    #[case::title_authors_and_year(book("A Tale of Two Cities", "Charles Dickens", 1859, None), 45)]
+    #[case::authors_and_title(book("A tale of two cities.", "Dickens, Charles", 2003, None), 35)]
+    #[case::year_and_title(book("A Tale of Two Cities", "Anon", 1859, None), 30)]
+    #[case::subtitle(book("A Tale of Two Cities: A Story", "Anon", 1900, None), 16)]
+    #[case::authors(book("Great Expectations", "Charles Dickens", 1861, None), 15)]
+    #[case::unrelated(book("Howards End", "E. M. Forster", 1910, None), 0)]
+    fn books_are_scored(target: Book, #[case] candidate: Book, #[case] expected: usize) {
+        assert_eq!(expected, book_comparator(&target, &candidate));
+    }
+
+    #[test]
+    fn isbn10_matches_isbn13() {
+        let isbn10 = book("Tale", "Someone", 2000, Some("0-14-143960-2"));
+        assert_eq!(67, book_comparator(&target(), &isbn10));
+    }
+
+    #[test]
+    fn isbns_beat_title_authors_and_year() {
+        let isbn = book("Other", "Other", 1, Some("9780141439600"));
+        let everything_else = book("A Tale of Two Cities", "Charles Dickens", 1859, None);
+        assert!(book_comparator(&target(), &isbn) > book_comparator(&target(), &everything_else));
+    }
+
+    #[test]
+    fn shared_identifiers_count_as_isbns() {
+        let asin = |title: &str| Book {
+            title: Some(title.to_string()),
+            asin: Some("B000FC1PJI".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ISBN_WEIGHT, book_comparator(&asin("One"), &asin("Other")));
+    }
+
+    #[test]
+    fn matching_order_is_preserved() {
+        let target = target();
+        let isbn = book("Other", "Other", 1, Some("9780141439600"));
+        let title_authors_year = book("A Tale of Two Cities", "Charles Dickens", 1859, None);
+        let authors_title = book("A Tale of Two Cities", "Charles Dickens", 2000, None);
+        let year_title = book("A Tale of Two Cities", "Anon", 1859, None);
+        let title = book("A Tale of Two Cities", "Anon", 2000, None);
+        let authors = book("Bleak House", "Charles Dickens", 2000, None);
+        let scores = [&isbn, &title_authors_year, &authors_title, &year_title, &title, &authors]
+            .iter()
+            .map(|candidate| book_comparator(&target, candidate))
+            .collect::<Vec<usize>>();
+        assert!(scores.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", scores);
+    }
+
+    #[test]
+    fn unreadable_files_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.odt");
+        std::fs::write(&notes, "not an e-book").unwrap();
+        assert!(matches!(Book::new(&notes), Err(RootsError::UnsupportedFormat(_))));
+        assert!(matches!(Book::new(&dir.path().join("missing.epub")), Err(RootsError::Io(..))));
+        let fake = dir.path().join("fake.azw3");
+        std::fs::write(&fake, "not an e-book either").unwrap();
+        assert!(matches!(Book::new(&fake), Err(RootsError::CorruptFile(..))));
+    }
+
+    #[test]
+    fn only_documents_with_metadata_are_books() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, text: &str| std::fs::write(dir.path().join(name), text).unwrap();
+        file("README.md", "# Roots\n");
+        file("page.html", "<html><body>Saved</body></html>");
+        file("notes.md", "---\ntitle: Field Notes\n---\n");
+        file("plain.txt", "Only connect.");
+        file("plain.roots.yml", "title: Plain\n");
+        file("pg1342.txt", "The Project Gutenberg EBook of Pride and Prejudice\n*** START OF THIS EBOOK ***\n");
+        let names = find_books(dir.path()).iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["notes.md", "pg1342.txt", "plain.txt"], names);
+    }
+
+    #[rstest(path, expected,
+        case("a/Roadside.FB2.zip", Some("fb2.zip")),
+        case("a/roadside.zip", Some("zip")),
+        case("a/he.epub", Some("epub")),
+        case("a/README", None),
+    )]
+    fn extensions_include_compound_ones(path: &str, expected: Option<&str>) {
+        assert_eq!(expected.map(String::from), extension(Path::new(path)));
+    }
+
+    #[test]
+    fn fictionbooks_are_books() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("roadside.fb2.zip");
+        fb2::tests::fb2_zip(&path);
+        let book = Book::new(&path).unwrap();
+        assert_eq!(Some("Пикник на обочине".to_string()), book.title);
+        assert_eq!(Some("Молодая гвардия".to_string()), book.publisher);
+        assert_eq!(vec!["1980"], book.values(Field::Year));
+        assert_eq!(Some("9785235000018".to_string()), book.isbn);
+        assert_eq!(Some(vec!["sf_social".to_string(), "prose_classic".to_string()]), book.subject);
+        assert_eq!(Some("Noon Universe".to_string()), book.series);
+        assert_eq!(vec!["7"], book.values(Field::SeriesIndex));
+        assert_eq!(Some("ru".to_string()), book.language);
+    }
+
+    #[test]
+    fn documents_are_books() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.html");
+        std::fs::write(&path, "---\ntitle: Field Notes\nauthor: Ann Writer\ntranslator: Bo Trans\ncontributors: [Cy Artist (illustrator), Di Helper]\ndate: 2019-05-01\nlanguage: English\ntags: [Birds]\nisbn: 0-14-118213-X\n---\n<p>Notes</p>\n").unwrap();
+        assert_eq!(Some("HTML".to_string()), Format::detect(&path).unwrap().map(|format| format.to_string()));
+        let book = Book::new(&path).unwrap();
+        assert_eq!(Some("Field Notes".to_string()), book.title);
+        assert_eq!(Some(vec!["Ann Writer".to_string()]), book.author);
+        let contributors = vec![
+            Contributor::new("Bo Trans", "trl"),
+            Contributor::new("Cy Artist", "ill"),
+            Contributor::new("Di Helper", "ctb"),
+        ];
+        assert_eq!(Some(contributors), book.contributors);
+        assert_eq!(Some("2019-05-01".to_string()), book.publication_date.map(|date| date.to_string()));
+        assert_eq!(Some("en".to_string()), book.language);
+        assert_eq!(Some(vec!["Birds".to_string()]), book.subject);
+        assert_eq!(Some("9780141182131".to_string()), book.isbn);
+        let binary = dir.path().join("image.txt");
+        std::fs::write(&binary, b"\x89PNG\r\n\x1a\n\0\0").unwrap();
+        assert!(matches!(Book::new(&binary), Err(RootsError::CorruptFile(..))));
+    }
+
+    #[test]
+    fn pdfs_are_books() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.pdf");
+        let info = dictionary! {
+            "Title" => Object::string_literal("Howards End"),
+            "Author" => Object::string_literal("E. M. Forster; Anonymous"),
+            "Subject" => Object::string_literal("A novel"),
+            "Keywords" => Object::string_literal("England, Fiction"),
+            "CreationDate" => Object::string_literal("D:19101018"),
+        };
+        pdf::tests::pdf(&path, info, None, "ISBN 978-0-14-118213-1");
+        let book = Book::new(&path).unwrap();
+        assert_eq!(Some("Howards End".to_string()), book.title);
+        assert_eq!(Some(vec!["E. M. Forster".to_string(), "Anonymous".to_string()]), book.author);
+        assert_eq!(Some("A novel".to_string()), book.description);
+        assert_eq!(Some(vec!["England".to_string(), "Fiction".to_string()]), book.subject);
+        assert_eq!(vec!["1910"], book.values(Field::Year));
+        assert_eq!(Some("9780141182131".to_string()), book.isbn);
+    }
+
+    #[test]
+    fn comics_are_books() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("watchtower.cbz");
+        comic::tests::cbz(&path);
+        let book = Book::new(&path).unwrap();
+        assert_eq!(Some("The Pit".to_string()), book.title);
+        assert_eq!(Some("Watchtower Vol. 2".to_string()), book.series);
+        assert_eq!(Some(3.0), book.series_index);
+        assert_eq!(Some(vec!["Ann Writer".to_string(), "Bo Writer".to_string(), "Cy Artist".to_string()]), book.author);
+        assert_eq!(vec!["Di Colour"], book.values(Field::Illustrator));
+        assert_eq!(vec!["Ed Trans"], book.values(Field::Translator));
+        assert_eq!(Some("Foghorn".to_string()), book.publisher);
+        assert_eq!(Some(PartialDate::Month(1987, 9)), book.publication_date);
+        assert_eq!(Some("The tower falls.".to_string()), book.description);
+        assert_eq!(Some("en-US".to_string()), book.language);
+        assert_eq!(Some(vec!["Superhero".to_string(), "Mystery".to_string()]), book.subject);
+        assert_eq!(Some("png"), Book::cover(&path).unwrap().map(|cover| cover.extension()));
+    }
 }
@@ -0,0 +1,165 @@
+/// Generational suffixes and degrees, which follow the forenames when a name
+/// is sorted.
+const SUFFIXES: [&str; 8] = ["jr", "sr", "ii", "iii", "iv", "phd", "md", "esq"];
+
+/// MARC relator codes with the names queries and listings use for them.
+pub const ROLES: [(&str, &str); 6] = [
+    ("aut", "author"),
+    ("ctb", "contributor"),
+    ("edt", "editor"),
+    ("ill", "illustrator"),
+    ("nrt", "narrator"),
+    ("trl", "translator"),
+];
+
+/// A personal name as it is shown, `E. M. Forster`, and as it is sorted,
+/// `Forster, E. M.`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub display: String,
+    pub sort: String,
+}
+
+impl Name {
+    /// `name` as written in metadata, either `Forenames Surname` or inverted
+    /// as `Surname, Forenames`. Names of a single word sort as they are.
+    pub fn parse(name: &str) -> Name {
+        let name = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+        match name.split_once(',') {
+            Some((surname, rest)) if !is_suffix(rest) => {
+                let (forenames, suffix) = match rest.split_once(',') {
+                    Some((forenames, suffix)) => (forenames.trim(), Some(suffix.trim())),
+                    None => (rest.trim(), None),
+                };
+                let display = [forenames, surname.trim()].into_iter()
+                    .chain(suffix)
+                    .filter(|part| !part.is_empty())
+                    .collect::<Vec<&str>>()
+                    .join(" ");
+                Name { display, sort: name.clone() }
+            }
+            _ => {
+                let sort = sort_name(&name);
+                Name { display: name, sort }
+            }
+        }
+    }
+
+    /// `name` sorted as `file_as`, the `opf:file-as` of EPUBs, when given.
+    pub fn with_sort(name: &str, file_as: Option<&str>) -> Name {
+        let mut result = Name::parse(name);
+        if let Some(sort) = file_as.map(str::trim).filter(|sort| !sort.is_empty()) {
+            result.sort = sort.to_string();
+        }
+        result
+    }
+
+    /// The display name in lower case with punctuation and spacing dropped,
+    /// so that `E.M. Forster` and `E. M. Forster` are the same author.
+    pub fn key(&self) -> String {
+        key(&self.display)
+    }
+}
+
+/// A person credited in a role other than author, such as a translator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contributor {
+    pub name: String,
+    /// The MARC relator code of the role, `trl` for translators.
+    pub role: String,
+}
+
+impl Contributor {
+    pub fn new(name: &str, role: &str) -> Contributor {
+        Contributor { name: Name::parse(name).display, role: relator(role) }
+    }
+}
+
+/// The MARC relator code for `role`, written as a code or as one of the
+/// names in `ROLES`.
+pub fn relator(role: &str) -> String {
+    let role = role.trim().to_lowercase();
+    ROLES.iter()
+        .find(|(_, name)| *name == role)
+        .map(|(code, _)| code.to_string())
+        .unwrap_or(role)
+}
+
+/// The key `Name::key` gives the name `name`, written in either order.
+pub fn key(name: &str) -> String {
+    let display = match name.contains(',') {
+        true => Name::parse(name).display,
+        false => name.to_string(),
+    };
+    display.to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn is_suffix(word: &str) -> bool {
+    let word = word.trim().trim_end_matches('.').replace('.', "").to_lowercase();
+    SUFFIXES.contains(&word.as_str())
+}
+
+/// `Surname, Forenames[, Suffix]` for a name in display order.
+fn sort_name(name: &str) -> String {
+    let (name, suffix) = match name.split_once(',') {
+        Some((name, suffix)) => (name.trim(), Some(suffix.trim())),
+        None => match name.rsplit_once(' ') {
+            Some((rest, last)) if is_suffix(last) => (rest, Some(last)),
+            _ => (name, None),
+        },
+    };
+    match name.rsplit_once(' ') {
+        Some((forenames, surname)) => [surname, forenames].into_iter().chain(suffix).collect::<Vec<&str>>().join(", "),
+        None => [name].into_iter().chain(suffix).collect::<Vec<&str>>().join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+
+    use super::*;
+
+    use self::rstest::*;
+
+    #[rstest]
+    #[case("E. M. Forster", "E. M. Forster", "Forster, E. M.")]
+    #[case("Forster, E. M.", "E. M. Forster", "Forster, E. M.")]
+    #[case("  Forster,  E.   M. ", "E. M. Forster", "Forster, E. M.")]
+    #[case("Martin Luther King Jr.", "Martin Luther King Jr.", "King, Martin Luther, Jr.")]
+    #[case("Martin Luther King, Jr.", "Martin Luther King, Jr.", "King, Martin Luther, Jr.")]
+    #[case("King, Martin Luther, Jr.", "Martin Luther King Jr.", "King, Martin Luther, Jr.")]
+    #[case("Homer", "Homer", "Homer")]
+    fn names_are_shown_and_sorted(#[case] name: &str, #[case] display: &str, #[case] sort: &str) {
+        let expected = Name { display: display.to_string(), sort: sort.to_string() };
+        assert_eq!(expected, Name::parse(name));
+    }
+
+    #[test]
+    fn file_as_overrides_the_derived_sort_name() {
+        assert_eq!("Le Guin, Ursula K.", Name::with_sort("Ursula K. Le Guin", Some("Le Guin, Ursula K.")).sort);
+        assert_eq!("Guin, Ursula K. Le", Name::with_sort("Ursula K. Le Guin", Some(" ")).sort);
+    }
+
+    #[rstest]
+    #[case("trl", "trl")]
+    #[case(" Translator", "trl")]
+    #[case("ill", "ill")]
+    #[case("bkp", "bkp")]
+    fn roles_are_relator_codes(#[case] role: &str, #[case] expected: &str) {
+        assert_eq!(expected, relator(role));
+    }
+
+    #[test]
+    fn spellings_of_a_name_share_a_key() {
+        let keys = ["E.M. Forster", "E. M. Forster", "Forster, E. M.", "e m forster"].map(key);
+        assert!(keys.iter().all(|key| key == "e m forster"));
+        assert_ne!(key("E. M. Forster"), key("Edward Morgan Forster"));
+    }
+}
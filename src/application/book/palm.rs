@@ -0,0 +1,615 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::application::book::Book;
+use crate::error::RootsError;
+use crate::filesystem;
+
+/// The most of a file read for its headers; record 0 holds a few kilobytes
+/// of headers and EXTH records, and sometimes the first text.
+const RECORD0_LIMIT: usize = 1 << 20;
+
+/// EXTH record types.
+pub const EXTH_AUTHOR: u32 = 100;
+pub const EXTH_PUBLISHER: u32 = 101;
+pub const EXTH_DESCRIPTION: u32 = 103;
+pub const EXTH_ISBN: u32 = 104;
+pub const EXTH_SUBJECT: u32 = 105;
+pub const EXTH_PUBLISHED: u32 = 106;
+pub const EXTH_CONTRIBUTOR: u32 = 108;
+pub const EXTH_SOURCE: u32 = 112;
+pub const EXTH_ASIN: u32 = 113;
+pub const EXTH_KF8_BOUNDARY: u32 = 121;
+pub const EXTH_COVER_OFFSET: u32 = 201;
+pub const EXTH_UPDATED_TITLE: u32 = 503;
+pub const EXTH_CDE_ASIN: u32 = 504;
+pub const EXTH_LANGUAGE: u32 = 524;
+
+const EXTH_FLAG: u32 = 0x40;
+const UTF8: u32 = 65001;
+const NONE: u32 = 0xffff_ffff;
+
+/// PalmDOC header fields, relative to the start of record 0.
+const ENCRYPTION: usize = 0x0c;
+
+/// MOBI header fields, relative to the start of the header.
+const ENCODING: usize = 0x0c;
+const FILE_VERSION: usize = 0x14;
+const FULL_NAME_OFFSET: usize = 0x44;
+const FULL_NAME_LENGTH: usize = 0x48;
+const LOCALE: usize = 0x4c;
+const FIRST_IMAGE: usize = 0x5c;
+const EXTH_FLAGS: usize = 0x70;
+const DRM_OFFSET: usize = 0x98;
+const DRM_COUNT: usize = 0x9c;
+
+/// Windows language identifiers, the low ten bits of the MOBI header locale,
+/// with their ISO 639-1 codes.
+const LOCALES: [(u32, &str); 30] = [
+    (0x01, "ar"), (0x02, "bg"), (0x03, "ca"), (0x04, "zh"), (0x05, "cs"), (0x06, "da"), (0x07, "de"), (0x08, "el"),
+    (0x09, "en"), (0x0a, "es"), (0x0b, "fi"), (0x0c, "fr"), (0x0d, "he"), (0x0e, "hu"), (0x0f, "is"), (0x10, "it"),
+    (0x11, "ja"), (0x12, "ko"), (0x13, "nl"), (0x14, "no"), (0x15, "pl"), (0x16, "pt"), (0x18, "ro"), (0x19, "ru"),
+    (0x1a, "hr"), (0x1b, "sk"), (0x1d, "sv"), (0x1f, "tr"), (0x22, "uk"), (0x2a, "vi"),
+];
+
+/// Regions of the sublanguages, the high six bits of the locale, that
+/// differ between the English, French, German, Spanish and Portuguese
+/// speaking countries.
+const REGIONS: [(u32, &str); 14] = [
+    (0x0409, "en-US"), (0x0809, "en-GB"), (0x0c09, "en-AU"), (0x1009, "en-CA"),
+    (0x040c, "fr-FR"), (0x080c, "fr-BE"), (0x0c0c, "fr-CA"),
+    (0x0407, "de-DE"), (0x0807, "de-CH"), (0x0c07, "de-AT"),
+    (0x0c0a, "es-ES"), (0x080a, "es-MX"),
+    (0x0416, "pt-BR"), (0x0816, "pt-PT"),
+];
+
+/// What the headers of a `BOOKMOBI` file say about its format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MobiHeader {
+    /// The MOBI format version, 8 for KF8 (AZW3).
+    pub version: u32,
+    /// Whether the file has a KF8 section, alone or after a MOBI 6 one.
+    pub kf8: bool,
+    /// Whether the text is encrypted.
+    pub drm: bool,
+}
+
+impl Display for MobiHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match (self.version, self.kf8) {
+            (version, _) if version >= 8 => write!(f, "KF8 (MOBI version {})", version)?,
+            (version, true) => write!(f, "MOBI version {} with KF8", version)?,
+            (version, false) => write!(f, "MOBI version {}", version)?,
+        }
+        if self.drm {
+            write!(f, ", DRM protected")?;
+        }
+        Ok(())
+    }
+}
+
+/// A record of the EXTH header, the metadata section of a MOBI file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExthRecord {
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+impl ExthRecord {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).trim_end_matches('\0').trim().to_string()
+    }
+}
+
+/// The metadata of a MOBI file the mobi crate does not expose: the EXTH
+/// records and the locale of the MOBI header.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MobiMetadata {
+    /// The EXTH records, empty when the file has none.
+    pub records: Vec<ExthRecord>,
+    pub locale: Option<String>,
+}
+
+impl MobiMetadata {
+    /// The language as written in EXTH 524, or else the locale.
+    pub fn language(&self) -> Option<String> {
+        self.records.iter()
+            .find(|record| record.kind == EXTH_LANGUAGE)
+            .map(ExthRecord::text)
+            .filter(|language| !language.is_empty())
+            .or_else(|| self.locale.clone())
+    }
+}
+
+/// Reads the EXTH records and locale of the MOBI file at `path` from record 0.
+pub fn read_metadata(path: &Path) -> Result<MobiMetadata, RootsError> {
+    let (_, data) = read_record0(path)?;
+    let records = parse_exth(&data).ok_or_else(|| RootsError::corrupt(path, "malformed MOBI header"))?;
+    Ok(MobiMetadata { records, locale: locale(&data) })
+}
+
+/// Reads the format version and DRM flag of the MOBI file at `path`, whose
+/// first bytes are `magic`, from the file up to the end of record 0.
+pub fn read_header(path: &Path, magic: &[u8]) -> Result<MobiHeader, RootsError> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(record0_end(magic) as u64).read_to_end(&mut data))
+        .map_err(|error| RootsError::io(path, error))?;
+    parse_header(&data).ok_or_else(|| RootsError::corrupt(path, "malformed MOBI header"))
+}
+
+/// Opens the MOBI file at `path` and reads it up to the end of record 0,
+/// which covers the record list before it.
+fn read_record0(path: &Path) -> Result<(File, Vec<u8>), RootsError> {
+    let mut file = File::open(path).map_err(|error| RootsError::io(path, error))?;
+    let mut data = Vec::new();
+    // The PalmDB header and record list up to the offset of record 1.
+    (&mut file).take(90).read_to_end(&mut data).map_err(|error| RootsError::io(path, error))?;
+    let rest = record0_end(&data).saturating_sub(data.len()) as u64;
+    (&mut file).take(rest).read_to_end(&mut data).map_err(|error| RootsError::io(path, error))?;
+    Ok((file, data))
+}
+
+/// Where record 0, which holds the headers, ends: at the start of record 1,
+/// or at `RECORD0_LIMIT` when that is further or unknown.
+fn record0_end(magic: &[u8]) -> usize {
+    let count = magic.get(76..78).map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+    u32_at(magic, 86)
+        .filter(|_| count > 1)
+        .map_or(RECORD0_LIMIT, |offset| (offset as usize).min(RECORD0_LIMIT))
+}
+
+fn parse_header(data: &[u8]) -> Option<MobiHeader> {
+    let record0 = u32_at(data, 78)? as usize;
+    let encryption = data.get(record0 + ENCRYPTION..record0 + ENCRYPTION + 2)?;
+    let version = u32_at(data, record0 + 16 + FILE_VERSION)?;
+    let boundary = exth_at(data, record0)?
+        .iter()
+        .any(|record| record.kind == EXTH_KF8_BOUNDARY && u32_at(&record.data, 0).is_some_and(|index| index != NONE));
+    Some(MobiHeader { version, kf8: version >= 8 || boundary, drm: encryption != [0, 0] })
+}
+
+/// The language tag of the Windows locale in the MOBI header.
+fn locale(data: &[u8]) -> Option<String> {
+    let locale = u32_at(data, u32_at(data, 78)? as usize + 16 + LOCALE)? & 0xffff;
+    REGIONS.iter()
+        .find(|(id, _)| *id == locale)
+        .or_else(|| LOCALES.iter().find(|(id, _)| *id == locale & 0x3ff))
+        .map(|(_, tag)| tag.to_string())
+}
+
+/// The image record EXTH 201 names as the cover of the MOBI file at `path`,
+/// counted from the first image record; only record 0 and the cover are read.
+pub fn read_cover(path: &Path) -> Result<Option<Vec<u8>>, RootsError> {
+    let (mut file, data) = read_record0(path)?;
+    let length = file.metadata().map_err(|error| RootsError::io(path, error))?.len() as usize;
+    let offsets = record_offsets(&data, length).ok_or_else(|| RootsError::corrupt(path, "malformed PalmDB header"))?;
+    let records = parse_exth(&data).ok_or_else(|| RootsError::corrupt(path, "malformed MOBI header"))?;
+    match cover_range(&data, &offsets, &records) {
+        Some((start, end)) => {
+            let mut cover = vec![0; end - start];
+            file.seek(SeekFrom::Start(start as u64))
+                .and_then(|_| file.read_exact(&mut cover))
+                .map_err(|error| RootsError::io(path, error))?;
+            Ok(Some(cover))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Where the cover record starts and ends, given the header record in `data`.
+fn cover_range(data: &[u8], offsets: &[usize], records: &[ExthRecord]) -> Option<(usize, usize)> {
+    let offset = records.iter()
+        .find(|record| record.kind == EXTH_COVER_OFFSET)
+        .and_then(|record| u32_at(&record.data, 0))
+        .filter(|offset| *offset != NONE)?;
+    let first_image = u32_at(data, offsets[0] + 16 + FIRST_IMAGE).filter(|index| *index != NONE)?;
+    let index = first_image.checked_add(offset)? as usize;
+    Some((*offsets.get(index)?, *offsets.get(index + 1)?))
+}
+
+/// The start of each record, followed by `end`, the end of the file.
+fn record_offsets(data: &[u8], end: usize) -> Option<Vec<usize>> {
+    let count = data.get(76..78).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)?;
+    let mut offsets = (0..count)
+        .map(|index| u32_at(data, 78 + index * 8).map(|offset| offset as usize))
+        .collect::<Option<Vec<usize>>>()?;
+    offsets.push(end);
+    if count == 0 || offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+        return None;
+    }
+    Some(offsets)
+}
+
+fn parse_exth(data: &[u8]) -> Option<Vec<ExthRecord>> {
+    exth_at(data, u32_at(data, 78)? as usize)
+}
+
+/// The EXTH records of the header record starting at `record`.
+fn exth_at(data: &[u8], record: usize) -> Option<Vec<ExthRecord>> {
+    let mobi = record + 16;
+    if data.get(mobi..mobi + 4)? != b"MOBI" {
+        return None;
+    }
+    let header_length = u32_at(data, mobi + 4)? as usize;
+    if u32_at(data, mobi + EXTH_FLAGS)? & EXTH_FLAG == 0 {
+        return Some(Vec::new());
+    }
+    let exth = mobi + header_length;
+    if data.get(exth..exth + 4)? != b"EXTH" {
+        return None;
+    }
+    let count = u32_at(data, exth + 8)?;
+    let mut offset = exth + 12;
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let kind = u32_at(data, offset)?;
+        let length = u32_at(data, offset + 4)? as usize;
+        if length < 8 {
+            return None;
+        }
+        records.push(ExthRecord { kind, data: data.get(offset + 8..offset + length)?.to_vec() });
+        offset += length;
+    }
+    Some(records)
+}
+
+/// Rewrites the metadata of a MOBI, AZW or AZW3 file in its EXTH header.
+pub struct MobiWriter {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl MobiWriter {
+    pub fn new(path: &Path) -> Result<MobiWriter, RootsError> {
+        let data = fs::read(path).map_err(|error| RootsError::io(path, error))?;
+        Ok(MobiWriter { path: path.to_path_buf(), data })
+    }
+
+    /// Writes the fields set in `changes`, keeping the original file as
+    /// `<name>.bak` unless an earlier backup exists.
+    pub fn write(&self, changes: &Book) -> Result<(), RootsError> {
+        let data = update(&self.data, changes).map_err(|error| RootsError::corrupt(&self.path, error))?;
+        let temporary = filesystem::with_suffix(&self.path, ".tmp");
+        if let Err(error) = fs::write(&temporary, data) {
+            let _ = fs::remove_file(&temporary);
+            return Err(RootsError::io(&temporary, error));
+        }
+        filesystem::replace(&self.path, &temporary).map_err(|error| RootsError::io(&self.path, error))
+    }
+}
+
+/// The EXTH records for the fields set in `changes`, by record type.
+fn exth_changes(changes: &Book) -> Vec<(u32, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut set = |kind: u32, values: Option<Vec<String>>| {
+        if let Some(values) = values {
+            records.push((kind, values));
+        }
+    };
+    set(EXTH_AUTHOR, changes.author.clone());
+    set(EXTH_PUBLISHER, changes.publisher.clone().map(|publisher| vec![publisher]));
+    set(EXTH_DESCRIPTION, changes.description.clone().map(|description| vec![description]));
+    set(EXTH_ISBN, changes.isbn.clone().map(|isbn| vec![isbn]));
+    set(EXTH_SUBJECT, changes.subject.clone());
+    set(EXTH_PUBLISHED, changes.publication_date.map(|date| vec![date.to_string()]));
+    set(EXTH_ASIN, changes.asin.clone().map(|asin| vec![asin]));
+    set(EXTH_UPDATED_TITLE, changes.title.clone().map(|title| vec![title]));
+    set(EXTH_LANGUAGE, changes.language.clone().map(|language| vec![language]));
+    records
+}
+
+/// The file with new header records holding `changes`, every later record
+/// moved by the change in length.
+///
+/// Files combining a MOBI and a KF8 (AZW3) section have a header record for
+/// each, both are rewritten.
+fn update(data: &[u8], changes: &Book) -> Result<Vec<u8>, &'static str> {
+    let malformed = "malformed MOBI header";
+    let offsets = record_offsets(data, data.len()).ok_or(malformed)?;
+    let count = offsets.len() - 1;
+    let boundary = exth_at(data, offsets[0])
+        .ok_or(malformed)?
+        .iter()
+        .find(|record| record.kind == EXTH_KF8_BOUNDARY)
+        .and_then(|record| u32_at(&record.data, 0))
+        .filter(|boundary| *boundary != NONE)
+        .map(|boundary| boundary as usize);
+    let changes = exth_changes(changes);
+    let title = changes.iter()
+        .find(|(kind, _)| *kind == EXTH_UPDATED_TITLE)
+        .and_then(|(_, values)| values.first());
+    let header = |index: usize| rewrite_header(&data[offsets[index]..offsets[index + 1]], &changes, title);
+    let mut rewritten = vec![(0, header(0).ok_or(malformed)??)];
+    if let Some(boundary) = boundary.filter(|boundary| *boundary > 0 && *boundary < count) {
+        if let Some(record) = header(boundary) {
+            rewritten.push((boundary, record?));
+        }
+    }
+    let mut file = data[..offsets[0]].to_vec();
+    for index in 0..count {
+        let offset = file.len() as u32;
+        file[78 + index * 8..82 + index * 8].copy_from_slice(&offset.to_be_bytes());
+        match rewritten.iter().find(|(header, _)| *header == index) {
+            Some((_, record)) => file.extend_from_slice(record),
+            None => file.extend_from_slice(&data[offsets[index]..offsets[index + 1]]),
+        }
+    }
+    Ok(file)
+}
+
+/// A header record with its EXTH records of the changed types replaced, and
+/// the full name following them set to `title`.
+fn rewrite_header(record: &[u8], changes: &[(u32, Vec<String>)], title: Option<&String>) -> Option<Result<Vec<u8>, &'static str>> {
+    let mobi = 16;
+    if record.get(mobi..mobi + 4)? != b"MOBI" {
+        return None;
+    }
+    let header_length = u32_at(record, mobi + 4)? as usize;
+    let encrypted = record.get(ENCRYPTION..ENCRYPTION + 2)? != [0, 0];
+    let drm = header_length > DRM_COUNT + 4 && u32_at(record, mobi + DRM_OFFSET)? != NONE && u32_at(record, mobi + DRM_COUNT)? > 0;
+    if encrypted || drm {
+        return Some(Err("DRM protected"));
+    }
+    let utf8 = u32_at(record, mobi + ENCODING)? == UTF8;
+    let name_offset = u32_at(record, mobi + FULL_NAME_OFFSET)? as usize;
+    let name_length = u32_at(record, mobi + FULL_NAME_LENGTH)? as usize;
+    let name = match title {
+        Some(title) => encode(title, utf8),
+        None => record.get(name_offset..name_offset + name_length)?.to_vec(),
+    };
+    let mut records = exth_at(record, 0)?
+        .into_iter()
+        .filter(|record| changes.iter().all(|(kind, _)| *kind != record.kind))
+        .collect::<Vec<ExthRecord>>();
+    for (kind, values) in changes {
+        records.extend(values.iter().map(|value| ExthRecord { kind: *kind, data: encode(value, utf8) }));
+    }
+
+    let mut header = record.get(..mobi + header_length)?.to_vec();
+    let flags = u32_at(&header, mobi + EXTH_FLAGS)? | EXTH_FLAG;
+    header[mobi + EXTH_FLAGS..mobi + EXTH_FLAGS + 4].copy_from_slice(&flags.to_be_bytes());
+    let exth = records.iter().fold(Vec::new(), |mut exth, record| {
+        exth.extend_from_slice(&record.kind.to_be_bytes());
+        exth.extend_from_slice(&(record.data.len() as u32 + 8).to_be_bytes());
+        exth.extend_from_slice(&record.data);
+        exth
+    });
+    header.extend_from_slice(b"EXTH");
+    header.extend_from_slice(&(exth.len() as u32 + 12).to_be_bytes());
+    header.extend_from_slice(&(records.len() as u32).to_be_bytes());
+    header.extend_from_slice(&exth);
+    pad(&mut header);
+    let name_offset = header.len() as u32;
+    header[mobi + FULL_NAME_OFFSET..mobi + FULL_NAME_OFFSET + 4].copy_from_slice(&name_offset.to_be_bytes());
+    header[mobi + FULL_NAME_LENGTH..mobi + FULL_NAME_LENGTH + 4].copy_from_slice(&(name.len() as u32).to_be_bytes());
+    header.extend_from_slice(&name);
+    header.extend_from_slice(&[0, 0]);
+    pad(&mut header);
+    Some(Ok(header))
+}
+
+fn pad(data: &mut Vec<u8>) {
+    data.resize(data.len().div_ceil(4) * 4, 0);
+}
+
+/// `text` in the file's encoding, UTF-8 or CP1252; characters CP1252 lacks
+/// become `?`.
+fn encode(text: &str, utf8: bool) -> Vec<u8> {
+    if utf8 {
+        return text.as_bytes().to_vec();
+    }
+    text.chars()
+        .map(|c| match c {
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            '\u{2026}' => 0x85,
+            c if (c as u32) < 0x80 || (0xa0..=0xff).contains(&(c as u32)) => c as u32 as u8,
+            _ => b'?',
+        })
+        .collect()
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// A minimal PalmDB with one record holding a MOBI header and `records`.
+    pub fn mobi(records: &[(u32, &str)]) -> Vec<u8> {
+        palm_db(&[header(records)])
+    }
+
+    fn palm_db(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0u8; 78];
+        data[60..68].copy_from_slice(b"BOOKMOBI");
+        data[76..78].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        let mut offset = 78 + records.len() * 8 + 2;
+        for record in records {
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&[0; 4]);
+            offset += record.len();
+        }
+        data.extend_from_slice(&[0; 2]);
+        data.extend(records.concat());
+        data
+    }
+
+    /// A header record in UTF-8 without DRM, with no full name.
+    fn header(records: &[(u32, &str)]) -> Vec<u8> {
+        let mut data = vec![0u8; 16];
+        let mut header = vec![0u8; 0xe8];
+        header[0..4].copy_from_slice(b"MOBI");
+        header[4..8].copy_from_slice(&0xe8u32.to_be_bytes());
+        header[ENCODING..ENCODING + 4].copy_from_slice(&UTF8.to_be_bytes());
+        header[EXTH_FLAGS..EXTH_FLAGS + 4].copy_from_slice(&EXTH_FLAG.to_be_bytes());
+        header[DRM_OFFSET..DRM_OFFSET + 4].copy_from_slice(&NONE.to_be_bytes());
+        data.extend_from_slice(&header);
+        let mut exth = Vec::new();
+        for (kind, value) in records {
+            exth.extend_from_slice(&kind.to_be_bytes());
+            exth.extend_from_slice(&(value.len() as u32 + 8).to_be_bytes());
+            exth.extend_from_slice(value.as_bytes());
+        }
+        data.extend_from_slice(b"EXTH");
+        data.extend_from_slice(&(exth.len() as u32 + 12).to_be_bytes());
+        data.extend_from_slice(&(records.len() as u32).to_be_bytes());
+        data.extend_from_slice(&exth);
+        data
+    }
+
+    fn texts(records: Vec<ExthRecord>) -> Vec<(u32, String)> {
+        records.iter().map(|record| (record.kind, record.text())).collect()
+    }
+
+    #[test]
+    fn exth_records_are_read() {
+        let records = parse_exth(&mobi(&[(EXTH_ISBN, "9780141439600"), (EXTH_ASIN, "B000FC1PJI")])).unwrap();
+        assert_eq!(2, records.len());
+        assert_eq!(EXTH_ASIN, records[1].kind);
+        assert_eq!("B000FC1PJI", records[1].text());
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        let data = mobi(&[(EXTH_ISBN, "9780141439600")]);
+        assert!(parse_exth(&data[..data.len() - 4]).is_none());
+        assert!(parse_exth(&data[..60]).is_none());
+    }
+
+    #[test]
+    fn writing_replaces_changed_records_and_moves_the_rest() {
+        let text = b"<p>Only connect.</p>".to_vec();
+        let data = palm_db(&[
+            header(&[(EXTH_AUTHOR, "Forster"), (EXTH_SOURCE, "calibre:12"), (EXTH_ISBN, "014118213X")]),
+            text.clone(),
+        ]);
+        let changes = Book {
+            title: Some("Howards End".to_string()),
+            author: Some(vec!["E. M. Forster".to_string(), "Anon".to_string()]),
+            isbn: Some("9780141182131".to_string()),
+            ..Default::default()
+        };
+        let updated = update(&data, &changes).unwrap();
+        assert_eq!(
+            vec![
+                (EXTH_SOURCE, "calibre:12".to_string()),
+                (EXTH_AUTHOR, "E. M. Forster".to_string()),
+                (EXTH_AUTHOR, "Anon".to_string()),
+                (EXTH_ISBN, "9780141182131".to_string()),
+                (EXTH_UPDATED_TITLE, "Howards End".to_string()),
+            ],
+            texts(parse_exth(&updated).unwrap())
+        );
+        let record0 = u32_at(&updated, 78).unwrap() as usize;
+        let name = record0 + u32_at(&updated, record0 + 16 + FULL_NAME_OFFSET).unwrap() as usize;
+        assert_eq!(b"Howards End", &updated[name..name + 11]);
+        let record1 = u32_at(&updated, 86).unwrap() as usize;
+        assert_eq!(0, (record1 - record0) % 4);
+        assert_eq!(text, updated[record1..]);
+    }
+
+    #[test]
+    fn kf8_headers_are_rewritten_too() {
+        let boundary = String::from_utf8(2u32.to_be_bytes().to_vec()).unwrap();
+        let data = palm_db(&[
+            header(&[(EXTH_KF8_BOUNDARY, &boundary), (EXTH_PUBLISHER, "Arnold")]),
+            b"text".to_vec(),
+            header(&[(EXTH_PUBLISHER, "Arnold")]),
+        ]);
+        let changes = Book { publisher: Some("Edward Arnold".to_string()), ..Default::default() };
+        let updated = update(&data, &changes).unwrap();
+        let kf8 = u32_at(&updated, 94).unwrap() as usize;
+        assert_eq!(vec![(EXTH_PUBLISHER, "Edward Arnold".to_string())], texts(exth_at(&updated, kf8).unwrap()));
+    }
+
+    #[test]
+    fn kf8_and_drm_are_reported() {
+        let mut record0 = header(&[]);
+        record0[16 + FILE_VERSION..16 + FILE_VERSION + 4].copy_from_slice(&8u32.to_be_bytes());
+        record0[ENCRYPTION..ENCRYPTION + 2].copy_from_slice(&2u16.to_be_bytes());
+        let kf8 = parse_header(&palm_db(&[record0])).unwrap();
+        assert_eq!(MobiHeader { version: 8, kf8: true, drm: true }, kf8);
+        assert_eq!("KF8 (MOBI version 8), DRM protected", kf8.to_string());
+
+        let boundary = String::from_utf8(1u32.to_be_bytes().to_vec()).unwrap();
+        let mut record0 = header(&[(EXTH_KF8_BOUNDARY, &boundary)]);
+        record0[16 + FILE_VERSION..16 + FILE_VERSION + 4].copy_from_slice(&6u32.to_be_bytes());
+        let combined = parse_header(&palm_db(&[record0, b"text".to_vec()])).unwrap();
+        assert_eq!("MOBI version 6 with KF8", combined.to_string());
+    }
+
+    #[test]
+    fn headers_are_read_from_record_0_alone() {
+        let record0 = header(&[]);
+        let data = palm_db(&[record0.clone(), vec![0; 4096]]);
+        let end = u32_at(&data, 78).unwrap() as usize + record0.len();
+        assert_eq!(end, record0_end(&data[..1024]));
+        assert_eq!(RECORD0_LIMIT, record0_end(&palm_db(&[record0])));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.mobi");
+        fs::write(&path, &data).unwrap();
+        assert_eq!(parse_header(&data).unwrap(), read_header(&path, &data[..1024]).unwrap());
+        assert_eq!(end, read_record0(&path).unwrap().1.len());
+    }
+
+    #[test]
+    fn languages_are_read_from_exth_or_the_locale() {
+        let with_locale = |id: u32, records: &[(u32, &str)]| {
+            let mut record0 = header(records);
+            record0[16 + LOCALE..16 + LOCALE + 4].copy_from_slice(&id.to_be_bytes());
+            palm_db(&[record0])
+        };
+        assert_eq!(None, locale(&mobi(&[])));
+        assert_eq!(Some("en-GB".to_string()), locale(&with_locale(0x0809, &[])));
+        assert_eq!(Some("it".to_string()), locale(&with_locale(0x0410, &[])));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.mobi");
+        fs::write(&path, with_locale(0x0410, &[(EXTH_LANGUAGE, "en-us")])).unwrap();
+        let metadata = read_metadata(&path).unwrap();
+        assert_eq!(Some("it".to_string()), metadata.locale);
+        assert_eq!(Some("en-us".to_string()), metadata.language());
+    }
+
+    #[test]
+    fn cover_is_counted_from_the_first_image() {
+        let cover = vec![0xff, 0xd8, 0xff, 0xe0];
+        let offset = String::from_utf8(1u32.to_be_bytes().to_vec()).unwrap();
+        let mut record0 = header(&[(EXTH_COVER_OFFSET, &offset)]);
+        record0[16 + FIRST_IMAGE..16 + FIRST_IMAGE + 4].copy_from_slice(&2u32.to_be_bytes());
+        let data = palm_db(&[record0, b"text".to_vec(), b"GIF89a".to_vec(), cover.clone()]);
+        let offsets = record_offsets(&data, data.len()).unwrap();
+        let records = parse_exth(&data).unwrap();
+        assert_eq!(Some((offsets[3], offsets[4])), cover_range(&data, &offsets, &records));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.mobi");
+        fs::write(&path, &data).unwrap();
+        assert_eq!(Some(cover), read_cover(&path).unwrap());
+    }
+
+    #[test]
+    fn drm_protected_files_are_not_written() {
+        let mut data = mobi(&[(EXTH_ISBN, "9780141439600")]);
+        data[88 + 16 + DRM_OFFSET..88 + 16 + DRM_OFFSET + 4].copy_from_slice(&0x200u32.to_be_bytes());
+        data[88 + 16 + DRM_COUNT..88 + 16 + DRM_COUNT + 4].copy_from_slice(&1u32.to_be_bytes());
+        let changes = Book { isbn: Some("9780141182131".to_string()), ..Default::default() };
+        assert_eq!(Err("DRM protected"), update(&data, &changes));
+
+        let mut data = mobi(&[(EXTH_ISBN, "9780141439600")]);
+        data[88 + ENCRYPTION..88 + ENCRYPTION + 2].copy_from_slice(&1u16.to_be_bytes());
+        assert_eq!(Err("DRM protected"), update(&data, &changes));
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use lopdf::{decode_text_string, Dictionary, Document, Object};
+use regex::Regex;
+use roxmltree::Node;
+
+use crate::application::book::date::PartialDate;
+use crate::application::book::identifier::isbn13;
+use crate::error::RootsError;
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+/// The XMP schemas read, with the prefixes their properties are keyed by.
+const SCHEMAS: [(&str, &str); 5] = [
+    ("http://purl.org/dc/elements/1.1/", "dc"),
+    ("http://ns.adobe.com/xap/1.0/", "xmp"),
+    ("http://ns.adobe.com/pdf/1.3/", "pdf"),
+    ("http://prismstandard.org/namespaces/basic/2.0/", "prism"),
+    ("http://prismstandard.org/namespaces/basic/3.0/", "prism"),
+];
+
+/// How many pages are searched for an ISBN, the copyright page is rarely
+/// further in.
+const SCANNED_PAGES: u32 = 5;
+
+/// The version in the `%PDF-` header of a PDF file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdfHeader {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl PdfHeader {
+    /// The header at the start of `magic`, none when it is not a PDF.
+    pub fn parse(magic: &[u8]) -> Option<PdfHeader> {
+        let version = magic.strip_prefix(b"%PDF-")?;
+        match version {
+            [major, b'.', minor, ..] if major.is_ascii_digit() && minor.is_ascii_digit() => {
+                Some(PdfHeader { major: major - b'0', minor: minor - b'0' })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for PdfHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "PDF {}.{}", self.major, self.minor)
+    }
+}
+
+/// The metadata of a PDF file: its document information dictionary, its
+/// XMP metadata and the text of its first pages.
+pub struct PdfMetadata {
+    info: HashMap<String, String>,
+    xmp: HashMap<String, Vec<String>>,
+    pub text: String,
+}
+
+impl PdfMetadata {
+    pub fn read(path: &Path) -> Result<PdfMetadata, RootsError> {
+        let document = load(path)?;
+        let info = document.trailer.get_deref(b"Info", &document)
+            .and_then(Object::as_dict)
+            .map(info)
+            .unwrap_or_default();
+        let xmp = document.catalog()
+            .and_then(|catalog| catalog.get_deref(b"Metadata", &document))
+            .and_then(Object::as_stream)
+            .ok()
+            .and_then(|stream| stream.decompressed_content().ok().or_else(|| Some(stream.content.clone())))
+            .and_then(|data| String::from_utf8(data).ok())
+            .map(|packet| xmp(&packet))
+            .unwrap_or_default();
+        let text = page_text(&document, SCANNED_PAGES as usize);
+        Ok(PdfMetadata { info, xmp, text })
+    }
+
+    /// The entry `key` of the document information dictionary.
+    pub fn info(&self, key: &str) -> Option<String> {
+        self.info.get(key).cloned()
+    }
+
+    /// The values of the XMP property `name`, prefixed as in `SCHEMAS`.
+    pub fn xmp(&self, name: &str) -> Vec<String> {
+        self.xmp.get(name).cloned().unwrap_or_default()
+    }
+
+    /// The application that converted the document to PDF, such as
+    /// `pdfTeX-1.40.25`.
+    pub fn producer(&self) -> Option<String> {
+        self.xmp("pdf:Producer").into_iter().next().or_else(|| self.info("Producer"))
+    }
+}
+
+/// The text of every page of the PDF at `path`.
+pub fn text(path: &Path) -> Result<String, RootsError> {
+    Ok(page_text(&load(path)?, usize::MAX))
+}
+
+fn load(path: &Path) -> Result<Document, RootsError> {
+    let mut document = Document::load(path).map_err(|error| RootsError::corrupt(path, error))?;
+    // most encrypted PDFs only restrict printing or copying and open with
+    // an empty password
+    if document.is_encrypted() {
+        document.decrypt("").map_err(|_| RootsError::corrupt(path, "encrypted with a password"))?;
+    }
+    Ok(document)
+}
+
+/// The text of the first `pages` pages, skipping pages whose fonts cannot
+/// be decoded.
+fn page_text(document: &Document, pages: usize) -> String {
+    document.get_pages()
+        .into_keys()
+        .take(pages)
+        .filter_map(|page| document.extract_text(&[page]).ok())
+        .collect()
+}
+
+/// The text entries of an information dictionary, without blank ones.
+fn info(dictionary: &Dictionary) -> HashMap<String, String> {
+    dictionary.iter()
+        .filter_map(|(key, value)| {
+            let value = decode_text_string(value).ok()?;
+            Some((String::from_utf8_lossy(key).into_owned(), value.trim().to_string()))
+        })
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+}
+
+/// The properties of an XMP packet by prefixed name, whether written as
+/// elements, as `rdf:Alt`, `rdf:Bag` or `rdf:Seq` containers, or as
+/// attributes of `rdf:Description`.
+fn xmp(packet: &str) -> HashMap<String, Vec<String>> {
+    let mut properties: HashMap<String, Vec<String>> = HashMap::new();
+    let document = match roxmltree::Document::parse(packet.trim_end_matches('\0')) {
+        Ok(document) => document,
+        Err(_) => return properties,
+    };
+    let descriptions = document.descendants()
+        .filter(|node| node.has_tag_name((RDF, "Description")));
+    for description in descriptions {
+        for attribute in description.attributes() {
+            if let Some(name) = attribute.namespace().and_then(|namespace| prefixed(namespace, attribute.name())) {
+                properties.entry(name).or_default().push(attribute.value().trim().to_string());
+            }
+        }
+        for property in description.children().filter(Node::is_element) {
+            let name = match property.tag_name().namespace().and_then(|namespace| prefixed(namespace, property.tag_name().name())) {
+                Some(name) => name,
+                None => continue,
+            };
+            let items = property.descendants()
+                .filter(|node| node.has_tag_name((RDF, "li")))
+                .filter_map(|item| item.text())
+                .map(|text| text.trim().to_string())
+                .collect::<Vec<String>>();
+            let values = if items.is_empty() {
+                property.text().map(|text| text.trim().to_string()).into_iter().collect()
+            } else {
+                items
+            };
+            properties.entry(name).or_default().extend(values);
+        }
+    }
+    properties.values_mut().for_each(|values| values.retain(|value| !value.is_empty()));
+    properties
+}
+
+fn prefixed(namespace: &str, name: &str) -> Option<String> {
+    SCHEMAS.iter()
+        .find(|(uri, _)| *uri == namespace)
+        .map(|(_, prefix)| format!("{}:{}", prefix, name))
+}
+
+/// The ISBN-13s of the valid ISBNs labelled `ISBN` in `text`, in order.
+pub fn isbns(text: &str) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r"(?i)\bISBN(?:-1[03])?[:\s]*((?:97[89][- ]?)?(?:\d[- ]?){9}[\dX])\b").unwrap()
+    });
+    let mut isbns: Vec<String> = Vec::new();
+    for isbn in pattern.captures_iter(text).filter_map(|captures| isbn13(&captures[1])) {
+        if !isbns.contains(&isbn) {
+            isbns.push(isbn);
+        }
+    }
+    isbns
+}
+
+/// The day of a PDF date, `D:YYYYMMDDHHmmSS`, or an XMP date,
+/// `YYYY-MM-DDThh:mm:ss`, to the precision given: everything after the year
+/// is optional.
+pub fn parse_date(date: &str) -> Option<PartialDate> {
+    let day = date.trim().trim_start_matches("D:").split('T').next()?;
+    let digits = day.chars().take_while(|c| c.is_ascii_digit() || *c == '-').filter(char::is_ascii_digit).collect::<String>();
+    let number = |range: std::ops::Range<usize>| digits.get(range).and_then(|digits| digits.parse::<u32>().ok());
+    PartialDate::from_parts(number(0..4)? as i32, number(4..6), number(6..8).filter(|_| digits.len() >= 8))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Stream, StringFormat};
+
+    use super::*;
+
+    const PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/"
+        xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:CreateDate="1910-10-18T09:00:00Z">
+      <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Howards End</rdf:li></rdf:Alt></dc:title>
+      <dc:creator><rdf:Seq><rdf:li>E. M. Forster</rdf:li></rdf:Seq></dc:creator>
+      <dc:subject><rdf:Bag><rdf:li>England</rdf:li><rdf:li>Fiction</rdf:li></rdf:Bag></dc:subject>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    /// A PDF with one page of `text` in Helvetica.
+    pub fn pdf(path: &Path, info: Dictionary, packet: Option<&str>, text: &str) {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let font_id = document.add_object(dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Helvetica",
+        });
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                Operation::new("Td", vec![72.into(), 720.into()]),
+                Operation::new("Tj", vec![Object::string_literal(text)]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = document.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        document.objects.insert(pages_id, Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }));
+        let mut catalog = dictionary! { "Type" => "Catalog", "Pages" => pages_id };
+        if let Some(packet) = packet {
+            let metadata = Stream::new(dictionary! { "Type" => "Metadata", "Subtype" => "XML" }, packet.as_bytes().to_vec());
+            catalog.set("Metadata", document.add_object(metadata));
+        }
+        let catalog_id = document.add_object(catalog);
+        let info_id = document.add_object(info);
+        document.trailer.set("Root", catalog_id);
+        document.trailer.set("Info", info_id);
+        document.save(path).unwrap();
+    }
+
+    #[test]
+    fn info_and_xmp_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("he.pdf");
+        let info = dictionary! {
+            "Title" => Object::String(b"Howards End".to_vec(), StringFormat::Literal),
+            "Author" => Object::String(b"\xfe\xff\0F\0o\0r\0s\0t\0e\0r".to_vec(), StringFormat::Hexadecimal),
+            "Keywords" => Object::string_literal(" "),
+            "CreationDate" => Object::string_literal("D:19101018090000+01'00'"),
+            "Producer" => Object::string_literal("pdfTeX-1.40.25"),
+        };
+        pdf(&path, info, Some(PACKET), "Howards End");
+        let metadata = PdfMetadata::read(&path).unwrap();
+        assert_eq!(Some("Howards End".to_string()), metadata.info("Title"));
+        assert_eq!(Some("Forster".to_string()), metadata.info("Author"));
+        assert_eq!(None, metadata.info("Keywords"));
+        assert_eq!(vec!["Howards End"], metadata.xmp("dc:title"));
+        assert_eq!(vec!["E. M. Forster"], metadata.xmp("dc:creator"));
+        assert_eq!(vec!["England", "Fiction"], metadata.xmp("dc:subject"));
+        assert_eq!(vec!["1910-10-18T09:00:00Z"], metadata.xmp("xmp:CreateDate"));
+        assert_eq!(Some("pdfTeX-1.40.25".to_string()), metadata.producer());
+        assert!(metadata.text.contains("Howards End"));
+    }
+
+    #[test]
+    fn labelled_isbns_are_found() {
+        let text = "First published 1910\nISBN 0-14-118213-X (paperback)\nisbn-13: 978 0 14 118213 1\nISBN 978-0-14-118213-0";
+        assert_eq!(vec!["9780141182131"], isbns(text));
+        assert!(isbns("Printed in 1978 0141182132").is_empty());
+    }
+
+    #[test]
+    fn pdf_and_xmp_dates_are_parsed() {
+        let day = |date| parse_date(date).map(|date| date.to_string());
+        assert_eq!(Some("1910-10-18".to_string()), day("D:19101018090000+01'00'"));
+        assert_eq!(Some("1910-10-18".to_string()), day("1910-10-18T09:00:00Z"));
+        assert_eq!(Some("1910-10".to_string()), day("D:191010"));
+        assert_eq!(Some("1910".to_string()), day("D:1910"));
+        assert_eq!(None, day("October 1910"));
+    }
+
+    #[test]
+    fn versions_are_read_from_the_header() {
+        assert_eq!(Some(PdfHeader { major: 1, minor: 7 }), PdfHeader::parse(b"%PDF-1.7\n%\xe2\xe3"));
+        assert_eq!(None, PdfHeader::parse(b"%!PS-Adobe-3.0"));
+    }
+}
@@ -1,12 +1,23 @@
 #[derive(Debug)]
 pub enum Command {
+    Alias {
+        remove: bool,
+        alias: Option<String>,
+        author: Option<String>,
+    },
     Config {
         path: bool,
         default: bool,
     },
+    Cover {
+        thumbnail: bool,
+        extract: Option<String>,
+        query: Vec<String>,
+    },
     Fields,
     Find {
         show_scores: bool,
+        target: Vec<String>,
     },
     Import {
         path: String,
@@ -14,11 +25,22 @@ pub enum Command {
     Info {
         path: String,
         fetch: bool,
+        write: bool,
     },
     List {
         author: bool,
         isbn: bool,
         table: bool,
+        fields: Option<String>,
+        format: Option<String>,
+        template: Option<String>,
+        sort: Option<String>,
+        query: Vec<String>,
+    },
+    Update {
+        dry_run: bool,
+        yes: bool,
+        fetch: bool,
+        write: bool,
     },
-    Update,
 }
@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageError, ImageFormat};
+use sha2::{Digest, Sha256};
+
+use crate::application::book::{Book, Cover};
+use crate::configuration::Configuration;
+use crate::database::{Library, Record};
+use crate::error::RootsError;
+
+/// Cover images taken from library books, named by their SHA-256 digest so
+/// editions sharing a cover share the file, with thumbnails of a fixed size.
+pub struct CoverCache {
+    directory: PathBuf,
+    width: u32,
+    height: u32,
+}
+
+impl CoverCache {
+    pub fn new(directory: PathBuf, width: u32, height: u32) -> CoverCache {
+        CoverCache { directory, width, height }
+    }
+
+    pub fn from_config(cfg: &Configuration) -> CoverCache {
+        CoverCache::new(cfg.get_cover_directory(), cfg.get_cover().width, cfg.get_cover().height)
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.directory.join(name)
+    }
+
+    /// Extracts the cover of the e-book at `path` into the cache, records it
+    /// for the book with `book_id` and returns its name; none when the file
+    /// has no cover.
+    pub fn store(&self, library: &mut Library, book_id: i64, path: &Path) -> Result<Option<String>, RootsError> {
+        let cover = match Book::cover(path)? {
+            Some(cover) => cover,
+            None => return Ok(None),
+        };
+        let name = self.save(&cover)?;
+        self.thumbnail(&name)?;
+        library.set_cover(book_id, Some(&name))?;
+        Ok(Some(name))
+    }
+
+    /// The name of the cached cover of `record`, extracted from its files
+    /// when it is not cached yet.
+    pub fn find(&self, library: &mut Library, record: &Record) -> Result<Option<String>, RootsError> {
+        if let Some(name) = library.cover(record.id)?.filter(|name| self.path(name).is_file()) {
+            return Ok(Some(name));
+        }
+        for file in record.files.iter().filter(|file| file.path.is_file()) {
+            if let Some(name) = self.store(library, record.id, &file.path)? {
+                return Ok(Some(name));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The thumbnail of the cached cover `name`, created on first use. The
+    /// cover is scaled and cropped to fill the thumbnail size.
+    pub fn thumbnail(&self, name: &str) -> Result<PathBuf, RootsError> {
+        let stem = Path::new(name).file_stem().and_then(|stem| stem.to_str()).unwrap_or(name);
+        let thumbnail = self.path(&format!("{}-{}x{}.jpg", stem, self.width, self.height));
+        if !thumbnail.is_file() {
+            let cover = self.path(name);
+            let image = image::open(&cover).map_err(|error| RootsError::corrupt(&cover, error))?;
+            let resized = image.resize_to_fill(self.width, self.height, FilterType::Triangle);
+            DynamicImage::ImageRgb8(resized.to_rgb8())
+                .save_with_format(&thumbnail, ImageFormat::Jpeg)
+                .map_err(|error| RootsError::corrupt(&thumbnail, error))?;
+        }
+        Ok(thumbnail)
+    }
+
+    fn save(&self, cover: &Cover) -> Result<String, RootsError> {
+        let name = format!("{:x}.{}", Sha256::digest(&cover.data), cover.extension());
+        let path = self.path(&name);
+        if !path.is_file() {
+            fs::create_dir_all(&self.directory).map_err(|error| RootsError::io(&self.directory, error))?;
+            fs::write(&path, &cover.data).map_err(|error| RootsError::io(&path, error))?;
+        }
+        Ok(name)
+    }
+}
+
+/// Copies the image at `source` to `destination`, converting it when the
+/// destination's extension names another format.
+pub fn extract(source: &Path, destination: &Path) -> Result<(), RootsError> {
+    let format = ImageFormat::from_path(destination)
+        .ok()
+        .filter(|format| matches!(format, ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif))
+        .ok_or_else(|| RootsError::Usage(format!("{}: use a .jpg, .png or .gif file", destination.display())))?;
+    if ImageFormat::from_path(source).ok() == Some(format) {
+        fs::copy(source, destination).map_err(|error| RootsError::io(destination, error))?;
+        return Ok(());
+    }
+    let image = image::open(source).map_err(|error| RootsError::corrupt(source, error))?;
+    let image = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ => image,
+    };
+    image.save_with_format(destination, format).map_err(|error| match error {
+        ImageError::IoError(error) => RootsError::io(destination, error),
+        error => RootsError::corrupt(source, error),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{GenericImageView, RgbImage};
+
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn thumbnails_have_a_fixed_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = CoverCache::new(dir.path().to_path_buf(), 20, 30);
+        let name = cache.save(&Cover::new(png(100, 60)).unwrap()).unwrap();
+        assert!(name.ends_with(".png"));
+        let thumbnail = cache.thumbnail(&name).unwrap();
+        assert_eq!((20, 30), image::open(thumbnail).unwrap().dimensions());
+    }
+
+    #[test]
+    fn covers_are_converted_on_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("cover.png");
+        fs::write(&source, png(10, 10)).unwrap();
+        let destination = dir.path().join("out.jpg");
+        extract(&source, &destination).unwrap();
+        assert_eq!(ImageFormat::Jpeg, image::guess_format(&fs::read(destination).unwrap()).unwrap());
+        assert!(matches!(extract(&source, &dir.path().join("out.txt")), Err(RootsError::Usage(_))));
+    }
+}
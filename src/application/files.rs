@@ -1,14 +1,8 @@
-use regex::Regex;
+use crate::filesystem::Rules;
 
-use crate::filesystem::Replacements;
-
-#[allow(unused)]
-fn clean_path(replacements: Replacements, path: String) -> String {
-    replacements.iter().fold(path, |acc, (pat, rep)| {
-        Regex::new(pat)
-            .unwrap()
-            .replace_all(acc.as_str(), rep.as_str())
-            .to_string()
+pub fn clean_path(rules: &Rules, path: String) -> String {
+    rules.iter().fold(path, |acc, (pattern, replacement)| {
+        pattern.replace_all(acc.as_str(), replacement.as_str()).to_string()
     })
 }
 
@@ -18,6 +12,8 @@ mod tests {
 
     use maplit::hashmap;
 
+    use crate::filesystem::{rules, Replacements};
+
     use super::*;
 
     use self::rstest::*;
@@ -27,7 +23,7 @@ mod tests {
         hashmap! {
             r#"[<>:"\?!\*\|/]"#.to_string() => r#"_"#.to_string(),
             "[\u{00}-\u{1f}]".to_string() => "".to_string(),
-            r#"\.$"#.to_string() => r#"_"#.to_string(),
+            r#"\.\s*$"#.to_string() => r#"_"#.to_string(),
             r#"\s+$"#.to_string() => r#""#.to_string(),
             r#"^\s+"#.to_string() => r#""#.to_string(),
             r#"^\."#.to_string() => r#"_"#.to_string()
@@ -45,8 +41,19 @@ mod tests {
     #[case::pipes("put _that_ in your |", "put _that_ in your _")]
     #[case::identity("valid.epub", "valid.epub")]
     #[case::exclaimation_marks(".invalid!.epub", "_invalid_.epub")]
+    #[case::trailing_dot_and_space("Title. ", "Title_")]
     fn path_is_corrected(#[case] input: String, #[case] expected: String, replacements: Replacements) {
-        let actual: String = clean_path(replacements, input);
+        let actual: String = clean_path(&rules(&replacements).unwrap(), input);
         assert_eq!(expected, actual)
     }
+
+    #[rstest]
+    fn rules_apply_in_pattern_order(replacements: Replacements) {
+        let patterns = rules(&replacements).unwrap().into_iter()
+            .map(|(pattern, _)| pattern.as_str().to_string())
+            .collect::<Vec<String>>();
+        let mut sorted = replacements.into_keys().collect::<Vec<String>>();
+        sorted.sort();
+        assert_eq!(sorted, patterns);
+    }
 }
@@ -0,0 +1,267 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::application::book::{extension, language, sidecar, Book};
+use crate::application::cover::CoverCache;
+use crate::application::files::clean_path;
+use crate::application::query::Field;
+use crate::application::template::{Template, TemplateError};
+use crate::configuration::Configuration;
+use crate::database::{Hashes, Library};
+use crate::error::RootsError;
+use crate::filesystem::{self, Destination, ProposedMove, ProposedMoves, Source};
+
+/// Names a layout template can use.
+const LAYOUT_NAMES: [&str; 13] = [
+    "title", "author", "author_sort", "authors", "series", "series_index", "publisher", "imprint", "year", "lang",
+    "isbn", "asin", "ext",
+];
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub found: usize,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// The on-disk structure of the library, a template for each path component.
+#[derive(Debug)]
+pub struct Layout {
+    components: Vec<Template>,
+}
+
+impl Layout {
+    pub fn parse(path: &str) -> Result<Layout, TemplateError> {
+        let components = path.split('/')
+            .filter(|component| !component.is_empty())
+            .map(Template::parse)
+            .collect::<Result<Vec<Template>, TemplateError>>()?;
+        for component in &components {
+            component.validate(|name| LAYOUT_NAMES.contains(&name))?;
+        }
+        if components.is_empty() {
+            return Err(TemplateError("empty layout".to_string()));
+        }
+        Ok(Layout { components })
+    }
+
+    /// The rendered path components for `book`, components without a value,
+    /// such as `{series}` for a standalone title, are left out.
+    pub fn components(&self, book: &Book, source: &Path) -> Vec<String> {
+        self.components.iter()
+            .map(|component| component.render(|name| value(book, source, name)).trim().to_string())
+            .filter(|component| !component.is_empty())
+            .collect()
+    }
+}
+
+fn value(book: &Book, source: &Path, name: &str) -> Option<String> {
+    let first = |field: Field| book.values(field).into_iter().next();
+    match name {
+        "title" => first(Field::Title)
+            .or(source.file_stem().and_then(OsStr::to_str).map(String::from)),
+        "author" => first(Field::Author).or(Some("Unknown Author".to_string())),
+        "author_sort" => book.names().into_iter().next().map(|name| name.sort).or(Some("Unknown Author".to_string())),
+        "authors" => Some(book.values(Field::Author).join(", ")),
+        "series" => first(Field::Series),
+        "series_index" => first(Field::SeriesIndex),
+        "publisher" => first(Field::Publisher),
+        "imprint" => first(Field::Imprint),
+        "year" => first(Field::Year),
+        "lang" => first(Field::Language),
+        "isbn" => first(Field::Isbn),
+        "asin" => first(Field::Asin),
+        "ext" => extension(source),
+        _ => None,
+    }
+}
+
+/// Imports the e-books in `sources`, found below `root`, into the library.
+///
+/// Every destination is planned, and the plan printed, before any file moves.
+/// Files already in the library, or repeated in `sources`, are left out of
+/// the plan.
+pub fn import(cfg: &Configuration, library: &mut Library, root: &Path, sources: Vec<PathBuf>) -> Result<ImportSummary, RootsError> {
+    let settings = cfg.get_import();
+    let layout = Layout::parse(&settings.path)
+        .map_err(|error| RootsError::Config(format!("import.path, {}", error)))?;
+    let directory = cfg.get_directory();
+    let mut summary = ImportSummary { found: sources.len(), ..Default::default() };
+    let covers = CoverCache::from_config(cfg);
+    let rules = filesystem::rules(&settings.replacements)
+        .map_err(|error| RootsError::Config(format!("import.replacements, {}", error)))?;
+    let mut plan = ProposedMoves::new(rules, clean_path);
+    let mut planned: Vec<(Book, Hashes)> = Vec::new();
+    for source in sources {
+        let (book, hashes) = match read_book(cfg, &source).and_then(|book| Ok((book, hashes(cfg, &source)?))) {
+            Ok(found) => found,
+            Err(error) => {
+                println!("Failed to import {}", error);
+                summary.failed += 1;
+                continue;
+            }
+        };
+        let earlier = plan.iter()
+            .zip(planned.iter())
+            .find(|(_, (_, earlier))| same_content(earlier, &hashes))
+            .map(|(ProposedMove(Source(earlier), _), _)| earlier.clone());
+        if let Some(earlier) = earlier {
+            println!("Skipping {}, duplicate of {}", source.display(), earlier.display());
+            summary.duplicates += 1;
+            continue;
+        }
+        let destination = plan.destination(&directory, layout.components(&book, &source));
+        if let Some(original) = library.find_duplicate(&hashes)? {
+            if original.path == source || original.path == destination {
+                println!("Skipping {}, already imported", source.display());
+            } else if original.hash != hashes.file && original.content_hash == hashes.content {
+                println!("Skipping {}, same content as {}", source.display(), original.path.display());
+            } else {
+                println!("Skipping {}, duplicate of {}", source.display(), original.path.display());
+            }
+            summary.duplicates += 1;
+            continue;
+        }
+        plan.propose(&source, destination);
+        planned.push((book, hashes));
+    }
+    print!("{}", plan);
+    for (ProposedMove(Source(source), Destination(destination)), (book, hashes)) in plan.iter().zip(planned) {
+        let in_place = destination == source;
+        if !in_place && destination.exists() && !settings.overwrite {
+            println!("Skipping {}, {} already exists", source.display(), destination.display());
+            summary.skipped += 1;
+            continue;
+        }
+        if !in_place {
+            if let Err(error) = filesystem::transfer(source, destination, settings.relocate) {
+                println!("Failed to import {}: {}", source.display(), error);
+                summary.failed += 1;
+                continue;
+            }
+            // documents keep their metadata beside them
+            if sidecar(source).is_file() {
+                if let Err(error) = filesystem::transfer(&sidecar(source), &sidecar(destination), settings.relocate) {
+                    println!("Failed to import {}: {}", sidecar(source).display(), error);
+                }
+            }
+        }
+        match library.add(&book, destination, &hashes) {
+            Ok(book_id) => {
+                summary.imported += 1;
+                if library.cover(book_id)?.is_none() {
+                    if let Err(error) = covers.store(library, book_id, destination) {
+                        println!("Cannot extract cover, {}", error);
+                    }
+                }
+            }
+            Err(error) => {
+                println!("Failed to record {}: {}", destination.display(), error);
+                summary.failed += 1;
+            }
+        }
+        if settings.relocate && settings.prune && !in_place {
+            if let Some(parent) = source.parent() {
+                filesystem::prune(root, parent);
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Reads the book at `path`, detecting its language from its text when the
+/// metadata gives none and `import.language` is set.
+pub fn read_book(cfg: &Configuration, path: &Path) -> Result<Book, RootsError> {
+    let mut book = Book::new(path)?;
+    if book.language.is_none() && cfg.get_import().detect_language {
+        book.language = Book::text(path).ok().flatten().as_deref().and_then(language::detect);
+    }
+    Ok(book)
+}
+
+/// Hashes `path`, with a content hash as well when `import.hash` is set.
+pub fn hashes(cfg: &Configuration, path: &Path) -> Result<Hashes, RootsError> {
+    let file = filesystem::hash_file(path).map_err(|error| RootsError::io(path, error))?;
+    let content = if cfg.get_import().hash {
+        Book::content_hash(path)?
+    } else {
+        None
+    };
+    Ok(Hashes { file: Some(file), content })
+}
+
+fn same_content(l: &Hashes, r: &Hashes) -> bool {
+    (l.file.is_some() && l.file == r.file) || (l.content.is_some() && l.content == r.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::application::book::date::PartialDate;
+
+    use super::*;
+
+    fn book() -> Book {
+        Book {
+            title: Some("Howards End".to_string()),
+            author: Some(vec!["E. M. Forster".to_string()]),
+            publication_date: PartialDate::parse("1910-10-18"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_layout_is_author_and_title() {
+        let layout = Layout::parse("{author}/{title}.{ext}").unwrap();
+        assert_eq!(
+            vec!["E. M. Forster", "Howards End.epub"],
+            layout.components(&book(), Path::new("/in/he.EPUB"))
+        );
+    }
+
+    #[test]
+    fn empty_components_are_left_out() {
+        let layout = Layout::parse("{author_sort}/{series}/{title} ({year}).{ext}").unwrap();
+        assert_eq!(
+            vec!["Forster, E. M.", "Howards End (1910).epub"],
+            layout.components(&book(), Path::new("/in/he.epub"))
+        );
+    }
+
+    #[test]
+    fn languages_have_their_own_folder() {
+        let layout = Layout::parse("{lang}/{author}/{title}.{ext}").unwrap();
+        let english = Book { language: Some("en-GB".to_string()), ..book() };
+        assert_eq!(
+            vec!["en-GB", "E. M. Forster", "Howards End.epub"],
+            layout.components(&english, Path::new("/in/he.epub"))
+        );
+        assert_eq!(vec!["E. M. Forster", "Howards End.epub"], layout.components(&book(), Path::new("/in/he.epub")));
+    }
+
+    #[test]
+    fn series_have_their_own_folder() {
+        let layout = Layout::parse("{author}/{series}/{series_index} - {title}.{ext}").unwrap();
+        let book = Book { series: Some("Howards End Trilogy".to_string()), series_index: Some(1.0), ..book() };
+        assert_eq!(
+            vec!["E. M. Forster", "Howards End Trilogy", "1 - Howards End.epub"],
+            layout.components(&book, Path::new("/in/he.epub"))
+        );
+    }
+
+    #[test]
+    fn missing_title_uses_file_name() {
+        let layout = Layout::parse("{author}/{title}.{ext}").unwrap();
+        assert_eq!(
+            vec!["Unknown Author", "he.mobi"],
+            layout.components(&Book::default(), Path::new("/in/he.mobi"))
+        );
+    }
+
+    #[test]
+    fn unknown_layout_names_are_rejected() {
+        assert!(Layout::parse("{author}/{colour}.{ext}").is_err());
+        assert!(Layout::parse("/").is_err());
+    }
+}
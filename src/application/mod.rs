@@ -1,3 +1,8 @@
 pub mod command;
 pub mod book;
-pub mod files;
\ No newline at end of file
+pub mod cover;
+pub mod files;
+pub mod import;
+pub mod query;
+pub mod template;
+pub mod update;
@@ -0,0 +1,493 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Fields a query term can filter on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Title,
+    Author,
+    /// Anyone credited in a role other than author.
+    Contributor,
+    Editor,
+    Illustrator,
+    Narrator,
+    Translator,
+    Subject,
+    Series,
+    SeriesIndex,
+    Publisher,
+    Imprint,
+    Description,
+    /// The BCP 47 tag of the book's language.
+    Language,
+    /// The publication date, as precise as it is known.
+    Date,
+    Year,
+    Asin,
+    Isbn,
+    Identifier,
+    Path,
+    Format,
+    Hash,
+    Imported,
+}
+
+impl Field {
+    pub const ALL: [Field; 23] = [
+        Field::Title,
+        Field::Author,
+        Field::Contributor,
+        Field::Editor,
+        Field::Illustrator,
+        Field::Narrator,
+        Field::Translator,
+        Field::Subject,
+        Field::Series,
+        Field::SeriesIndex,
+        Field::Publisher,
+        Field::Imprint,
+        Field::Description,
+        Field::Language,
+        Field::Date,
+        Field::Year,
+        Field::Asin,
+        Field::Isbn,
+        Field::Identifier,
+        Field::Path,
+        Field::Format,
+        Field::Hash,
+        Field::Imported,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Author => "author",
+            Field::Contributor => "contributor",
+            Field::Editor => "editor",
+            Field::Illustrator => "illustrator",
+            Field::Narrator => "narrator",
+            Field::Translator => "translator",
+            Field::Subject => "subject",
+            Field::Series => "series",
+            Field::SeriesIndex => "series_index",
+            Field::Publisher => "publisher",
+            Field::Imprint => "imprint",
+            Field::Description => "description",
+            Field::Language => "lang",
+            Field::Date => "date",
+            Field::Year => "year",
+            Field::Asin => "asin",
+            Field::Isbn => "isbn",
+            Field::Identifier => "identifier",
+            Field::Path => "path",
+            Field::Format => "format",
+            Field::Hash => "hash",
+            Field::Imported => "imported",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Field> {
+        let name = name.to_lowercase();
+        Field::ALL.iter().find(|field| field.name() == name).copied()
+    }
+
+    pub fn kind(&self) -> FieldKind {
+        match self {
+            Field::Author | Field::Subject | Field::Identifier => FieldKind::List,
+            Field::Contributor | Field::Editor | Field::Illustrator | Field::Narrator | Field::Translator => FieldKind::List,
+            Field::Date | Field::Year | Field::Imported => FieldKind::Date,
+            Field::SeriesIndex => FieldKind::Number,
+            Field::Asin | Field::Isbn | Field::Hash => FieldKind::Identifier,
+            _ => FieldKind::Text,
+        }
+    }
+
+    /// The MARC relator code of the contributors a role field holds.
+    pub fn role(&self) -> Option<&'static str> {
+        match self {
+            Field::Editor => Some("edt"),
+            Field::Illustrator => Some("ill"),
+            Field::Narrator => Some("nrt"),
+            Field::Translator => Some("trl"),
+            _ => None,
+        }
+    }
+
+    /// Whether `field:from..to` is meaningful for this field.
+    pub fn supports_ranges(&self) -> bool {
+        matches!(self.kind(), FieldKind::Date | FieldKind::Number)
+    }
+}
+
+/// How a field's values are stored and matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Matched case-insensitively on any part of the value.
+    Text,
+    /// Several values per book, any of which may match.
+    List,
+    /// Matched on a prefix, supports ranges.
+    Date,
+    /// Matched by value, supports ranges.
+    Number,
+    /// Matched exactly, ignoring case and hyphens.
+    Identifier,
+}
+
+impl Display for FieldKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let name = match self {
+            FieldKind::Text => "text",
+            FieldKind::List => "list",
+            FieldKind::Date => "date",
+            FieldKind::Number => "number",
+            FieldKind::Identifier => "identifier",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// Case-insensitive match on part of the field.
+    Text(String),
+    /// An inclusive range, either end may be open.
+    Range(Option<String>, Option<String>),
+    /// The field has any value, written `field:*`.
+    Present,
+}
+
+/// A single `field:value` filter; terms without a field match titles and authors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Term {
+    pub field: Option<Field>,
+    pub value: Value,
+}
+
+/// A parsed library query, for example
+/// `author:forster title:"howards end" -subject:poetry year:1900..1920 isbn:*`.
+///
+/// Terms next to each other must all match, `OR` matches either side, `-` or
+/// `NOT` negates a term and parentheses group.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    All,
+    Term(Term),
+    Not(Box<Query>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct QueryError(pub String);
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, QueryError> {
+        let tokens = tokenise(input)?;
+        if tokens.is_empty() {
+            return Ok(Query::All);
+        }
+        let mut parser = Parser { tokens, position: 0 };
+        let query = parser.or()?;
+        match parser.peek() {
+            None => Ok(query),
+            Some(token) => Err(QueryError(format!("unexpected {}", token))),
+        }
+    }
+
+    /// Parses a query given as command line arguments. The shell has already
+    /// removed quotes, so arguments containing spaces are treated as a single value.
+    pub fn from_args(args: &[String]) -> Result<Query, QueryError> {
+        let input = args.iter()
+            .map(|arg| quote_arg(arg))
+            .collect::<Vec<String>>()
+            .join(" ");
+        Query::parse(&input)
+    }
+}
+
+fn quote_arg(arg: &str) -> String {
+    if !arg.contains(char::is_whitespace) || arg.contains('"') {
+        return arg.to_string();
+    }
+    match arg.split_once(':') {
+        Some((field, value)) if Field::from_name(field.trim_start_matches('-')).is_some() => {
+            format!("{}:\"{}\"", field, value)
+        }
+        _ => format!("\"{}\"", arg),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(bool, Term),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::Term(_, term) => write!(f, "{:?}", term),
+        }
+    }
+}
+
+fn tokenise(input: &str) -> Result<Vec<Token>, QueryError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => tokens.push(word(&mut chars)?),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a keyword or a possibly negated `field:value` term.
+fn word(chars: &mut Peekable<Chars>) -> Result<Token, QueryError> {
+    let negated = chars.next_if_eq(&'-').is_some();
+    let mut field = None;
+    let mut text = String::new();
+    let mut quoted = false;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '"' => {
+                chars.next();
+                quoted = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(QueryError("unterminated quote".to_string())),
+                    }
+                }
+            }
+            ':' if field.is_none() && !quoted => {
+                chars.next();
+                field = Some(std::mem::take(&mut text));
+            }
+            c if c.is_whitespace() || c == '(' || c == ')' => break,
+            c => {
+                chars.next();
+                text.push(c);
+            }
+        }
+    }
+    if !negated && field.is_none() && !quoted {
+        match text.as_str() {
+            "AND" => return Ok(Token::And),
+            "OR" => return Ok(Token::Or),
+            "NOT" => return Ok(Token::Not),
+            _ => {}
+        }
+    }
+    let field = match field {
+        Some(name) => Some(Field::from_name(&name)
+            .ok_or(QueryError(format!("unknown field '{}'", name)))?),
+        None => None,
+    };
+    if text.is_empty() && !quoted {
+        return Err(QueryError(format!("missing value for '{}'", field.map(|f| f.name()).unwrap_or("-"))));
+    }
+    let value = if quoted {
+        Value::Text(text)
+    } else if text == "*" {
+        Value::Present
+    } else if let Some((from, to)) = text.split_once("..") {
+        let bound = |s: &str| if s.is_empty() { None } else { Some(s.to_string()) };
+        match field {
+            Some(f) if f.supports_ranges() => Value::Range(bound(from), bound(to)),
+            Some(f) => return Err(QueryError(format!("'{}' does not support ranges", f))),
+            None => return Err(QueryError("ranges need a field, e.g. year:1900..1920".to_string())),
+        }
+    } else {
+        Value::Text(text)
+    };
+    Ok(Token::Term(negated, Term { field, value }))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut alternatives = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            alternatives.push(self.and()?);
+        }
+        Ok(flatten(alternatives, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut terms = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                    terms.push(self.unary()?);
+                }
+                Some(Token::Or) | Some(Token::Close) | None => break,
+                Some(_) => terms.push(self.unary()?),
+            }
+        }
+        Ok(flatten(terms, Query::And))
+    }
+
+    fn unary(&mut self) -> Result<Query, QueryError> {
+        match self.next() {
+            Some(Token::Not) => Ok(Query::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(QueryError("missing ')'".to_string())),
+                }
+            }
+            Some(Token::Term(true, term)) => Ok(Query::Not(Box::new(Query::Term(term)))),
+            Some(Token::Term(false, term)) => Ok(Query::Term(term)),
+            Some(token) => Err(QueryError(format!("unexpected {}", token))),
+            None => Err(QueryError("query ends unexpectedly".to_string())),
+        }
+    }
+}
+
+fn flatten(mut queries: Vec<Query>, combine: fn(Vec<Query>) -> Query) -> Query {
+    if queries.len() == 1 {
+        queries.remove(0)
+    } else {
+        combine(queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Field, value: &str) -> Query {
+        Query::Term(Term { field: Some(field), value: Value::Text(value.to_string()) })
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(Ok(Query::All), Query::parse("  "));
+    }
+
+    #[test]
+    fn adjacent_terms_are_combined() {
+        let query = Query::parse(r#"author:forster title:"howards end" -subject:poetry year:1900..1920 isbn:*"#);
+        assert_eq!(
+            Ok(Query::And(vec![
+                term(Field::Author, "forster"),
+                term(Field::Title, "howards end"),
+                Query::Not(Box::new(term(Field::Subject, "poetry"))),
+                Query::Term(Term {
+                    field: Some(Field::Year),
+                    value: Value::Range(Some("1900".to_string()), Some("1920".to_string())),
+                }),
+                Query::Term(Term { field: Some(Field::Isbn), value: Value::Present }),
+            ])),
+            query
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let query = Query::parse("author:forster AND title:end OR author:woolf");
+        assert_eq!(
+            Ok(Query::Or(vec![
+                Query::And(vec![term(Field::Author, "forster"), term(Field::Title, "end")]),
+                term(Field::Author, "woolf"),
+            ])),
+            query
+        );
+    }
+
+    #[test]
+    fn parentheses_group() {
+        let query = Query::parse("NOT (author:forster OR author:woolf)");
+        assert_eq!(
+            Ok(Query::Not(Box::new(Query::Or(vec![
+                term(Field::Author, "forster"),
+                term(Field::Author, "woolf"),
+            ])))),
+            query
+        );
+    }
+
+    #[test]
+    fn open_ranges_are_allowed() {
+        let query = Query::parse("year:..1920");
+        assert_eq!(
+            Ok(Query::Term(Term { field: Some(Field::Year), value: Value::Range(None, Some("1920".to_string())) })),
+            query
+        );
+    }
+
+    #[test]
+    fn arguments_with_spaces_are_single_values() {
+        let args = vec!["title:howards end".to_string(), "forster".to_string()];
+        assert_eq!(
+            Ok(Query::And(vec![
+                term(Field::Title, "howards end"),
+                Query::Term(Term { field: None, value: Value::Text("forster".to_string()) }),
+            ])),
+            Query::from_args(&args)
+        );
+    }
+
+    #[test]
+    fn invalid_queries_are_rejected() {
+        assert!(Query::parse("colour:red").is_err());
+        assert!(Query::parse("title:\"open").is_err());
+        assert!(Query::parse("(author:forster").is_err());
+        assert!(Query::parse("title:a..b").is_err());
+        assert!(Query::parse("author:").is_err());
+    }
+}
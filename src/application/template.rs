@@ -0,0 +1,128 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A format string such as `{author} - {title} ({year})`. Names in braces are
+/// replaced by values when rendered, `{{` and `}}` are literal braces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Name(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TemplateError(pub String);
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Template, TemplateError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => literal.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => literal.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError(format!("unclosed '{{{}'", name))),
+                        }
+                    }
+                    let name = name.trim().to_string();
+                    if name.is_empty() {
+                        return Err(TemplateError("empty '{}' in template".to_string()));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Name(name));
+                }
+                '}' => return Err(TemplateError("unmatched '}' in template".to_string())),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// The names used by the template, in order of appearance.
+    pub fn names(&self) -> Vec<&str> {
+        self.parts.iter()
+            .filter_map(|part| match part {
+                Part::Name(name) => Some(name.as_str()),
+                Part::Literal(_) => None,
+            })
+            .collect()
+    }
+
+    /// Fails on the first name `known` does not accept.
+    pub fn validate(&self, known: impl Fn(&str) -> bool) -> Result<(), TemplateError> {
+        match self.names().into_iter().find(|name| !known(name)) {
+            Some(name) => Err(TemplateError(format!("unknown name '{{{}}}' in template", name))),
+            None => Ok(()),
+        }
+    }
+
+    /// Renders the template, names without a value are left empty.
+    pub fn render(&self, value: impl Fn(&str) -> Option<String>) -> String {
+        self.parts.iter()
+            .map(|part| match part {
+                Part::Literal(literal) => literal.clone(),
+                Part::Name(name) => value(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_replaced() {
+        let template = Template::parse("{author} - {title} ({year})").unwrap();
+        let rendered = template.render(|name| match name {
+            "author" => Some("E. M. Forster".to_string()),
+            "title" => Some("Howards End".to_string()),
+            _ => None,
+        });
+        assert_eq!("E. M. Forster - Howards End ()", rendered);
+        assert_eq!(vec!["author", "title", "year"], template.names());
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template = Template::parse("{{{title}}}").unwrap();
+        assert_eq!("{Howards End}", template.render(|_| Some("Howards End".to_string())));
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        assert!(Template::parse("{title").is_err());
+        assert!(Template::parse("title}").is_err());
+        assert!(Template::parse("{}").is_err());
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let template = Template::parse("{title} {colour}").unwrap();
+        assert_eq!(
+            Err(TemplateError("unknown name '{colour}' in template".to_string())),
+            template.validate(|name| name == "title")
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::path::{Path, PathBuf};
+
+use crate::application::book::{book_comparator, find_books, sidecar, supported_extensions, Book};
+use crate::application::cover::CoverCache;
+use crate::application::import::{hashes, read_book};
+use crate::application::query::{Field, Query};
+use crate::configuration::Configuration;
+use crate::database::{Library, Record};
+use crate::error::RootsError;
+use crate::filesystem;
+use crate::internet::metadata;
+
+/// Online matches scoring below this are not offered as corrections.
+pub const MINIMUM_SCORE: usize = 60;
+
+/// Fields compared between the library and other metadata sources.
+const COMPARED: [Field; 12] = [
+    Field::Title,
+    Field::Author,
+    Field::Contributor,
+    Field::Subject,
+    Field::Series,
+    Field::SeriesIndex,
+    Field::Publisher,
+    Field::Description,
+    Field::Language,
+    Field::Date,
+    Field::Asin,
+    Field::Isbn,
+];
+
+/// An inconsistency between the library database and the filesystem, or
+/// between the stored metadata and another source.
+#[derive(Debug)]
+pub enum Finding {
+    /// A library file no longer exists.
+    Missing(PathBuf),
+    /// A file in the library directory the database does not know about.
+    Untracked(PathBuf),
+    /// A missing library file found elsewhere with the same content.
+    Moved(PathBuf, PathBuf),
+    /// A file identical to a library file, and that file.
+    Duplicate(PathBuf, PathBuf),
+    /// Stored metadata differs from the file or an online source.
+    Metadata {
+        book_id: i64,
+        source: String,
+        /// The file the new metadata was read from, none for online sources.
+        path: Option<PathBuf>,
+        changes: Vec<Change>,
+        update: Book,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub field: Field,
+    pub old: String,
+    pub new: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Finding::Missing(path) => write!(f, "missing    {}", path.display()),
+            Finding::Untracked(path) => write!(f, "untracked  {}", path.display()),
+            Finding::Moved(from, to) => write!(f, "moved      {} -> {}", from.display(), to.display()),
+            Finding::Duplicate(path, original) => {
+                write!(f, "duplicate  {} (of {})", path.display(), original.display())
+            }
+            Finding::Metadata { source, changes, .. } => {
+                write!(f, "metadata   differs from {}", source)?;
+                for change in changes {
+                    write!(f, "\n    {}", change)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}: {:?} -> {:?}", self.field, self.old, self.new)
+    }
+}
+
+impl Finding {
+    /// What resolving the finding does, phrased as a question.
+    pub fn action(&self) -> &'static str {
+        match self {
+            Finding::Missing(_) => "Remove from library?",
+            Finding::Untracked(_) => "Add to library?",
+            Finding::Moved(..) => "Update location?",
+            Finding::Duplicate(..) => "Remove duplicate from library? The file is kept.",
+            Finding::Metadata { .. } => "Apply changes?",
+        }
+    }
+}
+
+/// Cross-references the library database with the library directory, and
+/// optionally with online metadata.
+pub fn reconcile(cfg: &Configuration, library: &Library, fetch: bool) -> Result<Vec<Finding>, RootsError> {
+    let records = library.find(&Query::All)?;
+    let tracked = records.iter()
+        .flat_map(|record| record.files.iter().map(|file| file.path.clone()))
+        .collect::<HashSet<PathBuf>>();
+    let mut untracked = filesystem::find_files(&cfg.get_directory(), &supported_extensions())
+        .into_iter()
+        .filter(|path| !tracked.contains(path))
+        .map(|path| {
+            let hash = filesystem::hash_file(&path).ok();
+            (path, hash)
+        })
+        .collect::<Vec<(PathBuf, Option<String>)>>();
+    let mut findings = Vec::new();
+    let mut originals: HashMap<&str, &PathBuf> = HashMap::new();
+    for record in &records {
+        for file in &record.files {
+            if file.path.exists() {
+                if let Some(hash) = file.hash.as_deref() {
+                    match originals.get(hash) {
+                        Some(original) => findings.push(Finding::Duplicate(file.path.clone(), original.to_path_buf())),
+                        None => {
+                            originals.insert(hash, &file.path);
+                        }
+                    }
+                }
+                continue;
+            }
+            let moved = file.hash.as_ref()
+                .and_then(|hash| untracked.iter().position(|(_, h)| h.as_ref() == Some(hash)));
+            match moved {
+                Some(index) => {
+                    let (to, _) = untracked.remove(index);
+                    findings.push(Finding::Moved(file.path.clone(), to));
+                }
+                None => findings.push(Finding::Missing(file.path.clone())),
+            }
+        }
+        if let Some(file) = record.files.iter().find(|file| file.path.exists()) {
+            match Book::new(&file.path) {
+                Ok(stored) => findings.extend(compare(record, &stored, file.path.display().to_string(), Some(&file.path))),
+                Err(error) => println!("Cannot compare metadata, {}", error),
+            }
+        }
+        if fetch {
+            let best = best_match(&record.book).unwrap_or_else(|error| {
+                println!("Cannot search for metadata: {}", error);
+                None
+            });
+            if let Some((score, candidate)) = best {
+                let source = format!("online metadata (score {})", score);
+                if let Some(finding) = compare(record, &candidate, source, None) {
+                    findings.push(finding);
+                }
+            }
+        }
+    }
+    // every file is a candidate for a moved one, as a moved document may
+    // have left its sidecar behind, but only books are reported
+    let books = find_books(&cfg.get_directory()).into_iter().collect::<HashSet<PathBuf>>();
+    findings.extend(untracked.into_iter().filter(|(path, _)| books.contains(path)).map(|(path, hash)| {
+        match hash.as_deref().and_then(|hash| originals.get(hash)) {
+            Some(original) => Finding::Duplicate(path, original.to_path_buf()),
+            None => Finding::Untracked(path),
+        }
+    }));
+    Ok(findings)
+}
+
+/// The online match scoring highest for `book`, if it scores at least
+/// `MINIMUM_SCORE`.
+pub fn best_match(book: &Book) -> Result<Option<(usize, Book)>, RootsError> {
+    Ok(metadata::search(book)?
+        .iter()
+        .map(Book::from)
+        .map(|candidate| (book_comparator(book, &candidate), candidate))
+        .max_by_key(|(score, _)| *score)
+        .filter(|(score, _)| *score >= MINIMUM_SCORE))
+}
+
+fn compare(record: &Record, other: &Book, source: String, path: Option<&Path>) -> Option<Finding> {
+    let (changes, update) = differences(&record.book, other);
+    if changes.is_empty() {
+        None
+    } else {
+        Some(Finding::Metadata { book_id: record.id, source, path: path.map(Path::to_path_buf), changes, update })
+    }
+}
+
+/// Differences between a book and another source, and a book holding only
+/// the changed values; values the source lacks are not treated as changes.
+pub fn differences(book: &Book, other: &Book) -> (Vec<Change>, Book) {
+    let mut update = Book::default();
+    let mut changes = Vec::new();
+    for field in COMPARED.iter().copied() {
+        let (old, new) = (book.values(field), other.values(field));
+        if new.is_empty() || old == new {
+            continue;
+        }
+        // `1910` is the same date as `1910-10-18`, only less precise, as
+        // `en` is the same language as `en-GB`
+        if !old.is_empty() && old.iter().zip(&new).all(|(old, new)| less_precise(field, old, new)) {
+            continue;
+        }
+        match field {
+            Field::Title => update.title = other.title.clone(),
+            Field::Author => {
+                update.author = other.author.clone();
+                update.author_sort = other.author_sort.clone();
+            }
+            Field::Contributor => update.contributors = other.contributors.clone(),
+            Field::Subject => update.subject = other.subject.clone(),
+            Field::Series => update.series = other.series.clone(),
+            Field::SeriesIndex => update.series_index = other.series_index,
+            Field::Publisher => update.publisher = other.publisher.clone(),
+            Field::Description => update.description = other.description.clone(),
+            Field::Language => update.language = other.language.clone(),
+            Field::Date => update.publication_date = other.publication_date,
+            Field::Asin => update.asin = other.asin.clone(),
+            Field::Isbn => update.isbn = other.isbn.clone(),
+            _ => continue,
+        }
+        changes.push(Change { field, old: old.join("; "), new: new.join("; ") });
+    }
+    (changes, update)
+}
+
+/// Whether `new` is the value `old` of `field` at a lower precision.
+fn less_precise(field: Field, old: &str, new: &str) -> bool {
+    match field {
+        Field::Date => old.starts_with(new),
+        Field::Language => {
+            let (old, new) = (old.to_lowercase(), new.to_lowercase());
+            old == new || old.starts_with(&format!("{}-", new))
+        }
+        _ => false,
+    }
+}
+
+/// Applies the resolution offered for `finding` to the library, and with
+/// `write` metadata changes to the book's other files as well.
+pub fn resolve(cfg: &Configuration, library: &mut Library, finding: &Finding, write: bool) -> Result<(), RootsError> {
+    match finding {
+        Finding::Missing(path) => library.remove(path)?,
+        Finding::Untracked(path) => {
+            let book_id = library.add(&read_book(cfg, path)?, path, &hashes(cfg, path)?)?;
+            if library.cover(book_id)?.is_none() {
+                if let Err(error) = CoverCache::from_config(cfg).store(library, book_id, path) {
+                    println!("Cannot extract cover, {}", error);
+                }
+            }
+        }
+        Finding::Moved(from, to) => {
+            library.relocate(from, to)?;
+            // documents keep their metadata beside them
+            if sidecar(from).is_file() && !sidecar(to).exists() {
+                filesystem::transfer(&sidecar(from), &sidecar(to), true).map_err(|error| RootsError::io(&sidecar(from), error))?;
+            }
+        }
+        // the duplicate is only untracked, deleting files is left to the user
+        Finding::Duplicate(path, _) => library.remove(path)?,
+        Finding::Metadata { book_id, path, update, .. } => {
+            library.update(*book_id, update)?;
+            if write {
+                for file in library.files(*book_id)? {
+                    if Some(&file.path) == path.as_ref() || !file.path.exists() {
+                        continue;
+                    }
+                    if Book::write_metadata(&file.path, update)? {
+                        library.rehash(&file.path, &hashes(cfg, &file.path)?)?;
+                        println!("Wrote metadata to {}", file.path.display());
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::application::book::date::PartialDate;
+    use crate::database::Hashes;
+
+    use super::*;
+
+    #[test]
+    fn only_differing_values_are_changes() {
+        let record = Record {
+            id: 7,
+            book: Book {
+                title: Some("Howards End".to_string()),
+                author: Some(vec!["Forster".to_string()]),
+                isbn: Some("9780141182131".to_string()),
+                ..Default::default()
+            },
+            files: Vec::new(),
+        };
+        let other = Book {
+            title: Some("Howards End".to_string()),
+            author: Some(vec!["E. M. Forster".to_string()]),
+            ..Default::default()
+        };
+        match compare(&record, &other, "file".to_string(), None) {
+            Some(Finding::Metadata { book_id, changes, update, .. }) => {
+                assert_eq!(7, book_id);
+                assert_eq!(
+                    vec![Change { field: Field::Author, old: "Forster".to_string(), new: "E. M. Forster".to_string() }],
+                    changes
+                );
+                assert_eq!(Book { author: Some(vec!["E. M. Forster".to_string()]), ..Default::default() }, update);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn less_precise_dates_are_not_changes() {
+        let book = Book { publication_date: PartialDate::parse("1910-10-18"), ..Default::default() };
+        let other = Book { publication_date: PartialDate::parse("1910"), ..Default::default() };
+        assert!(differences(&book, &other).0.is_empty());
+        let (changes, update) = differences(&other, &book);
+        assert_eq!(vec![Change { field: Field::Date, old: "1910".to_string(), new: "1910-10-18".to_string() }], changes);
+        assert_eq!(book.publication_date, update.publication_date);
+    }
+
+    #[test]
+    fn regionless_languages_are_not_changes() {
+        let book = Book { language: Some("en-GB".to_string()), ..Default::default() };
+        let other = Book { language: Some("en".to_string()), ..Default::default() };
+        assert!(differences(&book, &other).0.is_empty());
+        let (changes, update) = differences(&other, &book);
+        assert_eq!(vec![Change { field: Field::Language, old: "en".to_string(), new: "en-GB".to_string() }], changes);
+        assert_eq!(book.language, update.language);
+        let english = Book { language: Some("eng".to_string()), ..Default::default() };
+        assert_eq!(1, differences(&english, &other).0.len());
+    }
+
+    #[test]
+    fn missing_dates_are_filled_in() {
+        let other = Book { publication_date: PartialDate::parse("1910"), ..Default::default() };
+        let (changes, update) = differences(&Book::default(), &other);
+        assert_eq!(vec![Change { field: Field::Date, old: "".to_string(), new: "1910".to_string() }], changes);
+        assert_eq!(other.publication_date, update.publication_date);
+    }
+
+    #[test]
+    fn duplicates_are_untracked_but_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let (original, copy) = (dir.path().join("he.epub"), dir.path().join("he (1).epub"));
+        for path in [&original, &copy] {
+            fs::write(path, "Only connect.").unwrap();
+        }
+        let book = Book { title: Some("Howards End".to_string()), ..Default::default() };
+        let book_id = library.add(&book, &original, &Hashes::default()).unwrap();
+        library.add(&book, &copy, &Hashes::default()).unwrap();
+        resolve(&Configuration::default(), &mut library, &Finding::Duplicate(copy.clone(), original.clone()), false).unwrap();
+        assert!(copy.exists());
+        let files = library.files(book_id).unwrap().into_iter().map(|file| file.path).collect::<Vec<PathBuf>>();
+        assert_eq!(vec![original], files);
+    }
+
+    #[test]
+    fn sidecars_follow_moved_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let (from, to) = (dir.path().join("notes.md"), dir.path().join("moved/notes.md"));
+        fs::write(&from, "# Notes\n").unwrap();
+        fs::write(sidecar(&from), "title: Field Notes\n").unwrap();
+        let book = Book { title: Some("Field Notes".to_string()), ..Default::default() };
+        library.add(&book, &from, &Hashes::default()).unwrap();
+        fs::create_dir(to.parent().unwrap()).unwrap();
+        fs::rename(&from, &to).unwrap();
+        resolve(&Configuration::default(), &mut library, &Finding::Moved(from.clone(), to.clone()), false).unwrap();
+        assert!(!sidecar(&from).exists());
+        assert_eq!("title: Field Notes\n", fs::read_to_string(sidecar(&to)).unwrap());
+    }
+
+    #[test]
+    fn identical_metadata_is_not_a_finding() {
+        let book = Book { title: Some("Howards End".to_string()), ..Default::default() };
+        let record = Record { id: 1, book: book.clone(), files: Vec::new() };
+        assert!(compare(&record, &book, "file".to_string(), None).is_none());
+    }
+}
@@ -4,14 +4,32 @@ use std::{env, path::{Path, PathBuf}};
 use std::collections::HashMap;
 use std::fmt::{Display, Result, Formatter};
 
-use config::{Config, Environment, File};
+use config::{Config, ConfigError, Environment, File};
 use maplit::hashmap;
 use serde::{Deserialize, Serialize};
 
+use crate::error::RootsError;
+
 #[derive(Debug, Serialize, Deserialize)]
-struct List {
-    isbn: bool,
-    table: bool,
+pub struct List {
+    pub isbn: bool,
+    pub table: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cover {
+    /// Where cover images and thumbnails are kept, relative paths are
+    /// resolved against the configuration directory.
+    pub directory: PathBuf,
+    /// Thumbnail size in pixels.
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Update {
+    /// Also write accepted metadata changes into the e-book files.
+    pub write: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,13 +39,21 @@ struct Isbndb {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Import {
-    hash: bool,
+pub struct Import {
+    /// Also hash e-book content without its metadata, to find copies that
+    /// differ only in metadata.
+    pub hash: bool,
     #[serde(rename = "move")]
-    relocate: bool,
-    overwrite: bool,
-    prune: bool,
-    replacements: HashMap<String, String>,
+    pub relocate: bool,
+    pub overwrite: bool,
+    pub prune: bool,
+    /// Detect the language of books whose metadata gives none from their
+    /// text.
+    #[serde(rename = "language")]
+    pub detect_language: bool,
+    /// Layout template for library paths, e.g. `{author}/{title}.{ext}`.
+    pub path: String,
+    pub replacements: HashMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,8 +61,10 @@ pub struct Configuration {
     debug: bool,
     directory: PathBuf,
     library: PathBuf,
+    cover: Cover,
     import: Import,
     list: List,
+    update: Update,
     isbndb: Option<Isbndb>,
     #[serde(skip_serializing)]
     source: String,
@@ -44,13 +72,45 @@ pub struct Configuration {
 
 impl Configuration {
 
-    pub fn new() -> Configuration {
-        load().try_into().unwrap()
+    pub fn new() -> std::result::Result<Configuration, RootsError> {
+        Ok(load()?.try_into()?)
     }
 
     pub fn get_source(self) -> String {
         self.source
     }
+
+    pub fn get_directory(&self) -> PathBuf {
+        expand_home(&self.directory)
+    }
+
+    /// The library database path; relative paths are resolved against the
+    /// configuration directory.
+    pub fn get_library(&self) -> PathBuf {
+        relative_to_config(&self.library)
+    }
+
+    pub fn get_cover(&self) -> &Cover {
+        &self.cover
+    }
+
+    /// The cover directory; relative paths are resolved against the
+    /// configuration directory.
+    pub fn get_cover_directory(&self) -> PathBuf {
+        relative_to_config(&self.cover.directory)
+    }
+
+    pub fn get_import(&self) -> &Import {
+        &self.import
+    }
+
+    pub fn get_list(&self) -> &List {
+        &self.list
+    }
+
+    pub fn get_update(&self) -> &Update {
+        &self.update
+    }
 }
 
 impl Default for Configuration {
@@ -67,9 +127,9 @@ impl Display for Configuration {
 
 fn default_replacements() -> HashMap<String, String> {
     hashmap!{
-        r#"[<>:"\?\*\|/]"#.to_string() => r#"_"#.to_string(),
-        "[\u{00}-\u{1f}]".to_string() => "".to_string(),
-        r#"\.$"#.to_string() => r#"_"#.to_string(),
+        r#"[<>:"\?\*\|/\\]"#.to_string() => r#"_"#.to_string(),
+        r#"[\x00-\x1f]"#.to_string() => r#""#.to_string(),
+        r#"\.\s*$"#.to_string() => r#"_"#.to_string(),
         r#"\s+$"#.to_string() => r#""#.to_string(),
         r#"^\."#.to_string() => r#"_"#.to_string(),
     }
@@ -81,23 +141,29 @@ fn defaults() -> Config {
         .set_default("debug", false).unwrap()
         .set_default("directory", "~/Books".to_string()).unwrap()
         .set_default("library", "library.db".to_string()).unwrap()
+        .set_default("cover.directory", "covers".to_string()).unwrap()
+        .set_default("cover.width", 200).unwrap()
+        .set_default("cover.height", 300).unwrap()
         .set_default("import.hash", false).unwrap()
         .set_default("import.move", false).unwrap()
         .set_default("import.overwrite", false).unwrap()
         .set_default("import.prune", false).unwrap()
+        .set_default("import.language", false).unwrap()
+        .set_default("import.path", "{author}/{title}.{ext}".to_string()).unwrap()
         .set_default("import.replacements", default_replacements()).unwrap()
         .set_default("list.isbn", false).unwrap()
         .set_default("list.table", false).unwrap()
+        .set_default("update.write", false).unwrap()
         .set("source", resolve_source(&user_config_path)).unwrap()
         .to_owned()
 }
 
-fn load() -> Config {
+fn load() -> std::result::Result<Config, ConfigError> {
     let user_config_path = user_config_path();
-    defaults()
-        .merge(File::from(user_config_path.as_path()).required(false)).unwrap()
-        .merge(Environment::with_prefix("ROOTS").separator("_")).unwrap()
-        .to_owned()
+    Ok(defaults()
+        .merge(File::from(user_config_path.as_path()).required(false))?
+        .merge(Environment::with_prefix("ROOTS").separator("_"))?
+        .to_owned())
 }
 
 fn user_config_path() -> PathBuf {
@@ -105,6 +171,28 @@ fn user_config_path() -> PathBuf {
     PathBuf::new().join(&home).join(".config/roots/default")
 }
 
+fn relative_to_config(path: &Path) -> PathBuf {
+    let path = expand_home(path);
+    if path.is_relative() {
+        user_config_path()
+            .parent()
+            .map(|dir| dir.join(&path))
+            .unwrap_or(path)
+    } else {
+        path
+    }
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => {
+            let home = env::var("HOME").unwrap_or("./".to_string());
+            PathBuf::new().join(&home).join(rest)
+        }
+        Err(_) => path.to_path_buf(),
+    }
+}
+
 fn resolve_source(path: &Path) -> String {
     if path.with_extension("yml").is_file() {
         path.with_extension("yml")
@@ -115,3 +203,14 @@ fn resolve_source(path: &Path) -> String {
         "Default configuration".to_string()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_configuration_is_the_default() {
+        let example: serde_json::Value = serde_yaml::from_str(include_str!("../../etc/default.yml")).unwrap();
+        assert_eq!(serde_json::to_value(Configuration::default()).unwrap(), example);
+    }
+}
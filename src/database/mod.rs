@@ -1 +1,751 @@
-pub mod query;
\ No newline at end of file
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row, Transaction};
+
+use crate::application::book::date::PartialDate;
+use crate::application::book::identifier::Identifier;
+use crate::application::book::name::{self, Contributor, Name};
+use crate::application::book::{extension, Book};
+use crate::application::query::{Field, Query};
+
+pub mod query;
+mod schema;
+
+pub struct Library {
+    connection: Connection,
+}
+
+/// A book in the library together with the files that hold it.
+#[derive(Debug)]
+pub struct Record {
+    pub id: i64,
+    pub book: Book,
+    pub files: Vec<FileRecord>,
+}
+
+#[derive(Debug)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub format: String,
+    pub hash: Option<String>,
+    pub content_hash: Option<String>,
+    pub imported_at: String,
+}
+
+/// Digests identifying a file, the content hash ignores its metadata.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Hashes {
+    pub file: Option<String>,
+    pub content: Option<String>,
+}
+
+impl Record {
+    /// The values of `field` for this book, empty when it has none.
+    pub fn values(&self, field: Field) -> Vec<String> {
+        let files = |value: fn(&FileRecord) -> Option<String>| -> Vec<String> {
+            self.files.iter().filter_map(value).collect()
+        };
+        match field {
+            Field::Path => files(|file| Some(file.path.to_string_lossy().to_string())),
+            Field::Format => files(|file| Some(file.format.clone())),
+            Field::Hash => files(|file| file.hash.clone()),
+            Field::Imported => files(|file| Some(file.imported_at.clone())),
+            _ => self.book.values(field),
+        }
+    }
+
+    /// Orders records by `field`, ignoring case, with records lacking a
+    /// value last; authors by their sort names and books in a series by
+    /// their series index.
+    pub fn compare(&self, other: &Record, field: Field) -> Ordering {
+        let text = |record: &Record| Some(record.values(field).join("; ").to_lowercase()).filter(|value| !value.is_empty());
+        let sort = |record: &Record| {
+            let names = record.book.names().into_iter().map(|name| name.sort).collect::<Vec<String>>();
+            Some(names.join("; ").to_lowercase()).filter(|names| !names.is_empty())
+        };
+        let index = || values_last(self.book.series_index, other.book.series_index);
+        match field {
+            Field::Author => values_last(sort(self), sort(other)),
+            Field::SeriesIndex => index(),
+            Field::Series => values_last(text(self), text(other)).then_with(index),
+            _ => values_last(text(self), text(other)),
+        }
+    }
+}
+
+/// Compares two optional values, none after any value.
+fn values_last<T: PartialOrd>(l: Option<T>, r: Option<T>) -> Ordering {
+    match (l, r) {
+        (Some(l), Some(r)) => l.partial_cmp(&r).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+impl Library {
+    /// Opens the library at `path`, creating the database on first use and
+    /// migrating it to the current schema.
+    pub fn open(path: &Path) -> rusqlite::Result<Library> {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let mut connection = Connection::open(path)?;
+        schema::migrate(&mut connection)?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        Ok(Library { connection })
+    }
+
+    /// Opens the library at `path` if it has been created, without creating it.
+    pub fn open_existing(path: &Path) -> rusqlite::Result<Option<Library>> {
+        if path.is_file() {
+            Library::open(path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Records `book`, stored at `path`, replacing any previous entry for that
+    /// file. A book already in the library with the same ISBN, ASIN, or title
+    /// and first author gains the file as another edition, filling in any
+    /// metadata it lacks, rather than becoming a separate entry.
+    pub fn add(&mut self, book: &Book, path: &Path, hashes: &Hashes) -> rusqlite::Result<i64> {
+        let tx = self.connection.transaction()?;
+        remove_file(&tx, path)?;
+        let format = extension(path).unwrap_or_default();
+        let book_id = match edition_of(&tx, book, &format)? {
+            Some(book_id) => {
+                tx.execute(
+                    "UPDATE books SET title = coalesce(title, ?2), publisher = coalesce(publisher, ?3),
+                        publication_date = coalesce(publication_date, ?4), imprint = coalesce(imprint, ?5),
+                        description = coalesce(description, ?6), asin = coalesce(asin, ?7), isbn = coalesce(isbn, ?8),
+                        series_index = CASE WHEN series IS NULL THEN ?10 ELSE series_index END,
+                        series = coalesce(series, ?9), language = coalesce(language, ?11)
+                     WHERE id = ?1",
+                    params![
+                        book_id,
+                        book.title,
+                        book.publisher,
+                        book.publication_date.map(|d| d.to_string()),
+                        book.imprint,
+                        book.description,
+                        book.asin,
+                        book.isbn,
+                        book.series,
+                        book.series_index,
+                        book.language,
+                    ],
+                )?;
+                let credited = tx.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM book_contributors WHERE book_id = ?1)",
+                    params![book_id],
+                    |row| row.get::<_, bool>(0),
+                )?;
+                if !credited {
+                    set_contributors(&tx, book_id, book.contributors.as_deref().unwrap_or_default())?;
+                }
+                add_identifiers(&tx, book_id, &identifiers_of(book))?;
+                book_id
+            }
+            None => {
+                tx.execute(
+                    "INSERT INTO books (title, publisher, publication_date, imprint, description, asin, isbn, series, series_index,
+                        language)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                    params![
+                        book.title,
+                        book.publisher,
+                        book.publication_date.map(|d| d.to_string()),
+                        book.imprint,
+                        book.description,
+                        book.asin,
+                        book.isbn,
+                        book.series,
+                        book.series_index,
+                        book.language,
+                    ],
+                )?;
+                let book_id = tx.last_insert_rowid();
+                set_authors(&tx, book_id, &book.names())?;
+                set_contributors(&tx, book_id, book.contributors.as_deref().unwrap_or_default())?;
+                set_subjects(&tx, book_id, book.subject.as_deref().unwrap_or_default())?;
+                add_identifiers(&tx, book_id, &identifiers_of(book))?;
+                book_id
+            }
+        };
+        tx.execute(
+            "INSERT INTO files (book_id, path, format, hash, content_hash, imported_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![book_id, path.to_string_lossy(), format, hashes.file, hashes.content, Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(book_id)
+    }
+
+    /// The library file with the same contents as `hashes`, an identical file
+    /// is preferred over one whose content alone matches.
+    pub fn find_duplicate(&self, hashes: &Hashes) -> rusqlite::Result<Option<FileRecord>> {
+        self.connection.query_row(
+            "SELECT path, format, hash, content_hash, imported_at FROM files
+             WHERE hash = ?1 OR content_hash = ?2 ORDER BY hash IS ?1 DESC, id LIMIT 1",
+            params![hashes.file, hashes.content],
+            file_from_row,
+        ).optional()
+    }
+
+    /// Overwrites the stored metadata of a book with the fields set in `changes`.
+    pub fn update(&mut self, book_id: i64, changes: &Book) -> rusqlite::Result<()> {
+        let tx = self.connection.transaction()?;
+        tx.execute(
+            "UPDATE books SET title = coalesce(?2, title), publisher = coalesce(?3, publisher),
+                publication_date = coalesce(?4, publication_date), imprint = coalesce(?5, imprint),
+                description = coalesce(?6, description), asin = coalesce(?7, asin), isbn = coalesce(?8, isbn),
+                series = coalesce(?9, series), series_index = coalesce(?10, series_index),
+                language = coalesce(?11, language)
+             WHERE id = ?1",
+            params![
+                book_id,
+                changes.title,
+                changes.publisher,
+                changes.publication_date.map(|d| d.to_string()),
+                changes.imprint,
+                changes.description,
+                changes.asin,
+                changes.isbn,
+                changes.series,
+                changes.series_index,
+                changes.language,
+            ],
+        )?;
+        if changes.author.is_some() {
+            set_authors(&tx, book_id, &changes.names())?;
+        }
+        if let Some(contributors) = changes.contributors.as_ref() {
+            set_contributors(&tx, book_id, contributors)?;
+        }
+        if let Some(subjects) = changes.subject.as_ref() {
+            set_subjects(&tx, book_id, subjects)?;
+        }
+        if changes.identifiers.is_some() {
+            tx.execute("DELETE FROM identifiers WHERE book_id = ?1", params![book_id])?;
+        }
+        add_identifiers(&tx, book_id, &identifiers_of(changes))?;
+        tx.commit()
+    }
+
+    /// Forgets the file at `path`, and its book if it has no other files.
+    pub fn remove(&mut self, path: &Path) -> rusqlite::Result<()> {
+        let tx = self.connection.transaction()?;
+        remove_file(&tx, path)?;
+        tx.commit()
+    }
+
+    /// Records that the file at `from` is now at `to`.
+    pub fn relocate(&mut self, from: &Path, to: &Path) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "UPDATE files SET path = ?2 WHERE path = ?1",
+            params![from.to_string_lossy(), to.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    /// The file name of the cached cover of the book with `book_id`.
+    pub fn cover(&self, book_id: i64) -> rusqlite::Result<Option<String>> {
+        self.connection.query_row("SELECT cover FROM books WHERE id = ?1", params![book_id], |row| row.get(0))
+            .optional()
+            .map(Option::flatten)
+    }
+
+    pub fn set_cover(&mut self, book_id: i64, cover: Option<&str>) -> rusqlite::Result<()> {
+        self.connection.execute("UPDATE books SET cover = ?2 WHERE id = ?1", params![book_id, cover])?;
+        Ok(())
+    }
+
+    /// Records new hashes for the file at `path`, after its metadata was
+    /// rewritten; a missing content hash keeps the stored one.
+    pub fn rehash(&mut self, path: &Path, hashes: &Hashes) -> rusqlite::Result<()> {
+        self.connection.execute(
+            "UPDATE files SET hash = ?2, content_hash = COALESCE(?3, content_hash) WHERE path = ?1",
+            params![path.to_string_lossy(), hashes.file, hashes.content],
+        )?;
+        Ok(())
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Finds the books matching `query`, ordered by title.
+    pub fn find(&self, query: &Query) -> rusqlite::Result<Vec<Record>> {
+        let filter = query::compile(query);
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT b.id, b.title, b.publisher, b.publication_date, b.imprint, b.description, b.asin, b.isbn,
+                b.series, b.series_index, b.language
+             FROM books b WHERE {} ORDER BY b.title COLLATE NOCASE, b.id",
+            filter.clause
+        ))?;
+        let rows = statement.query_map(params_from_iter(filter.params.iter()), |row| {
+            Ok((row.get::<_, i64>(0)?, book_from_row(row)?))
+        })?;
+        rows.map(|row| {
+            let (id, mut book) = row?;
+            let authors = self.authors(id)?;
+            if !authors.is_empty() {
+                book.author = Some(authors.iter().map(|name| name.display.clone()).collect());
+                book.author_sort = Some(authors.into_iter().map(|name| name.sort).collect());
+            }
+            book.contributors = Some(self.contributors(id)?).filter(|contributors| !contributors.is_empty());
+            book.subject = Some(self.names(
+                "SELECT s.name FROM book_subjects bs JOIN subjects s ON s.id = bs.subject_id
+                 WHERE bs.book_id = ?1 ORDER BY s.name",
+                id,
+            )?).filter(|names| !names.is_empty());
+            book.identifiers = Some(self.identifiers(id)?).filter(|identifiers| !identifiers.is_empty());
+            Ok(Record { id, book, files: self.files(id)? })
+        }).collect()
+    }
+
+    fn authors(&self, book_id: i64) -> rusqlite::Result<Vec<Name>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT a.name, a.sort_name FROM book_authors ba JOIN authors a ON a.id = ba.author_id
+             WHERE ba.book_id = ?1 ORDER BY ba.position",
+        )?;
+        let rows = statement.query_map(params![book_id], |row| Ok(Name { display: row.get(0)?, sort: row.get(1)? }))?;
+        rows.collect()
+    }
+
+    fn contributors(&self, book_id: i64) -> rusqlite::Result<Vec<Contributor>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT a.name, bc.role FROM book_contributors bc JOIN authors a ON a.id = bc.author_id
+             WHERE bc.book_id = ?1 ORDER BY bc.position",
+        )?;
+        let rows = statement.query_map(params![book_id], |row| Ok(Contributor { name: row.get(0)?, role: row.get(1)? }))?;
+        rows.collect()
+    }
+
+    fn names(&self, sql: &str, book_id: i64) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self.connection.prepare_cached(sql)?;
+        let rows = statement.query_map(params![book_id], |row| row.get::<_, String>(0))?;
+        rows.collect()
+    }
+
+    fn identifiers(&self, book_id: i64) -> rusqlite::Result<Vec<Identifier>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT scheme, value FROM identifiers WHERE book_id = ?1 ORDER BY rowid",
+        )?;
+        let rows = statement.query_map(params![book_id], |row| {
+            Ok(Identifier { scheme: row.get(0)?, value: row.get(1)? })
+        })?;
+        rows.collect()
+    }
+
+    /// Makes `alias` another name of the author `name`. Books credited to
+    /// `alias` are credited to `name` instead, as are books imported under
+    /// `alias` from now on.
+    pub fn add_alias(&mut self, alias: &str, name: &str) -> rusqlite::Result<()> {
+        let tx = self.connection.transaction()?;
+        let (alias, name) = (Name::parse(alias), Name::parse(name));
+        let author_id = author_id(&tx, &name)?;
+        let merged = tx.query_row("SELECT id FROM authors WHERE key = ?1", params![alias.key()], |row| row.get::<_, i64>(0))
+            .optional()?
+            .filter(|id| *id != author_id);
+        if let Some(merged) = merged {
+            tx.execute("UPDATE OR IGNORE book_authors SET author_id = ?2 WHERE author_id = ?1", params![merged, author_id])?;
+            tx.execute("DELETE FROM book_authors WHERE author_id = ?1", params![merged])?;
+            tx.execute("UPDATE OR IGNORE book_contributors SET author_id = ?2 WHERE author_id = ?1", params![merged, author_id])?;
+            tx.execute("DELETE FROM book_contributors WHERE author_id = ?1", params![merged])?;
+            tx.execute("UPDATE author_aliases SET author_id = ?2 WHERE author_id = ?1", params![merged, author_id])?;
+            tx.execute("DELETE FROM authors WHERE id = ?1", params![merged])?;
+        }
+        if alias.key() != name.key() {
+            tx.execute(
+                "INSERT OR REPLACE INTO author_aliases (key, alias, author_id) VALUES (?1, ?2, ?3)",
+                params![alias.key(), alias.display, author_id],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Forgets the alias `alias`, true when there was one. Books merged when
+    /// it was added stay with their author.
+    pub fn remove_alias(&mut self, alias: &str) -> rusqlite::Result<bool> {
+        let removed = self.connection.execute("DELETE FROM author_aliases WHERE key = ?1", params![name::key(alias)])?;
+        Ok(removed > 0)
+    }
+
+    /// Every alias with the name of its author, ordered by author.
+    pub fn aliases(&self) -> rusqlite::Result<Vec<(String, String)>> {
+        let mut statement = self.connection.prepare(
+            "SELECT al.alias, a.name FROM author_aliases al JOIN authors a ON a.id = al.author_id
+             ORDER BY a.sort_name COLLATE NOCASE, al.alias COLLATE NOCASE",
+        )?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect()
+    }
+
+    /// The files of the book with `book_id`.
+    pub fn files(&self, book_id: i64) -> rusqlite::Result<Vec<FileRecord>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT path, format, hash, content_hash, imported_at FROM files WHERE book_id = ?1 ORDER BY id",
+        )?;
+        let rows = statement.query_map(params![book_id], file_from_row)?;
+        rows.collect()
+    }
+}
+
+fn book_from_row(row: &Row) -> rusqlite::Result<Book> {
+    Ok(Book {
+        title: row.get(1)?,
+        author: None,
+        author_sort: None,
+        contributors: None,
+        publisher: row.get(2)?,
+        publication_date: row.get::<_, Option<String>>(3)?.and_then(|date| PartialDate::parse(&date)),
+        imprint: row.get(4)?,
+        description: row.get(5)?,
+        language: row.get(10)?,
+        subject: None,
+        series: row.get(8)?,
+        series_index: row.get(9)?,
+        asin: row.get(6)?,
+        isbn: row.get(7)?,
+        identifiers: None,
+    })
+}
+
+fn file_from_row(row: &Row) -> rusqlite::Result<FileRecord> {
+    Ok(FileRecord {
+        path: PathBuf::from(row.get::<_, String>(0)?),
+        format: row.get(1)?,
+        hash: row.get(2)?,
+        content_hash: row.get(3)?,
+        imported_at: row.get(4)?,
+    })
+}
+
+/// The book `book` is another edition of, matched by ISBN, ASIN, another
+/// identifier, or title and first author when it has no file in `format`
+/// yet: two EPUBs of one title are different editions.
+fn edition_of(tx: &Transaction, book: &Book, format: &str) -> rusqlite::Result<Option<i64>> {
+    for identifier in identifiers_of(book).iter().filter(|i| matches!(i.scheme.as_str(), "isbn" | "asin" | "doi")) {
+        let book_id = tx.query_row(
+            "SELECT book_id FROM identifiers WHERE scheme = ?1 AND value = ?2 ORDER BY book_id LIMIT 1",
+            params![identifier.scheme, identifier.value],
+            |row| row.get(0),
+        ).optional()?;
+        if book_id.is_some() {
+            return Ok(book_id);
+        }
+    }
+    let isbn = book.isbn.as_ref().map(|isbn| isbn.replace('-', ""));
+    let asin = book.asin.as_ref().map(|asin| asin.to_lowercase());
+    let author = match book.names().first() {
+        Some(name) => find_author(tx, &name.key())?,
+        None => None,
+    };
+    tx.query_row(
+        "SELECT b.id FROM books b
+         WHERE replace(b.isbn, '-', '') = ?1
+            OR lower(b.asin) = ?2
+            OR (b.title = ?3 COLLATE NOCASE AND EXISTS (
+                SELECT 1 FROM book_authors ba
+                WHERE ba.book_id = b.id AND ba.position = 0 AND ba.author_id = ?4)
+                AND NOT EXISTS (SELECT 1 FROM files f WHERE f.book_id = b.id AND f.format = ?5))
+         ORDER BY b.id LIMIT 1",
+        params![isbn, asin, book.title, author, format],
+        |row| row.get(0),
+    ).optional()
+}
+
+/// The identifiers of `book`, including its ISBN and ASIN.
+fn identifiers_of(book: &Book) -> Vec<Identifier> {
+    let mut identifiers = book.identifiers.clone().unwrap_or_default();
+    let known = [("isbn", &book.isbn), ("asin", &book.asin)];
+    for (scheme, value) in known.iter() {
+        if let Some(value) = value {
+            let identifier = Identifier::new(scheme, value);
+            if !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+    }
+    identifiers
+}
+
+fn add_identifiers(tx: &Transaction, book_id: i64, identifiers: &[Identifier]) -> rusqlite::Result<()> {
+    for identifier in identifiers {
+        tx.execute(
+            "INSERT OR IGNORE INTO identifiers (book_id, scheme, value) VALUES (?1, ?2, ?3)",
+            params![book_id, identifier.scheme, identifier.value],
+        )?;
+    }
+    Ok(())
+}
+
+fn set_authors(tx: &Transaction, book_id: i64, authors: &[Name]) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM book_authors WHERE book_id = ?1", params![book_id])?;
+    for (position, name) in authors.iter().enumerate() {
+        tx.execute(
+            "INSERT OR IGNORE INTO book_authors (book_id, author_id, position) VALUES (?1, ?2, ?3)",
+            params![book_id, author_id(tx, name)?, position as i64],
+        )?;
+    }
+    Ok(())
+}
+
+fn set_contributors(tx: &Transaction, book_id: i64, contributors: &[Contributor]) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM book_contributors WHERE book_id = ?1", params![book_id])?;
+    for (position, contributor) in contributors.iter().enumerate() {
+        tx.execute(
+            "INSERT OR IGNORE INTO book_contributors (book_id, author_id, role, position) VALUES (?1, ?2, ?3, ?4)",
+            params![book_id, author_id(tx, &Name::parse(&contributor.name))?, contributor.role, position as i64],
+        )?;
+    }
+    Ok(())
+}
+
+/// The author `name` is an alias or spelling of, added when there is none.
+fn author_id(tx: &Transaction, name: &Name) -> rusqlite::Result<i64> {
+    if let Some(author_id) = find_author(tx, &name.key())? {
+        return Ok(author_id);
+    }
+    tx.execute(
+        "INSERT INTO authors (name, sort_name, key) VALUES (?1, ?2, ?3)",
+        params![name.display, name.sort, name.key()],
+    )?;
+    Ok(tx.last_insert_rowid())
+}
+
+/// The author with the name `key`, or the alias `key`.
+fn find_author(tx: &Transaction, key: &str) -> rusqlite::Result<Option<i64>> {
+    let alias = tx.query_row("SELECT author_id FROM author_aliases WHERE key = ?1", params![key], |row| row.get(0))
+        .optional()?;
+    match alias {
+        Some(author_id) => Ok(Some(author_id)),
+        None => tx.query_row("SELECT id FROM authors WHERE key = ?1", params![key], |row| row.get(0)).optional(),
+    }
+}
+
+fn set_subjects(tx: &Transaction, book_id: i64, subjects: &[String]) -> rusqlite::Result<()> {
+    tx.execute("DELETE FROM book_subjects WHERE book_id = ?1", params![book_id])?;
+    for name in subjects {
+        tx.execute("INSERT OR IGNORE INTO subjects (name) VALUES (?1)", params![name])?;
+        tx.execute(
+            "INSERT OR IGNORE INTO book_subjects (book_id, subject_id)
+             SELECT ?1, id FROM subjects WHERE name = ?2",
+            params![book_id, name],
+        )?;
+    }
+    Ok(())
+}
+
+/// Removes the entry for the file at `path`, and its book if no other file refers to it.
+fn remove_file(tx: &Transaction, path: &Path) -> rusqlite::Result<()> {
+    let path = path.to_string_lossy();
+    tx.execute(
+        "DELETE FROM books WHERE id IN (SELECT book_id FROM files WHERE path = ?1)
+            AND (SELECT count(*) FROM files WHERE book_id = books.id) = 1",
+        params![path],
+    )?;
+    tx.execute("DELETE FROM files WHERE path = ?1", params![path])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(title: &str) -> Book {
+        Book {
+            title: Some(title.to_string()),
+            author: Some(vec!["E. M. Forster".to_string()]),
+            subject: Some(vec!["Fiction".to_string()]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn library_is_created_on_first_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/library.db");
+        assert!(Library::open_existing(&path).unwrap().is_none());
+        Library::open(&path).unwrap();
+        assert!(Library::open_existing(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn reimporting_a_file_replaces_its_book() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        library.add(&book("A Room with a View"), Path::new("/b/rv.epub"), &Hashes::default()).unwrap();
+        let titles = library.find(&Query::All).unwrap()
+            .into_iter()
+            .filter_map(|record| record.book.title)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["A Room with a View", "Howards End"], titles);
+    }
+
+    #[test]
+    fn update_only_changes_given_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let id = library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        let changes = Book {
+            isbn: Some("9780141182131".to_string()),
+            author: Some(vec!["Forster, Edward Morgan".to_string()]),
+            ..Default::default()
+        };
+        library.update(id, &changes).unwrap();
+        let records = library.find(&Query::All).unwrap();
+        assert_eq!(Some("Howards End".to_string()), records[0].book.title);
+        assert_eq!(Some("9780141182131".to_string()), records[0].book.isbn);
+        assert_eq!(Some(vec!["Edward Morgan Forster".to_string()]), records[0].book.author);
+        assert_eq!(Some(vec!["Forster, Edward Morgan".to_string()]), records[0].book.author_sort);
+    }
+
+    #[test]
+    fn spellings_and_aliases_resolve_to_one_author() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let spellings = ["E.M. Forster", "Forster, E. M.", "Edward Morgan Forster"];
+        for (index, author) in spellings.iter().enumerate() {
+            let mut book = book(&format!("Book {}", index));
+            book.author = Some(vec![author.to_string()]);
+            library.add(&book, Path::new(&format!("/b/{}.epub", index)), &Hashes::default()).unwrap();
+        }
+        library.add_alias("Edward Morgan Forster", "E. M. Forster").unwrap();
+        let mut book = book("A Passage to India");
+        book.author = Some(vec!["Edward Morgan Forster".to_string()]);
+        library.add(&book, Path::new("/b/passage.epub"), &Hashes::default()).unwrap();
+        let records = library.find(&Query::All).unwrap();
+        assert_eq!(4, records.len());
+        for record in &records {
+            assert_eq!(Some(vec!["E.M. Forster".to_string()]), record.book.author);
+            assert_eq!(Some(vec!["Forster, E.M.".to_string()]), record.book.author_sort);
+        }
+        assert_eq!(vec![("Edward Morgan Forster".to_string(), "E.M. Forster".to_string())], library.aliases().unwrap());
+        assert!(library.remove_alias("edward morgan forster").unwrap());
+        assert!(library.aliases().unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_filters_by_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        library.add(&book("A Room with a View"), Path::new("/b/rv.mobi"), &Hashes::default()).unwrap();
+        let records = library.find(&Query::parse("author:forster -format:mobi").unwrap()).unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!(Some("Howards End".to_string()), records[0].book.title);
+        assert_eq!(Some(vec!["E. M. Forster".to_string()]), records[0].book.author);
+        assert_eq!(PathBuf::from("/b/he.epub"), records[0].files[0].path);
+    }
+
+    #[test]
+    fn other_formats_are_editions_of_one_book() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let epub = library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        let isbn = Book { isbn: Some("9780141182131".to_string()), ..book("Howards End") };
+        let mobi = library.add(&isbn, Path::new("/b/he.mobi"), &Hashes::default()).unwrap();
+        assert_eq!(epub, mobi);
+        let records = library.find(&Query::All).unwrap();
+        assert_eq!(1, records.len());
+        assert_eq!(vec!["epub", "mobi"], records[0].values(Field::Format));
+        assert_eq!(Some("9780141182131".to_string()), records[0].book.isbn);
+    }
+
+    #[test]
+    fn files_of_one_format_are_different_editions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let first = library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        let second = library.add(&book("Howards End"), Path::new("/c/he.epub"), &Hashes::default()).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(2, library.find(&Query::All).unwrap().len());
+    }
+
+    #[test]
+    fn series_are_stored_and_ordered() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let volumes = [("The Towers of Silence", 3.0), ("The Jewel in the Crown", 1.0), ("A Division of the Spoils", 4.0)];
+        for (title, index) in volumes {
+            let book = Book { series: Some("The Raj Quartet".to_string()), series_index: Some(index), ..book(title) };
+            library.add(&book, &Path::new("/b").join(title), &Hashes::default()).unwrap();
+        }
+        library.add(&book("Howards End"), Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        let mut records = library.find(&Query::parse("series:quartet").unwrap()).unwrap();
+        assert_eq!(3, records.len());
+        records.extend(library.find(&Query::parse("-series:*").unwrap()).unwrap());
+        records.sort_by(|l, r| l.compare(r, Field::Series));
+        let titles = records.iter().map(|record| record.values(Field::Title).join("")).collect::<Vec<String>>();
+        assert_eq!(
+            vec!["The Jewel in the Crown", "The Towers of Silence", "A Division of the Spoils", "Howards End"],
+            titles
+        );
+        assert_eq!(vec!["3"], records[1].values(Field::SeriesIndex));
+    }
+
+    #[test]
+    fn contributors_are_stored_by_role() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let mut war = book("War and Peace");
+        war.author = Some(vec!["Leo Tolstoy".to_string()]);
+        war.contributors = Some(vec![Contributor::new("Garnett, Constance", "trl"), Contributor::new("Ann Helper", "edt")]);
+        library.add(&war, Path::new("/b/war.epub"), &Hashes::default()).unwrap();
+        let mut anna = book("Anna Karenina");
+        anna.author = Some(vec!["Leo Tolstoy".to_string()]);
+        anna.contributors = Some(vec![Contributor::new("Constance Garnett", "edt")]);
+        library.add(&anna, Path::new("/b/anna.epub"), &Hashes::default()).unwrap();
+        let titles = |query: &str| library.find(&Query::parse(query).unwrap()).unwrap()
+            .into_iter()
+            .filter_map(|record| record.book.title)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["War and Peace"], titles("translator:\"constance garnett\""));
+        assert_eq!(vec!["Anna Karenina", "War and Peace"], titles("contributor:garnett"));
+        assert!(titles("author:garnett").is_empty());
+        let records = library.find(&Query::parse("title:war").unwrap()).unwrap();
+        assert_eq!(vec!["Constance Garnett"], records[0].values(Field::Translator));
+        assert_eq!(vec!["Ann Helper"], records[0].values(Field::Editor));
+    }
+
+    #[test]
+    fn duplicates_are_found_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let hashes = Hashes { file: Some("aa".to_string()), content: Some("cc".to_string()) };
+        library.add(&book("Howards End"), Path::new("/b/he.epub"), &hashes).unwrap();
+        let same_content = Hashes { file: Some("bb".to_string()), content: Some("cc".to_string()) };
+        let duplicate = library.find_duplicate(&same_content).unwrap().unwrap();
+        assert_eq!(PathBuf::from("/b/he.epub"), duplicate.path);
+        assert_ne!(same_content.file, duplicate.hash);
+        assert!(library.find_duplicate(&Hashes { file: Some("dd".to_string()), content: None }).unwrap().is_none());
+    }
+
+    #[test]
+    fn identifiers_are_stored_and_queried() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let he = Book {
+            isbn: Some("9780141182131".to_string()),
+            identifiers: Some(vec![Identifier::new("doi", "10.1000/182")]),
+            ..book("Howards End")
+        };
+        library.add(&he, Path::new("/b/he.epub"), &Hashes::default()).unwrap();
+        let records = library.find(&Query::parse("identifier:doi:10.1000").unwrap()).unwrap();
+        assert_eq!(
+            vec!["doi:10.1000/182", "isbn:9780141182131"],
+            records[0].values(Field::Identifier)
+        );
+        let print = Book { identifiers: Some(vec![Identifier::new("isbn", "014118213X")]), ..book("Howards End (Penguin)") };
+        library.add(&print, Path::new("/b/he.mobi"), &Hashes::default()).unwrap();
+        assert_eq!(1, library.find(&Query::All).unwrap().len());
+    }
+}
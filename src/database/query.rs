@@ -1,10 +1,338 @@
-pub fn list_fields() -> Vec<String> {
-    let available_fields = Vec::new();
-    return available_fields;
+use std::path::Path;
+
+use rusqlite::types::ValueRef;
+use rusqlite::Connection;
+
+use crate::application::book::language;
+use crate::application::query::{Field, FieldKind, Query, Term, Value};
+use crate::database::Library;
+
+/// How a queryable field is used across the library.
+#[derive(Debug)]
+pub struct FieldSummary {
+    pub field: Field,
+    /// The number of books with a value for the field.
+    pub count: usize,
+    /// The most common values.
+    pub examples: Vec<String>,
+}
+
+pub fn list_fields(library: &Path) -> Vec<FieldSummary> {
+    match Library::open_existing(library) {
+        Ok(Some(library)) => Field::ALL.iter()
+            .filter_map(|&field| summarise(library.connection(), field).ok())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn summarise(connection: &Connection, field: Field) -> rusqlite::Result<FieldSummary> {
+    let mut params = Vec::new();
+    let present = compile_field(field, &Value::Present, &mut params);
+    let count = connection.query_row(
+        &format!("SELECT count(*) FROM books b WHERE {}", present),
+        [],
+        |row| row.get::<_, i64>(0),
+    )? as usize;
+    let (column, join) = column(field);
+    let sql = match join {
+        Some((tables, link)) => format!(
+            "SELECT {c} FROM books b, {} WHERE {} AND {c} IS NOT NULL AND {c} != ''
+             GROUP BY {c} ORDER BY count(*) DESC, {c} LIMIT 2",
+            tables, link, c = column
+        ),
+        None => format!(
+            "SELECT {c} FROM books b WHERE {c} IS NOT NULL AND {c} != ''
+             GROUP BY {c} ORDER BY count(*) DESC, {c} LIMIT 2",
+            c = column
+        ),
+    };
+    let mut statement = connection.prepare(&sql)?;
+    let examples = statement.query_map([], |row| match row.get_ref(0)? {
+            // series indexes are stored as numbers
+            ValueRef::Real(number) => Ok(number.to_string()),
+            _ => row.get::<_, String>(0),
+        })?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(FieldSummary { field, count, examples })
+}
+
+/// A query compiled to a `WHERE` clause over `books b`, with its parameters.
+#[derive(Debug, PartialEq)]
+pub struct Filter {
+    pub clause: String,
+    pub params: Vec<String>,
+}
+
+pub fn compile(query: &Query) -> Filter {
+    let mut params = Vec::new();
+    let clause = compile_query(query, &mut params);
+    Filter { clause, params }
+}
+
+fn compile_query(query: &Query, params: &mut Vec<String>) -> String {
+    match query {
+        Query::All => "1".to_string(),
+        Query::Term(term) => compile_term(term, params),
+        // a term on an unset column is NULL rather than false, and books
+        // without a publisher are still not published by Penguin
+        Query::Not(query) => format!("NOT coalesce(({}), 0)", compile_query(query, params)),
+        Query::And(queries) => join(queries, " AND ", params),
+        Query::Or(queries) => join(queries, " OR ", params),
+    }
+}
+
+fn join(queries: &[Query], operator: &str, params: &mut Vec<String>) -> String {
+    queries.iter()
+        .map(|query| format!("({})", compile_query(query, params)))
+        .collect::<Vec<String>>()
+        .join(operator)
+}
+
+fn compile_term(term: &Term, params: &mut Vec<String>) -> String {
+    match term.field {
+        Some(field) => compile_field(field, &term.value, params),
+        None => {
+            let title = compile_field(Field::Title, &term.value, params);
+            let author = compile_field(Field::Author, &term.value, params);
+            format!("({}) OR ({})", title, author)
+        }
+    }
+}
+
+/// The column a field is stored in and, for fields held in other tables,
+/// those tables with the condition linking them to `books b`.
+fn column(field: Field) -> (&'static str, Option<(&'static str, &'static str)>) {
+    const AUTHORS: (&str, &str) = ("book_authors ba JOIN authors a ON a.id = ba.author_id", "ba.book_id = b.id");
+    const CONTRIBUTORS: &str = "book_contributors bc JOIN authors c ON c.id = bc.author_id";
+    const SUBJECTS: (&str, &str) = ("book_subjects bs JOIN subjects s ON s.id = bs.subject_id", "bs.book_id = b.id");
+    const FILES: (&str, &str) = ("files f", "f.book_id = b.id");
+    const IDENTIFIERS: (&str, &str) = ("identifiers i", "i.book_id = b.id");
+    match field {
+        Field::Title => ("b.title", None),
+        Field::Author => ("a.name", Some(AUTHORS)),
+        Field::Contributor => ("c.name", Some((CONTRIBUTORS, "bc.book_id = b.id"))),
+        Field::Editor => ("c.name", Some((CONTRIBUTORS, "bc.book_id = b.id AND bc.role = 'edt'"))),
+        Field::Illustrator => ("c.name", Some((CONTRIBUTORS, "bc.book_id = b.id AND bc.role = 'ill'"))),
+        Field::Narrator => ("c.name", Some((CONTRIBUTORS, "bc.book_id = b.id AND bc.role = 'nrt'"))),
+        Field::Translator => ("c.name", Some((CONTRIBUTORS, "bc.book_id = b.id AND bc.role = 'trl'"))),
+        Field::Subject => ("s.name", Some(SUBJECTS)),
+        Field::Series => ("b.series", None),
+        Field::SeriesIndex => ("b.series_index", None),
+        Field::Publisher => ("b.publisher", None),
+        Field::Imprint => ("b.imprint", None),
+        Field::Description => ("b.description", None),
+        Field::Language => ("b.language", None),
+        Field::Date => ("b.publication_date", None),
+        Field::Year => ("substr(b.publication_date, 1, 4)", None),
+        Field::Asin => ("b.asin", None),
+        Field::Isbn => ("replace(b.isbn, '-', '')", None),
+        Field::Identifier => ("i.scheme || ':' || i.value", Some(IDENTIFIERS)),
+        Field::Path => ("f.path", Some(FILES)),
+        Field::Format => ("f.format", Some(FILES)),
+        Field::Hash => ("f.hash", Some(FILES)),
+        Field::Imported => ("substr(f.imported_at, 1, 10)", Some(FILES)),
+    }
+}
+
+fn compile_field(field: Field, value: &Value, params: &mut Vec<String>) -> String {
+    let (column, join) = column(field);
+    let condition = match value {
+        Value::Present => format!("{c} IS NOT NULL AND {c} != ''", c = column),
+        Value::Text(text) => match field {
+            Field::Asin | Field::Isbn | Field::Hash | Field::Format | Field::Year => {
+                params.push(text.replace('-', "").to_lowercase());
+                format!("lower({}) = ?", column)
+            }
+            // dates are stored as ISO 8601 to their precision
+            Field::Date => {
+                params.push(format!("{}%", escape_like(text)));
+                format!("{} LIKE ? ESCAPE '\\'", column)
+            }
+            // `lang:en` matches `en-GB` too, names and codes are read as tags
+            Field::Language => {
+                let tag = language::normalise(text).unwrap_or_else(|| text.clone()).to_lowercase();
+                params.push(tag.clone());
+                params.push(format!("{}-%", escape_like(&tag)));
+                format!("(lower({c}) = ? OR lower({c}) LIKE ? ESCAPE '\\')", c = column)
+            }
+            // the column's affinity makes SQLite compare the text as a number
+            _ if field.kind() == FieldKind::Number => {
+                params.push(text.clone());
+                format!("{} = ?", column)
+            }
+            _ => {
+                params.push(format!("%{}%", escape_like(text)));
+                format!("{} LIKE ? ESCAPE '\\'", column)
+            }
+        },
+        Value::Range(from, to) if field.kind() == FieldKind::Number => {
+            let mut bounds = vec![format!("{} IS NOT NULL", column)];
+            if let Some(from) = from {
+                params.push(from.clone());
+                bounds.push(format!("{} >= ?", column));
+            }
+            if let Some(to) = to {
+                params.push(to.clone());
+                bounds.push(format!("{} <= ?", column));
+            }
+            bounds.join(" AND ")
+        }
+        Value::Range(from, to) => {
+            let mut bounds = Vec::new();
+            if let Some(from) = from {
+                params.push(from.clone());
+                params.push(from.clone());
+                bounds.push(format!("substr({}, 1, length(?)) >= ?", column));
+            }
+            if let Some(to) = to {
+                params.push(to.clone());
+                params.push(to.clone());
+                bounds.push(format!("substr({}, 1, length(?)) <= ?", column));
+            }
+            if bounds.is_empty() {
+                format!("{} IS NOT NULL", column)
+            } else {
+                bounds.join(" AND ")
+            }
+        }
+    };
+    match join {
+        Some((tables, link)) => format!("EXISTS (SELECT 1 FROM {} WHERE {} AND {})", tables, link, condition),
+        None => condition,
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
 }
 
-pub fn list_titles() -> Vec<String> {
-    // TODO this should be a `title' type
-    let available_titles = Vec::new();
-    return available_titles;
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_terms_match_substrings() {
+        let filter = compile(&Query::parse("title:50%").unwrap());
+        assert_eq!("b.title LIKE ? ESCAPE '\\'", filter.clause);
+        assert_eq!(vec!["%50\\%%"], filter.params);
+    }
+
+    #[test]
+    fn list_fields_use_subqueries() {
+        let filter = compile(&Query::parse("-subject:poetry").unwrap());
+        assert!(filter.clause.starts_with("NOT coalesce((EXISTS (SELECT 1 FROM book_subjects"));
+    }
+
+    #[test]
+    fn fields_are_summarised() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        let book = crate::application::book::Book {
+            title: Some("Howards End".to_string()),
+            author: Some(vec!["E. M. Forster".to_string()]),
+            ..Default::default()
+        };
+        library.add(&book, Path::new("/b/he.epub"), &crate::database::Hashes::default()).unwrap();
+        let fields = list_fields(&dir.path().join("library.db"));
+        assert_eq!(Field::ALL.len(), fields.len());
+        let author = fields.iter().find(|summary| summary.field == Field::Author).unwrap();
+        assert_eq!(1, author.count);
+        assert_eq!(vec!["E. M. Forster"], author.examples);
+        let isbn = fields.iter().find(|summary| summary.field == Field::Isbn).unwrap();
+        assert_eq!(0, isbn.count);
+    }
+
+    #[test]
+    fn series_indexes_are_compared_as_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        for (index, title) in [(2.0, "The Day of the Scorpion"), (10.0, "Staying On"), (1.5, "Interlude")] {
+            let book = crate::application::book::Book {
+                title: Some(title.to_string()),
+                series: Some("The Raj Quartet".to_string()),
+                series_index: Some(index),
+                ..Default::default()
+            };
+            library.add(&book, &dir.path().join(title), &crate::database::Hashes::default()).unwrap();
+        }
+        let titles = |query: &str| library.find(&Query::parse(query).unwrap()).unwrap()
+            .into_iter()
+            .filter_map(|record| record.book.title)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["The Day of the Scorpion"], titles("series_index:2"));
+        assert_eq!(vec!["Interlude", "The Day of the Scorpion"], titles("series:raj series_index:..9"));
+        assert_eq!(vec!["Staying On"], titles("series_index:3.."));
+    }
+
+    #[test]
+    fn dates_match_at_any_precision() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        for (date, title) in [("1910", "Howards End"), ("1924-06", "A Passage to India"), ("1971-10-07", "Maurice")] {
+            let book = crate::application::book::Book {
+                title: Some(title.to_string()),
+                publication_date: crate::application::book::date::PartialDate::parse(date),
+                ..Default::default()
+            };
+            library.add(&book, &dir.path().join(title), &crate::database::Hashes::default()).unwrap();
+        }
+        let titles = |query: &str| library.find(&Query::parse(query).unwrap()).unwrap()
+            .into_iter()
+            .filter_map(|record| record.book.title)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["A Passage to India", "Howards End"], titles("year:1900..1930"));
+        assert_eq!(vec!["A Passage to India"], titles("date:1924-06"));
+        assert_eq!(vec!["Maurice"], titles("date:1971-10"));
+        assert!(titles("date:1910-05").is_empty());
+    }
+
+    #[test]
+    fn negations_match_unset_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        for (publisher, title) in [(Some("Penguin"), "Howards End"), (None, "Maurice")] {
+            let book = crate::application::book::Book {
+                title: Some(title.to_string()),
+                publisher: publisher.map(String::from),
+                ..Default::default()
+            };
+            library.add(&book, &dir.path().join(title), &crate::database::Hashes::default()).unwrap();
+        }
+        let titles = |query: &str| library.find(&Query::parse(query).unwrap()).unwrap()
+            .into_iter()
+            .filter_map(|record| record.book.title)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["Maurice"], titles("-publisher:penguin"));
+        assert_eq!(vec!["Howards End", "Maurice"], titles("-publisher:x"));
+        assert_eq!(vec!["Howards End", "Maurice"], titles("-lang:fr"));
+    }
+
+    #[test]
+    fn languages_match_their_regional_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut library = Library::open(&dir.path().join("library.db")).unwrap();
+        for (language, title) in [("en-GB", "Howards End"), ("en", "Maurice"), ("fr", "Route des Indes"), ("eo", "La Batalo")] {
+            let book = crate::application::book::Book {
+                title: Some(title.to_string()),
+                language: Some(language.to_string()),
+                ..Default::default()
+            };
+            library.add(&book, &dir.path().join(title), &crate::database::Hashes::default()).unwrap();
+        }
+        let titles = |query: &str| library.find(&Query::parse(query).unwrap()).unwrap()
+            .into_iter()
+            .filter_map(|record| record.book.title)
+            .collect::<Vec<String>>();
+        assert_eq!(vec!["Howards End", "Maurice"], titles("lang:en"));
+        assert_eq!(vec!["Howards End"], titles("lang:en-gb"));
+        assert_eq!(vec!["Route des Indes"], titles("lang:French"));
+        assert_eq!(vec!["Howards End", "La Batalo", "Maurice"], titles("-lang:fre"));
+    }
+
+    #[test]
+    fn ranges_compare_prefixes() {
+        let filter = compile(&Query::parse("year:1900..1920").unwrap());
+        assert_eq!(vec!["1900", "1900", "1920", "1920"], filter.params);
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use rusqlite::{ffi, params, Connection, Transaction};
+
+use crate::application::book::name::Name;
+
+type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Schema migrations, in order. The library records the number of migrations
+/// applied in `PRAGMA user_version`, so existing entries must never change;
+/// new schema versions are appended.
+const MIGRATIONS: [Migration; 10] = [
+    create_books,
+    normalise_books,
+    add_content_hash,
+    create_identifiers,
+    add_cover,
+    add_series,
+    add_author_names,
+    create_contributors,
+    shorten_dates,
+    add_language,
+];
+
+pub fn version(connection: &Connection) -> rusqlite::Result<usize> {
+    connection.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+        .map(|version| version as usize)
+}
+
+/// Brings the library schema up to date with this version of roots.
+pub fn migrate(connection: &mut Connection) -> rusqlite::Result<()> {
+    let current = version(connection)?;
+    if current > MIGRATIONS.len() {
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_MISMATCH),
+            Some(format!(
+                "library schema version {} is newer than this version of roots supports ({})",
+                current,
+                MIGRATIONS.len()
+            )),
+        ));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let tx = connection.transaction()?;
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Version 1, a single table with one row per file.
+fn create_books(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS books (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT,
+            author TEXT,
+            publisher TEXT,
+            publication_date TEXT,
+            imprint TEXT,
+            description TEXT,
+            subject TEXT,
+            asin TEXT,
+            isbn TEXT,
+            path TEXT NOT NULL UNIQUE,
+            hash TEXT,
+            imported_at TEXT NOT NULL
+        );",
+    )
+}
+
+/// Version 2, splits authors, subjects and file locations out of `books`.
+fn normalise_books(tx: &Transaction) -> rusqlite::Result<()> {
+    let legacy = {
+        let mut statement = tx.prepare(
+            "SELECT id, author, subject, path, hash, imported_at FROM books",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    tx.execute_batch(
+        "CREATE TABLE books_v2 (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT,
+            publisher TEXT,
+            publication_date TEXT,
+            imprint TEXT,
+            description TEXT,
+            asin TEXT,
+            isbn TEXT
+        );
+        INSERT INTO books_v2 (id, title, publisher, publication_date, imprint, description, asin, isbn)
+            SELECT id, title, publisher, publication_date, imprint, description, asin, isbn FROM books;
+        DROP TABLE books;
+        ALTER TABLE books_v2 RENAME TO books;
+
+        CREATE TABLE authors (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE book_authors (
+            book_id INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+            author_id INTEGER NOT NULL REFERENCES authors(id) ON DELETE CASCADE,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (book_id, author_id)
+        );
+        CREATE TABLE subjects (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE
+        );
+        CREATE TABLE book_subjects (
+            book_id INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+            subject_id INTEGER NOT NULL REFERENCES subjects(id) ON DELETE CASCADE,
+            PRIMARY KEY (book_id, subject_id)
+        );
+        CREATE TABLE files (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            book_id INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+            path TEXT NOT NULL UNIQUE,
+            format TEXT NOT NULL,
+            hash TEXT,
+            imported_at TEXT NOT NULL
+        );
+
+        CREATE INDEX idx_books_title ON books(title);
+        CREATE INDEX idx_books_isbn ON books(isbn);
+        CREATE INDEX idx_books_asin ON books(asin);
+        CREATE INDEX idx_files_book_id ON files(book_id);
+        CREATE INDEX idx_files_hash ON files(hash);",
+    )?;
+    for (book_id, author, subject, path, hash, imported_at) in legacy {
+        for (position, name) in split_legacy(author).into_iter().enumerate() {
+            tx.execute("INSERT OR IGNORE INTO authors (name) VALUES (?1)", params![name])?;
+            tx.execute(
+                "INSERT OR IGNORE INTO book_authors (book_id, author_id, position)
+                 SELECT ?1, id, ?2 FROM authors WHERE name = ?3",
+                params![book_id, position as i64, name],
+            )?;
+        }
+        for name in split_legacy(subject) {
+            tx.execute("INSERT OR IGNORE INTO subjects (name) VALUES (?1)", params![name])?;
+            tx.execute(
+                "INSERT OR IGNORE INTO book_subjects (book_id, subject_id)
+                 SELECT ?1, id FROM subjects WHERE name = ?2",
+                params![book_id, name],
+            )?;
+        }
+        let format = Path::new(&path).extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default()
+            .to_lowercase();
+        tx.execute(
+            "INSERT INTO files (book_id, path, format, hash, imported_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![book_id, path, format, hash, imported_at],
+        )?;
+    }
+    Ok(())
+}
+
+/// Version 3, a hash of each file's content that ignores its metadata.
+fn add_content_hash(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE files ADD COLUMN content_hash TEXT;
+        CREATE INDEX idx_files_content_hash ON files(content_hash);",
+    )
+}
+
+/// Version 4, every identifier of a book, seeded with the stored ISBNs and ASINs.
+fn create_identifiers(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE identifiers (
+            book_id INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+            scheme TEXT NOT NULL,
+            value TEXT NOT NULL,
+            PRIMARY KEY (book_id, scheme, value)
+        );
+        CREATE INDEX idx_identifiers_value ON identifiers(scheme, value);
+        INSERT INTO identifiers (book_id, scheme, value)
+            SELECT id, 'isbn', replace(isbn, '-', '') FROM books WHERE isbn IS NOT NULL AND isbn != '';
+        INSERT OR IGNORE INTO identifiers (book_id, scheme, value)
+            SELECT id, 'asin', asin FROM books WHERE asin IS NOT NULL AND asin != '';",
+    )
+}
+
+/// Version 5, the file name of each book's cached cover image.
+fn add_cover(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE books ADD COLUMN cover TEXT;")
+}
+
+/// Version 6, the series of each book and its position in it.
+fn add_series(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE books ADD COLUMN series TEXT;
+        ALTER TABLE books ADD COLUMN series_index REAL;
+        CREATE INDEX idx_books_series ON books(series);",
+    )
+}
+
+/// Version 7, the sort name of each author and a key that matches other
+/// spellings of the name, authors sharing a key are merged; and the aliases
+/// users give authors.
+fn add_author_names(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE authors ADD COLUMN sort_name TEXT;
+        ALTER TABLE authors ADD COLUMN key TEXT;
+        CREATE TABLE author_aliases (
+            key TEXT PRIMARY KEY,
+            alias TEXT NOT NULL,
+            author_id INTEGER NOT NULL REFERENCES authors(id) ON DELETE CASCADE
+        );",
+    )?;
+    let authors = {
+        let mut statement = tx.prepare("SELECT id, name FROM authors ORDER BY id")?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let mut kept: HashMap<String, i64> = HashMap::new();
+    let mut names = Vec::new();
+    for (id, name) in authors {
+        let name = Name::parse(&name);
+        match kept.get(&name.key()) {
+            Some(author_id) => {
+                tx.execute("UPDATE OR IGNORE book_authors SET author_id = ?2 WHERE author_id = ?1", params![id, author_id])?;
+                tx.execute("DELETE FROM book_authors WHERE author_id = ?1", params![id])?;
+                tx.execute("DELETE FROM authors WHERE id = ?1", params![id])?;
+            }
+            None => {
+                kept.insert(name.key(), id);
+                names.push((id, name));
+            }
+        }
+    }
+    for (id, name) in names {
+        tx.execute(
+            "UPDATE authors SET name = ?2, sort_name = ?3, key = ?4 WHERE id = ?1",
+            params![id, name.display, name.sort, name.key()],
+        )?;
+    }
+    tx.execute_batch("CREATE UNIQUE INDEX idx_authors_key ON authors(key);")
+}
+
+/// Version 8, the people credited in roles other than author, kept with the
+/// authors so that aliases apply to them too.
+fn create_contributors(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE book_contributors (
+            book_id INTEGER NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+            author_id INTEGER NOT NULL REFERENCES authors(id) ON DELETE CASCADE,
+            role TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            PRIMARY KEY (book_id, author_id, role)
+        );
+        CREATE INDEX idx_book_contributors_author ON book_contributors(author_id);",
+    )
+}
+
+/// Version 9, publication dates kept to the precision they are known.
+/// Earlier versions stored every date as a timestamp at midnight UTC; those
+/// become days, while real timestamps are rewritten with a `Z`.
+fn shorten_dates(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "UPDATE books SET publication_date = substr(publication_date, 1, 10)
+            WHERE publication_date LIKE '____-__-__T00:00:00+00:00';
+        UPDATE books SET publication_date = substr(publication_date, 1, 19) || 'Z'
+            WHERE publication_date LIKE '____-__-__T__:__:__+00:00';",
+    )
+}
+
+/// Version 10, the BCP 47 tag of each book's language.
+fn add_language(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE books ADD COLUMN language TEXT;")
+}
+
+fn split_legacy(value: Option<String>) -> Vec<String> {
+    value.map(|v| v.split("; ").map(String::from).filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_empty_database_to_latest() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        assert_eq!(MIGRATIONS.len(), version(&connection).unwrap());
+    }
+
+    #[test]
+    fn migrates_version_one_rows() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        {
+            let tx = connection.transaction().unwrap();
+            create_books(&tx).unwrap();
+            tx.execute(
+                "INSERT INTO books (title, author, subject, path, imported_at)
+                 VALUES ('Howards End', 'E. M. Forster; Someone Else', 'Fiction', '/b/he.epub', 'now')",
+                [],
+            ).unwrap();
+            tx.pragma_update(None, "user_version", 1).unwrap();
+            tx.commit().unwrap();
+        }
+        migrate(&mut connection).unwrap();
+        let authors: i64 = connection
+            .query_row("SELECT count(*) FROM book_authors", [], |row| row.get(0))
+            .unwrap();
+        let format: String = connection
+            .query_row("SELECT format FROM files WHERE path = '/b/he.epub'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(2, authors);
+        assert_eq!("epub", format);
+    }
+
+    #[test]
+    fn spellings_of_an_author_are_merged() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        {
+            let tx = connection.transaction().unwrap();
+            for migration in MIGRATIONS.iter().take(6) {
+                migration(&tx).unwrap();
+            }
+            tx.execute_batch(
+                "INSERT INTO books (id, title) VALUES (1, 'Howards End'), (2, 'Maurice');
+                INSERT INTO authors (id, name) VALUES (1, 'Forster, E. M.'), (2, 'E.M. Forster'), (3, 'E. M. Forster');
+                INSERT INTO book_authors (book_id, author_id, position) VALUES (1, 1, 0), (2, 2, 0), (2, 3, 1);",
+            ).unwrap();
+            tx.pragma_update(None, "user_version", 6).unwrap();
+            tx.commit().unwrap();
+        }
+        migrate(&mut connection).unwrap();
+        let author = connection
+            .query_row("SELECT name, sort_name FROM authors", [], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+            .unwrap();
+        let credits: i64 = connection
+            .query_row("SELECT count(*) FROM book_authors WHERE author_id = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(("E. M. Forster".to_string(), "Forster, E. M.".to_string()), author);
+        assert_eq!(2, credits);
+    }
+
+    #[test]
+    fn midnight_timestamps_become_days() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        {
+            let tx = connection.transaction().unwrap();
+            for migration in MIGRATIONS.iter().take(8) {
+                migration(&tx).unwrap();
+            }
+            tx.execute_batch(
+                "INSERT INTO books (id, title, publication_date) VALUES
+                    (1, 'Howards End', '1910-10-18T00:00:00+00:00'),
+                    (2, 'Maurice', '1971-10-07T09:30:00+00:00');",
+            ).unwrap();
+            tx.pragma_update(None, "user_version", 8).unwrap();
+            tx.commit().unwrap();
+        }
+        migrate(&mut connection).unwrap();
+        let dates = connection
+            .prepare("SELECT publication_date FROM books ORDER BY id").unwrap()
+            .query_map([], |row| row.get::<_, String>(0)).unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>().unwrap();
+        assert_eq!(vec!["1910-10-18", "1971-10-07T09:30:00Z"], dates);
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        connection.pragma_update(None, "user_version", 99).unwrap();
+        assert!(migrate(&mut connection).is_err());
+    }
+}
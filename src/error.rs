@@ -0,0 +1,113 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::application::query::QueryError;
+use crate::application::template::TemplateError;
+
+/// Everything that can stop a command, each kind with its own exit code.
+#[derive(Debug)]
+pub enum RootsError {
+    /// Invalid arguments, such as a malformed query or template.
+    Usage(String),
+    /// A file that is not a supported e-book format.
+    UnsupportedFormat(PathBuf),
+    /// An e-book that cannot be read.
+    CorruptFile(PathBuf, String),
+    Io(PathBuf, io::Error),
+    Config(String),
+    Network(String),
+    Database(rusqlite::Error),
+}
+
+impl RootsError {
+    pub fn corrupt(path: &Path, reason: impl Display) -> RootsError {
+        RootsError::CorruptFile(path.to_path_buf(), reason.to_string())
+    }
+
+    pub fn io(path: &Path, error: io::Error) -> RootsError {
+        RootsError::Io(path.to_path_buf(), error)
+    }
+
+    /// The process exit code, 1 is left to argument errors reported by clap.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RootsError::Usage(_) => 2,
+            RootsError::UnsupportedFormat(_) => 3,
+            RootsError::CorruptFile(..) => 4,
+            RootsError::Io(..) => 5,
+            RootsError::Config(_) => 6,
+            RootsError::Network(_) => 7,
+            RootsError::Database(_) => 8,
+        }
+    }
+}
+
+impl Display for RootsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            RootsError::Usage(message) => write!(f, "{}", message),
+            RootsError::UnsupportedFormat(path) => write!(f, "{}: unsupported format", path.display()),
+            RootsError::CorruptFile(path, reason) => write!(f, "{}: cannot read e-book, {}", path.display(), reason),
+            RootsError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            RootsError::Config(message) => write!(f, "invalid configuration, {}", message),
+            RootsError::Network(message) => write!(f, "metadata request failed, {}", message),
+            RootsError::Database(error) => write!(f, "library error, {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RootsError {}
+
+impl From<rusqlite::Error> for RootsError {
+    fn from(error: rusqlite::Error) -> Self {
+        RootsError::Database(error)
+    }
+}
+
+impl From<reqwest::Error> for RootsError {
+    fn from(error: reqwest::Error) -> Self {
+        RootsError::Network(error.to_string())
+    }
+}
+
+impl From<config::ConfigError> for RootsError {
+    fn from(error: config::ConfigError) -> Self {
+        RootsError::Config(error.to_string())
+    }
+}
+
+impl From<QueryError> for RootsError {
+    fn from(error: QueryError) -> Self {
+        RootsError::Usage(format!("invalid query, {}", error))
+    }
+}
+
+impl From<TemplateError> for RootsError {
+    fn from(error: TemplateError) -> Self {
+        RootsError::Usage(format!("invalid template, {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let errors = [
+            RootsError::Usage(String::new()),
+            RootsError::UnsupportedFormat(PathBuf::new()),
+            RootsError::CorruptFile(PathBuf::new(), String::new()),
+            RootsError::Io(PathBuf::new(), io::Error::from(io::ErrorKind::NotFound)),
+            RootsError::Config(String::new()),
+            RootsError::Network(String::new()),
+            RootsError::Database(rusqlite::Error::InvalidQuery),
+        ];
+        let mut codes = errors.iter().map(RootsError::exit_code).collect::<Vec<i32>>();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(errors.len(), codes.len());
+        assert!(!codes.contains(&0) && !codes.contains(&1));
+    }
+}
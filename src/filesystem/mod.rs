@@ -1,14 +1,187 @@
 #![allow(dead_code)]
 use std::collections::HashMap;
-use std::path::Path;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
 
 pub type Replacements = HashMap<String, String>;
+/// Replacements compiled once, in the order they are applied.
+pub type Rules = Vec<(Regex, String)>;
+
+pub struct Source(pub PathBuf);
+pub struct Destination(pub PathBuf);
+pub struct ProposedMove(pub Source, pub Destination);
+
+/// Compiles `replacements` sorted by pattern, so that overlapping patterns
+/// apply in the same order on every run.
+pub fn rules(replacements: &Replacements) -> Result<Rules, regex::Error> {
+    let mut patterns = replacements.iter().collect::<Vec<(&String, &String)>>();
+    patterns.sort();
+    patterns.into_iter()
+        .map(|(pattern, replacement)| Ok((Regex::new(pattern)?, replacement.clone())))
+        .collect()
+}
+
+/// Where files will go, every destination path component passed through
+/// `transformer` with the rules.
+pub struct ProposedMoves {
+    moves: Vec<ProposedMove>,
+    rules: Rules,
+    transformer: fn(&Rules, String) -> String,
+}
+
+impl ProposedMoves {
+    pub fn new(rules: Rules, transformer: fn(&Rules, String) -> String) -> Self {
+        ProposedMoves { moves: Vec::new(), rules, transformer }
+    }
+
+    /// The path of the `components` below `root`, each one transformed.
+    pub fn destination(&self, root: &Path, components: Vec<String>) -> PathBuf {
+        components.into_iter()
+            .fold(root.to_path_buf(), |path, component| {
+                path.join((self.transformer)(&self.rules, component))
+            })
+    }
+
+    pub fn propose(&mut self, source: &Path, destination: PathBuf) {
+        self.moves.push(ProposedMove(Source(source.to_path_buf()), Destination(destination)));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProposedMove> {
+        self.moves.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+}
+
+impl Display for ProposedMoves {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        for ProposedMove(Source(source), Destination(destination)) in &self.moves {
+            writeln!(f, "{} -> {}", source.display(), destination.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Recursively finds files below `root` with one of the given extensions.
+pub fn find_files(root: &Path, extensions: &[&str]) -> Vec<PathBuf> {
+    let mut files = WalkDir::new(root)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            let name = path.file_name().and_then(OsStr::to_str).unwrap_or_default().to_lowercase();
+            extensions.iter().any(|ext| name.ends_with(&format!(".{}", ext)))
+        })
+        .collect::<Vec<PathBuf>>();
+    files.sort();
+    files
+}
+
+/// Hex encoded SHA-256 digest of a file's contents.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Copies `source` to `destination`, or moves it if `relocate` is set,
+/// creating any missing parent directories.
+pub fn transfer(source: &Path, destination: &Path, relocate: bool) -> io::Result<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if relocate {
+        // rename fails across filesystems, fall back to copy and delete
+        fs::rename(source, destination).or_else(|_| {
+            fs::copy(source, destination)?;
+            fs::remove_file(source)
+        })
+    } else {
+        fs::copy(source, destination).map(|_| ())
+    }
+}
+
+/// `path` with `suffix` added to its file name, e.g. `he.epub.bak`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Replaces `path` with `replacement`, keeping the original as `<name>.bak`
+/// unless an earlier backup exists.
+pub fn replace(path: &Path, replacement: &Path) -> io::Result<()> {
+    let backup = with_suffix(path, ".bak");
+    if !backup.exists() {
+        fs::copy(path, &backup)?;
+    }
+    fs::rename(replacement, path)
+}
+
+/// Removes empty directories from `dir` upwards, stopping at `root`.
+pub fn prune(root: &Path, dir: &Path) {
+    let mut current = Some(dir);
+    while let Some(path) = current {
+        if !path.starts_with(root) || path == root {
+            break;
+        }
+        if fs::remove_dir(path).is_err() {
+            break;
+        }
+        current = path.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn find_files_recurses_and_filters() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("a/b")).unwrap();
+        fs::write(root.path().join("a/one.epub"), b"").unwrap();
+        fs::write(root.path().join("a/b/two.MOBI"), b"").unwrap();
+        fs::write(root.path().join("a/b/three.txt"), b"").unwrap();
+        fs::write(root.path().join("a/b/four.fb2.zip"), b"").unwrap();
+        fs::write(root.path().join("a/b/five.zip"), b"").unwrap();
+        let files = find_files(root.path(), &["epub", "mobi", "fb2.zip"]);
+        assert_eq!(vec![root.path().join("a/b/four.fb2.zip"), root.path().join("a/b/two.MOBI"), root.path().join("a/one.epub")], files);
+    }
 
-struct Source<'a>(&'a Path);
-struct Destination<'a>(&'a Path);
-struct ProposedMove<'a>(Source<'a>, Destination<'a>);
+    #[test]
+    fn prune_removes_empty_directories_below_root() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("a/b/c")).unwrap();
+        fs::write(root.path().join("a/keep.epub"), b"").unwrap();
+        prune(root.path(), &root.path().join("a/b/c"));
+        assert!(!root.path().join("a/b").exists());
+        assert!(root.path().join("a").exists());
+    }
 
-struct ProposedMoves<'a> {
-    moves: Vec<ProposedMove<'a>>,
-    transformer: fn(Replacements, String) -> String,
-}
\ No newline at end of file
+    #[test]
+    fn proposed_components_are_transformed() {
+        let mut moves = ProposedMoves::new(Rules::new(), |_, component| component.replace(':', "_"));
+        let destination = moves.destination(Path::new("/books"), vec!["Forster".to_string(), "Space: 1999.epub".to_string()]);
+        moves.propose(Path::new("/in/a.epub"), destination);
+        assert_eq!("/in/a.epub -> /books/Forster/Space_ 1999.epub\n", moves.to_string());
+    }
+}
@@ -1,13 +1,23 @@
 use {
     clap::{App, AppSettings, Arg, SubCommand},
-    crate::database::query::{list_fields, list_titles},
+    crate::database::query::list_fields,
+    std::io::{self, Write},
     std::path::Path,
 };
-use crate::application::book::Book;
+use crate::application::book::name::Name;
+use crate::application::book::{book_comparator, find_books, loader, Book, MAX_SCORE};
+use crate::application::cover::{self, CoverCache};
+use crate::application::import::import;
+use crate::application::query::{Field, Query};
+use crate::application::template::Template;
+use crate::application::update::{best_match, differences, reconcile, resolve, MINIMUM_SCORE};
+use crate::database::Library;
+use crate::interface::format::{parse_fields, Output};
 use crate::internet::metadata;
 
 use crate::application::command::Command;
 use crate::configuration::Configuration;
+use crate::error::RootsError;
 
 pub struct Application {
     cfg: Configuration,
@@ -18,25 +28,67 @@ impl Application {
         Application { cfg }
     }
 
-    pub fn run(self) -> Result<(), ()> {
+    pub fn run(self) -> Result<(), RootsError> {
         let cmd: Command = parse_command_line();
         handle_command(self.cfg, cmd)
     }
 }
 
-fn handle_command(cfg: Configuration, cmd: Command) -> Result<(), ()> {
+fn handle_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
     match cmd {
+        Command::Alias { .. } => handle_alias_command(cfg, cmd),
         Command::Config { .. } => handle_config_command(cfg, cmd),
+        Command::Cover { .. } => handle_cover_command(cfg, cmd),
         Command::Fields => handle_fields_command(cfg, cmd),
-        Command::Find { .. } => Ok(()),
+        Command::Find { .. } => handle_find_command(cfg, cmd),
         Command::Import { .. } => handle_import_command(cfg, cmd),
         Command::Info { .. } => handle_info_command(cfg, cmd),
         Command::List { .. } => handle_list_command(cfg, cmd),
-        Command::Update => handle_update_command(cfg, cmd),
+        Command::Update { .. } => handle_update_command(cfg, cmd),
     }
 }
 
-fn handle_config_command(cfg: Configuration, cmd: Command) -> Result<(), ()> {
+fn handle_alias_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
+    match cmd {
+        Command::Alias { remove, alias, author } => match (alias, author) {
+            (Some(alias), None) if remove => {
+                let removed = match Library::open_existing(&cfg.get_library())? {
+                    Some(mut library) => library.remove_alias(&alias)?,
+                    None => false,
+                };
+                if removed {
+                    println!("Removed the alias {}", alias);
+                } else {
+                    println!("{} is not an alias.", alias);
+                }
+                Ok(())
+            }
+            (Some(alias), Some(author)) if !remove => {
+                Library::open(&cfg.get_library())?.add_alias(&alias, &author)?;
+                println!("{} is now an alias of {}", alias, author);
+                Ok(())
+            }
+            (None, None) if !remove => {
+                let aliases = match Library::open_existing(&cfg.get_library())? {
+                    Some(library) => library.aliases()?,
+                    None => Vec::new(),
+                };
+                if aliases.is_empty() {
+                    println!("No aliases.");
+                }
+                for (alias, author) in aliases {
+                    println!("{} -> {}", alias, author);
+                }
+                Ok(())
+            }
+            _ if remove => Err(RootsError::Usage("--remove takes an alias alone".to_string())),
+            _ => Err(RootsError::Usage("an alias needs the name of its author".to_string())),
+        },
+        _ => unreachable!(),
+    }
+}
+
+fn handle_config_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
     match cmd {
         Command::Config {
             path: true,
@@ -59,79 +111,378 @@ fn handle_config_command(cfg: Configuration, cmd: Command) -> Result<(), ()> {
             println!("{}", cfg);
             Ok(())
         }
-        _ => Err(()),
+        _ => unreachable!(),
     }
 }
 
-fn handle_fields_command(_cfg: Configuration, cmd: Command) -> Result<(), ()> {
+fn handle_cover_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
+    match cmd {
+        Command::Cover { thumbnail, extract, query } => {
+            let query = Query::from_args(&query)?;
+            let mut library = match Library::open_existing(&cfg.get_library())? {
+                Some(library) => library,
+                None => {
+                    println!("No titles found, is roots initialised?");
+                    return Ok(());
+                }
+            };
+            let records = library.find(&query)?;
+            if records.is_empty() {
+                println!("No matching titles.");
+                return Ok(());
+            }
+            let covers = CoverCache::from_config(&cfg);
+            for record in &records {
+                let title = record.book.title.as_deref().unwrap_or("Untitled");
+                let path = match covers.find(&mut library, record)? {
+                    Some(name) if thumbnail => covers.thumbnail(&name)?,
+                    Some(name) => covers.path(&name),
+                    None if extract.is_some() => return Err(RootsError::Usage(format!("{} has no cover", title))),
+                    None => {
+                        println!("{}: no cover", title);
+                        continue;
+                    }
+                };
+                match &extract {
+                    Some(destination) => {
+                        cover::extract(&path, Path::new(destination))?;
+                        println!("Wrote cover of {} to {}", title, destination);
+                        break;
+                    }
+                    None => println!("{}: {}", title, path.display()),
+                }
+            }
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn handle_fields_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
     match cmd {
         Command::Fields => {
-            let available_fields = list_fields();
+            let available_fields = list_fields(&cfg.get_library());
             if available_fields.is_empty() {
                 println!("No available fields, is roots initialised?")
             } else {
+                println!("{:<12} {:<11} {:>6}  {}", "FIELD", "TYPE", "BOOKS", "EXAMPLES");
                 for f in available_fields {
-                    println!("{}", f);
+                    let examples = f.examples.iter()
+                        .map(|example| format!("{:?}", truncate(example, 30)))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    println!("{:<12} {:<11} {:>6}  {}", f.field.name(), f.field.kind().to_string(), f.count, examples);
                 }
             }
             Ok(())
         }
-        _ => Err(()),
+        _ => unreachable!(),
     }
 }
 
-fn handle_import_command(_cfg: Configuration, _cmd: Command) -> Result<(), ()> {
-    println!("No files found to import.");
-    Ok(())
+fn truncate(value: &str, length: usize) -> String {
+    if value.chars().count() > length {
+        format!("{}...", value.chars().take(length).collect::<String>().trim_end())
+    } else {
+        value.to_string()
+    }
+}
+
+fn handle_find_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
+    match cmd {
+        Command::Find { show_scores, target } => {
+            let book = match find_target(&cfg, &target)? {
+                Some(book) => book,
+                None => return Ok(()),
+            };
+            let mut candidates = metadata::search(&book)?.iter()
+                .map(Book::from)
+                .map(|candidate| (book_comparator(&book, &candidate), candidate))
+                .collect::<Vec<(usize, Book)>>();
+            candidates.sort_by(|(l, _), (r, _)| r.cmp(l));
+            match candidates.first() {
+                None => println!("No metadata found."),
+                Some(_) if show_scores => {
+                    for (score, candidate) in &candidates {
+                        println!("{:>3}/{}  {}", score, MAX_SCORE, describe(candidate));
+                    }
+                }
+                Some((_, best)) => println!("{:#?}", best),
+            }
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// The book `find` looks up, a file when given a path, otherwise the first
+/// library book matching the query.
+fn find_target(cfg: &Configuration, target: &[String]) -> Result<Option<Book>, RootsError> {
+    if target.is_empty() {
+        println!("Nothing to find, give a path or a query.");
+        return Ok(None);
+    }
+    if let [path] = target {
+        if Path::new(path).is_file() {
+            return Ok(Some(Book::new(Path::new(path))?));
+        }
+    }
+    let query = Query::from_args(target)?;
+    let records = match Library::open_existing(&cfg.get_library())? {
+        Some(library) => library.find(&query)?,
+        None => {
+            println!("No titles found, is roots initialised?");
+            return Ok(None);
+        }
+    };
+    match records.into_iter().next() {
+        Some(record) => Ok(Some(record.book)),
+        None => {
+            println!("No matching titles.");
+            Ok(None)
+        }
+    }
+}
+
+/// A one line summary, `title by authors (year) isbn`.
+fn describe(book: &Book) -> String {
+    let mut line = book.title.clone().unwrap_or("Untitled".to_string());
+    if let Some(authors) = book.author.as_ref().filter(|a| !a.is_empty()) {
+        line.push_str(&format!(" by {}", authors.join(", ")));
+    }
+    if let Some(date) = book.publication_date {
+        line.push_str(&format!(" ({})", date.year()));
+    }
+    if let Some(isbn) = book.isbn.as_ref() {
+        line.push_str(&format!(" {}", isbn));
+    }
+    line
+}
+
+fn handle_import_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
+    match cmd {
+        Command::Import { path } => {
+            let root = Path::new(&path).canonicalize()
+                .map_err(|error| RootsError::io(Path::new(&path), error))?;
+            let sources = find_books(&root);
+            if sources.is_empty() {
+                println!("No files found to import.");
+                return Ok(());
+            }
+            let mut library = Library::open(&cfg.get_library())?;
+            let summary = import(&cfg, &mut library, &root, sources)?;
+            println!(
+                "Imported {} of {} files ({} duplicates, {} skipped, {} failed).",
+                summary.imported, summary.found, summary.duplicates, summary.skipped, summary.failed
+            );
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
 }
 
-fn handle_info_command(_cfg: Configuration, cmd: Command) -> Result<(), ()> {
+fn handle_info_command(_cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
     match cmd {
-        Command::Info { path, fetch } => {
-            let book = Book::new(Path::new(&path));
+        Command::Info { path, fetch, write } => {
+            let path = Path::new(&path);
+            let book = Book::new(path)?;
+            if let Some((format, loader)) = loader(path)? {
+                println!("Format: {}", format);
+                for (label, value) in loader.properties(path)? {
+                    println!("{}: {}", label, value);
+                }
+            }
             if fetch {
-                let result = metadata::request(&book);
-                let volumes = result.map(|r| r.items).unwrap();
-                let books = volumes.iter().map(Book::from).collect::<Vec<Book>>();
+                let books = metadata::search(&book)?.iter().map(Book::from).collect::<Vec<Book>>();
                 println!("{:#?}", books);
             }
             println!("{:#?}", book);
+            if write {
+                write_best_match(path, &book)?;
+            }
             Ok(())
         }
-        _ => Err(()),
+        _ => unreachable!(),
     }
 }
 
-fn handle_list_command(_cfg: Configuration, cmd: Command) -> Result<(), ()> {
+/// Writes the metadata of the best online match for `book` into its file.
+fn write_best_match(path: &Path, book: &Book) -> Result<(), RootsError> {
+    let (score, candidate) = match best_match(book)? {
+        Some(best) => best,
+        None => {
+            println!("No metadata found scoring at least {}.", MINIMUM_SCORE);
+            return Ok(());
+        }
+    };
+    let (changes, update) = differences(book, &candidate);
+    if changes.is_empty() {
+        println!("Metadata matches online metadata (score {}).", score);
+        return Ok(());
+    }
+    println!("Online metadata (score {}):", score);
+    for change in &changes {
+        println!("    {}", change);
+    }
+    if Book::write_metadata(path, &update)? {
+        println!("Wrote metadata to {}", path.display());
+    } else {
+        println!("Cannot write metadata to {}, the format is not supported.", path.display());
+    }
+    Ok(())
+}
+
+fn handle_list_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
     match cmd {
-        Command::List { .. } => {
-            let available_titles = list_titles();
-            if available_titles.is_empty() {
-                println!("No titles to list, is roots initialised?");
-            } else {
-                for t in available_titles {
-                    println!("{}", t);
+        Command::List { author, isbn, table, fields, format, template, sort, query } => {
+            let query = Query::from_args(&query)?;
+            let sort = match sort {
+                Some(name) => Some(Field::from_name(&name).ok_or(RootsError::Usage(format!("unknown sort field '{}'", name)))?),
+                None => None,
+            };
+            let isbn = isbn || cfg.get_list().isbn;
+            let table = table || cfg.get_list().table;
+            let output = list_output(isbn, table, fields, format, template)?;
+            let mut records = match Library::open_existing(&cfg.get_library())? {
+                Some(library) => library.find(&query)?,
+                None => {
+                    println!("No titles to list, is roots initialised?");
+                    return Ok(());
                 }
+            };
+            if let Some(field) = sort {
+                records.sort_by(|l, r| l.compare(r, field));
+            }
+            if records.is_empty() {
+                println!("No matching titles.");
+            } else if author {
+                let mut authors = records.iter()
+                    .flat_map(|record| record.book.names())
+                    .collect::<Vec<Name>>();
+                authors.sort_by_key(|name| (name.sort.to_lowercase(), name.display.clone()));
+                authors.dedup_by(|l, r| l.display == r.display);
+                for a in authors {
+                    println!("{}", a.display);
+                }
+            } else {
+                print!("{}", output.render(&records));
             }
             Ok(())
         }
-        _ => Err(()),
+        _ => unreachable!(),
     }
 }
 
-fn handle_update_command(_cfg: Configuration, cmd: Command) -> Result<(), ()> {
+/// Chooses the output for `list`, an explicit format or template takes
+/// precedence over the table flag.
+fn list_output(
+    isbn: bool,
+    table: bool,
+    fields: Option<String>,
+    format: Option<String>,
+    template: Option<String>,
+) -> Result<Output, RootsError> {
+    if let Some(template) = template {
+        let template = Template::parse(&template)?;
+        template.validate(|name| Field::from_name(name).is_some())?;
+        return Ok(Output::Template(template));
+    }
+    let selected = match fields {
+        Some(fields) => Some(parse_fields(&fields).map_err(|error| RootsError::Usage(format!("invalid fields, {}", error)))?),
+        None => None,
+    };
+    let with_isbn = |mut fields: Vec<Field>| {
+        if isbn && !fields.contains(&Field::Isbn) {
+            fields.push(Field::Isbn);
+        }
+        fields
+    };
+    Ok(match format.as_deref() {
+        Some("json") => Output::Json(with_isbn(selected.unwrap_or(Field::ALL.to_vec()))),
+        Some("csv") => Output::Delimited(with_isbn(selected.unwrap_or(Field::ALL.to_vec())), ','),
+        Some("tsv") => Output::Delimited(with_isbn(selected.unwrap_or(Field::ALL.to_vec())), '\t'),
+        Some(other) => return Err(RootsError::Usage(format!("unknown format '{}'", other))),
+        None if table || selected.is_some() => {
+            Output::Table(with_isbn(selected.unwrap_or(vec![Field::Title, Field::Author, Field::Year])))
+        }
+        None => Output::Lines(with_isbn(vec![Field::Title])),
+    })
+}
+
+fn handle_update_command(cfg: Configuration, cmd: Command) -> Result<(), RootsError> {
     match cmd {
-        Command::Update => {
-            // scan the filesystem, cross-reference the database and ensure they're consistent
-            // use a 3p service to cross-ref metadata for existing titles
-            // offer to resolve any inconsistencies
-            let available_titles = list_titles();
-            if available_titles.is_empty() {
-                println!("No titles found, is roots initialised?");
+        Command::Update { dry_run, yes, fetch, write } => {
+            let write = write || cfg.get_update().write;
+            let mut library = match Library::open_existing(&cfg.get_library())? {
+                Some(library) => library,
+                None => {
+                    println!("No titles found, is roots initialised?");
+                    return Ok(());
+                }
+            };
+            let findings = reconcile(&cfg, &library, fetch)?;
+            if findings.is_empty() {
+                println!("Library is up to date.");
+                return Ok(());
+            }
+            let mut apply_all = yes;
+            let mut resolved = 0;
+            for finding in &findings {
+                println!("{}", finding);
+                if dry_run {
+                    continue;
+                }
+                let accept = apply_all || match prompt(finding.action()) {
+                    Answer::Yes => true,
+                    Answer::No => false,
+                    Answer::All => {
+                        apply_all = true;
+                        true
+                    }
+                    Answer::Quit => break,
+                };
+                if accept {
+                    match resolve(&cfg, &mut library, finding, write) {
+                        Ok(()) => resolved += 1,
+                        Err(error) => println!("Cannot resolve, {}", error),
+                    }
+                }
+            }
+            if dry_run {
+                println!("{} inconsistencies found, nothing changed.", findings.len());
+            } else {
+                println!("Resolved {} of {} inconsistencies.", resolved, findings.len());
             }
             Ok(())
         }
-        _ => Err(()),
+        _ => unreachable!(),
+    }
+}
+
+enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+/// Asks until the answer is one of yes, no, all or quit; the end of input quits.
+fn prompt(question: &str) -> Answer {
+    loop {
+        print!("{} [y]es, [n]o, [a]ll, [q]uit: ", question);
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        match io::stdin().read_line(&mut answer) {
+            Ok(0) | Err(_) => return Answer::Quit,
+            Ok(_) => {}
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Answer::Yes,
+            "n" | "no" | "s" | "skip" => return Answer::No,
+            "a" | "all" => return Answer::All,
+            "q" | "quit" => return Answer::Quit,
+            _ => continue,
+        }
     }
 }
 
@@ -141,8 +492,50 @@ fn parse_command_line() -> Command {
         .version(crate_version!())
         .version_short("v")
         .about("roots e-book manager")
+        .after_help(
+            "EXIT STATUS:
+    0 success, 1 invalid arguments, 2 invalid query, template or fields,
+    3 unsupported format, 4 unreadable e-book, 5 file error,
+    6 configuration error, 7 metadata request failed, 8 library error",
+        )
         .setting(AppSettings::VersionlessSubcommands)
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("alias")
+                .about("Shows or sets other names of authors")
+                .usage(
+                    "root alias [FLAGS] [alias] [author]
+
+An alias is another name of an author, such as a full or pen name. Books
+credited to an alias are credited to its author, now and on import.
+Spellings differing in case, spacing or punctuation, or written as
+'Forster, E. M.', need no alias.
+
+EXAMPLES:
+    root alias
+       -> lists every alias with its author
+
+    root alias \"Edward Morgan Forster\" \"E. M. Forster\"
+       -> credits books by Edward Morgan Forster to E. M. Forster
+
+    root alias --remove \"Edward Morgan Forster\"
+       -> forgets the alias, books keep their author",
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .short("r")
+                        .long("remove")
+                        .help("Remove the alias"),
+                )
+                .arg(
+                    Arg::with_name("alias")
+                        .help("The other name, e.g. \"Edward Morgan Forster\""),
+                )
+                .arg(
+                    Arg::with_name("author")
+                        .help("The author's name, e.g. \"E. M. Forster\""),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("config")
                 .about("Shows the configuration")
@@ -161,17 +554,79 @@ fn parse_command_line() -> Command {
                         .help("Display configuration defaults"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("cover")
+                .about("Shows or extracts cover images")
+                .usage(
+                    "root cover [FLAGS] [OPTIONS] [query]...
+
+Covers are taken from the e-books and kept in the cover.directory, with
+thumbnails of cover.width by cover.height pixels.
+
+EXAMPLES:
+    root cover author:forster
+       -> the cover image of each title by Forster
+
+    root cover --thumbnail howards end
+       -> the thumbnail of each matching title
+
+    root cover --extract he.png howards end
+       -> writes the cover of the first match to 'he.png'",
+                )
+                .arg(
+                    Arg::with_name("thumbnail")
+                        .short("t")
+                        .long("thumbnail")
+                        .help("Use the thumbnail instead of the full size cover"),
+                )
+                .arg(
+                    Arg::with_name("extract")
+                        .short("x")
+                        .long("extract")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("Write the cover of the first match to a .jpg, .png or .gif file"),
+                )
+                .arg(
+                    Arg::with_name("query")
+                        .help("Query terms, e.g. author:forster howards end")
+                        .multiple(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("fields")
-                .about("Shows fields that can be used in queries"),
+                .about("Shows fields that can be used in queries")
+                .usage(
+                    "root fields
+
+Lists each field with its type, the number of books with a value and
+some example values. Text fields match any part of the value, list fields
+match any of their values, date fields support ranges such as
+year:1900..1920 and identifiers match exactly.",
+                ),
         ).subcommand(
         SubCommand::with_name("find")
             .about("Find metadata online")
+            .usage(
+                "root find [FLAGS] <path|query>...
+
+EXAMPLES:
+    root find file.epub
+       -> best match for 'file.epub'
+
+    root find -s author:dickens two cities
+       -> all matches for a library book, ranked by score",
+            )
             .arg(
                 Arg::with_name("scores")
                     .short("s")
                     .long("show-scores")
                     .help("Show multiple results ranked by score"),
+            )
+            .arg(
+                Arg::with_name("target")
+                    .help("Path to an e-book file or a query for a library book")
+                    .multiple(true),
             ),
     )
         .subcommand(
@@ -180,6 +635,12 @@ fn parse_command_line() -> Command {
                 .usage(
                     "root import <path>
 
+Books are placed in the library directory following the import.path
+layout, e.g. '{author_sort}/{series}/{title} ({year}).{ext}'. Available
+names are title, author, author_sort, authors, series, series_index,
+publisher, imprint, year, isbn, asin and ext; empty path components are
+left out.
+
 EXAMPLES:
     root import ~/Downloads/
        -> imports books from ~/Downloads/",
@@ -194,10 +655,14 @@ EXAMPLES:
             SubCommand::with_name("info")
                 .about("Display information for a file")
                 .usage(
-                    "root info <path>
+                    "root info [FLAGS] <path>
 EXAMPLES:
     root info file.epub
-       -> displays information for 'file.epub'",
+       -> displays information for 'file.epub'
+
+    root info --fetch --write file.epub
+       -> writes the best online match into 'file.epub', keeping
+          the original as 'file.epub.bak'",
                 )
                 .arg(
                     Arg::with_name("path")
@@ -209,23 +674,54 @@ EXAMPLES:
                         .short("f")
                         .long("fetch")
                         .help("Fetches missing information from the web"),
+                )
+                .arg(
+                    Arg::with_name("write")
+                        .short("w")
+                        .long("write")
+                        .requires("fetch")
+                        .help("Writes the best online match into the file"),
                 ),
         )
         .subcommand(
             SubCommand::with_name("list")
                 .about("Queries the library")
                 .usage(
-                    "root list [FLAGS]
+                    "root list [FLAGS] [query]...
 
 EXAMPLES:
     root list author:forster
       -> All titles by Forster
 
+    root list author:forster OR author:woolf
+      -> All titles by Forster or Woolf
+
+    root list -subject:poetry year:1900..1920 isbn:*
+      -> Titles from 1900 to 1920 with an ISBN, excluding poetry
+
     root list --author howards end
       -> All authors of matching titles
 
     root list --isbn
-      -> All known titles with ISBNs",
+      -> All known titles with ISBNs
+
+    root list --fields title,author,year author:forster
+      -> Titles, authors and years in aligned columns
+
+    root list --fields title,translator translator:\"constance garnett\"
+      -> Titles translated by Constance Garnett, with their translators
+
+    root list lang:en -lang:en-us
+      -> English titles other than those in American English
+
+    root list --format csv
+      -> The whole library as CSV, also json and tsv
+
+    root list --template '{author} - {title} ({year})'
+      -> Each title formatted with a template
+
+    root list --sort series series:*
+      -> Titles in a series, in series order",
                 )
                 .arg(
                     Arg::with_name("author")
@@ -244,18 +740,115 @@ EXAMPLES:
                         .short("t")
                         .long("table")
                         .help("Print the matches in a table"),
+                )
+                .arg(
+                    Arg::with_name("fields")
+                        .short("f")
+                        .long("fields")
+                        .takes_value(true)
+                        .value_name("FIELDS")
+                        .help("Comma separated fields to show, e.g. title,author,year"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["json", "csv", "tsv"])
+                        .conflicts_with("table")
+                        .help("Print the matches as JSON, CSV or TSV"),
+                )
+                .arg(
+                    Arg::with_name("template")
+                        .long("template")
+                        .takes_value(true)
+                        .value_name("TEMPLATE")
+                        .conflicts_with_all(&["table", "format", "fields", "isbn"])
+                        .help("Print each match with a template, e.g. '{author} - {title} ({year})'"),
+                )
+                .arg(
+                    Arg::with_name("sort")
+                        .short("s")
+                        .long("sort")
+                        .takes_value(true)
+                        .value_name("FIELD")
+                        .help("Sort the matches by a field instead of the title, e.g. series or year"),
+                )
+                .arg(
+                    Arg::with_name("query")
+                        .help("Query terms, e.g. author:forster -subject:poetry year:1900..1920")
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("update")
+                .about("Updates the library")
+                .usage(
+                    "root update [FLAGS]
+
+Scans the library directory and database for missing, untracked and moved
+files and for metadata that differs from the files, offering to resolve each.
+
+EXAMPLES:
+    root update --dry-run
+       -> lists inconsistencies without changing anything
+
+    root update --fetch --yes
+       -> also checks metadata online and applies every resolution
+
+    root update --fetch --write
+       -> also writes accepted metadata into the e-book files, keeping
+          each original as a .bak file",
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .conflicts_with("yes")
+                        .help("Show inconsistencies without resolving them"),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .short("y")
+                        .long("yes")
+                        .help("Resolve every inconsistency without asking"),
+                )
+                .arg(
+                    Arg::with_name("fetch")
+                        .short("f")
+                        .long("fetch")
+                        .help("Compare metadata with an online source"),
+                )
+                .arg(
+                    Arg::with_name("write")
+                        .short("w")
+                        .long("write")
+                        .help("Write accepted metadata changes into the e-book files"),
                 ),
         )
-        .subcommand(SubCommand::with_name("update").about("Updates the library"))
         .get_matches();
     match matches.subcommand() {
+        ("alias", Some(alias)) => Command::Alias {
+            remove: alias.is_present("remove"),
+            alias: alias.value_of("alias").map(String::from),
+            author: alias.value_of("author").map(String::from),
+        },
         ("config", Some(config)) => Command::Config {
             path: config.is_present("path"),
             default: config.is_present("default"),
         },
+        ("cover", Some(cover)) => Command::Cover {
+            thumbnail: cover.is_present("thumbnail"),
+            extract: cover.value_of("extract").map(String::from),
+            query: cover.values_of("query")
+                .map(|values| values.map(String::from).collect())
+                .unwrap_or_default(),
+        },
         ("fields", _) => Command::Fields,
         ("find", Some(find)) => Command::Find {
-            show_scores: find.is_present("scores")
+            show_scores: find.is_present("scores"),
+            target: find.values_of("target")
+                .map(|values| values.map(String::from).collect())
+                .unwrap_or_default(),
         },
         ("import", Some(import)) => Command::Import {
             path: import.value_of("path").map(|v| String::from(v)).unwrap(),
@@ -263,13 +856,26 @@ EXAMPLES:
         ("info", Some(info)) => Command::Info {
             path: info.value_of("path").unwrap().to_string(),
             fetch: info.is_present("fetch"),
+            write: info.is_present("write"),
         },
         ("list", Some(list)) => Command::List {
             author: list.is_present("author"),
             isbn: list.is_present("isbn"),
             table: list.is_present("table"),
+            fields: list.value_of("fields").map(String::from),
+            format: list.value_of("format").map(String::from),
+            template: list.value_of("template").map(String::from),
+            sort: list.value_of("sort").map(String::from),
+            query: list.values_of("query")
+                .map(|values| values.map(String::from).collect())
+                .unwrap_or_default(),
+        },
+        ("update", Some(update)) => Command::Update {
+            dry_run: update.is_present("dry-run"),
+            yes: update.is_present("yes"),
+            fetch: update.is_present("fetch"),
+            write: update.is_present("write"),
         },
-        ("update", _) => Command::Update,
         _ => unreachable!(),
     }
 }
@@ -282,6 +888,9 @@ mod tests {
 
     use assert_cmd::prelude::*;
 
+    use crate::application::query::Field;
+    use crate::interface::format::Output;
+
     #[test]
     fn find_returns_successfully() {
         let mut cmd = Command::cargo_bin("roots").unwrap();
@@ -324,7 +933,9 @@ mod tests {
 
     #[test]
     fn fields_handles_no_database() {
+        let home = tempfile::tempdir().unwrap();
         let mut cmd = Command::cargo_bin("roots").unwrap();
+        cmd.env("ROOTS_DIRECTORY", home.path().join("Books")).env("ROOTS_LIBRARY", home.path().join("library.db"));
         cmd.arg("fields");
         let assert = cmd.assert();
         assert
@@ -335,27 +946,208 @@ mod tests {
 
     #[test]
     fn list_handles_no_database() {
-        let assert = Command::cargo_bin("roots").unwrap().arg("list").assert();
+        let home = tempfile::tempdir().unwrap();
+        let assert = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("list")
+            .assert();
         assert
             .success()
             .stdout("No titles to list, is roots initialised?\n")
             .code(0);
     }
 
+    #[test]
+    fn list_rejects_invalid_query() {
+        let assert = Command::cargo_bin("roots").unwrap().arg("list").arg("colour:red").assert();
+        assert
+            .failure()
+            .stderr("root: invalid query, unknown field 'colour'\n")
+            .code(2);
+    }
+
+    #[test]
+    fn list_formats_include_the_isbn_when_asked() {
+        let csv = super::list_output(true, false, Some("title,author".to_string()), Some("csv".to_string()), None).unwrap();
+        assert!(matches!(csv, Output::Delimited(fields, ',') if fields == vec![Field::Title, Field::Author, Field::Isbn]));
+        let json = super::list_output(true, false, Some("isbn,title".to_string()), Some("json".to_string()), None).unwrap();
+        assert!(matches!(json, Output::Json(fields) if fields == vec![Field::Isbn, Field::Title]));
+    }
+
+    #[test]
+    fn list_rejects_unknown_template_names() {
+        let assert = Command::cargo_bin("roots").unwrap()
+            .arg("list").arg("--template").arg("{title} {colour}")
+            .assert();
+        assert
+            .failure()
+            .stderr("root: invalid template, unknown name '{colour}' in template\n")
+            .code(2);
+    }
+
     #[test]
     fn update_handles_no_database() {
-        let assert = Command::cargo_bin("roots").unwrap().arg("update").assert();
+        let home = tempfile::tempdir().unwrap();
+        let assert = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("update")
+            .assert();
+        assert
+            .success()
+            .stdout("No titles found, is roots initialised?\n")
+            .code(0);
+    }
+
+    #[test]
+    fn cover_handles_no_database() {
+        let home = tempfile::tempdir().unwrap();
+        let assert = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("cover").arg("author:forster")
+            .assert();
         assert
             .success()
             .stdout("No titles found, is roots initialised?\n")
             .code(0);
     }
 
+    #[test]
+    fn update_dry_run_and_yes_conflict() {
+        let assert = Command::cargo_bin("roots").unwrap()
+            .arg("update").arg("--dry-run").arg("--yes")
+            .assert();
+        assert.failure().code(1);
+    }
+
+    #[test]
+    fn update_finds_untracked_files() {
+        let home = tempfile::tempdir().unwrap();
+        let books = home.path().join("Books");
+        std::fs::create_dir_all(&books).unwrap();
+        Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", &books)
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("import").arg("var/cache")
+            .assert()
+            .success();
+        std::fs::copy("var/cache/pg98.epub", books.join("copy.epub")).unwrap();
+        let output = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", &books)
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("update").arg("--dry-run")
+            .output()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).contains("untracked"));
+    }
+
     #[test]
     fn import_returns_successfully() {
-        let assert = Command::cargo_bin("roots").unwrap().arg("import").arg(".").assert();
+        let home = tempfile::tempdir().unwrap();
+        let empty = tempfile::tempdir().unwrap();
+        let assert = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("import").arg(empty.path())
+            .assert();
         assert.success()
             .stdout("No files found to import.\n")
             .code(0);
     }
+
+    #[test]
+    fn import_copies_books_into_library() {
+        let home = tempfile::tempdir().unwrap();
+        let source = tempfile::tempdir().unwrap();
+        std::fs::copy("var/cache/pg98.epub", source.path().join("pg98.epub")).unwrap();
+        let assert = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("import").arg(source.path())
+            .assert();
+        assert.success().code(0);
+        assert!(home.path().join("library.db").is_file());
+        assert!(source.path().join("pg98.epub").is_file());
+        assert!(!crate::filesystem::find_files(&home.path().join("Books"), &["epub"]).is_empty());
+    }
+
+    #[test]
+    fn list_shows_imported_titles() {
+        let home = tempfile::tempdir().unwrap();
+        Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("import").arg("var/cache")
+            .assert()
+            .success();
+        let output = Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("list")
+            .output()
+            .unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).contains("A Tale of Two Cities"));
+    }
+
+    #[test]
+    fn import_brings_documents_sidecars_along() {
+        let home = tempfile::tempdir().unwrap();
+        let inbox = home.path().join("inbox");
+        std::fs::create_dir(&inbox).unwrap();
+        std::fs::write(inbox.join("notes.md"), "# Notes\n").unwrap();
+        std::fs::write(inbox.join("notes.roots.yml"), "title: Field Notes\nauthor: Ann Writer\n").unwrap();
+        Command::cargo_bin("roots").unwrap()
+            .env("ROOTS_DIRECTORY", home.path().join("Books"))
+            .env("ROOTS_LIBRARY", home.path().join("library.db"))
+            .arg("import").arg(&inbox)
+            .assert()
+            .success();
+        assert!(home.path().join("Books/Ann Writer/Field Notes.md").is_file());
+        assert!(home.path().join("Books/Ann Writer/Field Notes.roots.yml").is_file());
+    }
+
+    #[test]
+    fn aliases_merge_authors() {
+        let home = tempfile::tempdir().unwrap();
+        let inbox = home.path().join("inbox");
+        std::fs::create_dir(&inbox).unwrap();
+        std::fs::write(inbox.join("maurice.md"), "---\ntitle: Maurice\nauthor: Edward Morgan Forster\n---\n").unwrap();
+        std::fs::write(inbox.join("he.md"), "---\ntitle: Howards End\nauthor: Forster, E. M.\n---\n").unwrap();
+        let roots = || {
+            let mut cmd = Command::cargo_bin("roots").unwrap();
+            cmd.env("ROOTS_DIRECTORY", home.path().join("Books")).env("ROOTS_LIBRARY", home.path().join("library.db"));
+            cmd
+        };
+        roots().arg("import").arg(&inbox).assert().success();
+        roots().arg("alias").arg("Edward Morgan Forster").arg("E. M. Forster").assert().success();
+        let authors = roots().arg("list").arg("--author").output().unwrap();
+        assert_eq!("E. M. Forster\n", String::from_utf8_lossy(&authors.stdout));
+        let aliases = roots().arg("alias").output().unwrap();
+        assert_eq!("Edward Morgan Forster -> E. M. Forster\n", String::from_utf8_lossy(&aliases.stdout));
+    }
+
+    #[test]
+    fn alias_needs_an_author() {
+        let assert = Command::cargo_bin("roots").unwrap().arg("alias").arg("Edward Morgan Forster").assert();
+        assert
+            .failure()
+            .stderr("root: an alias needs the name of its author\n")
+            .code(2);
+    }
+
+    #[test]
+    fn import_fails_missing_path() {
+        let assert = Command::cargo_bin("roots").unwrap().arg("import").arg("does/not/exist").assert();
+        assert.failure().code(5);
+    }
+
+    #[test]
+    fn info_rejects_unsupported_formats() {
+        let assert = Command::cargo_bin("roots").unwrap().arg("info").arg("Cargo.toml").assert();
+        assert
+            .failure()
+            .stderr("root: Cargo.toml: unsupported format\n")
+            .code(3);
+    }
 }
@@ -0,0 +1,183 @@
+use serde_json::{Map, Value};
+
+use crate::application::query::{Field, FieldKind};
+use crate::application::template::Template;
+use crate::database::Record;
+
+/// Longest value shown in a table cell.
+const CELL_WIDTH: usize = 40;
+
+/// How `list` prints the books it finds.
+#[derive(Debug)]
+pub enum Output {
+    /// One line per book, values separated by ` - `.
+    Lines(Vec<Field>),
+    /// Aligned columns with a header.
+    Table(Vec<Field>),
+    Json(Vec<Field>),
+    /// Comma or tab separated values with a header.
+    Delimited(Vec<Field>, char),
+    Template(Template),
+}
+
+impl Output {
+    pub fn render(&self, records: &[Record]) -> String {
+        match self {
+            Output::Lines(fields) => records.iter()
+                .map(|record| fields.iter()
+                    .map(|&field| join(record, field))
+                    .filter(|value| !value.is_empty())
+                    .collect::<Vec<String>>()
+                    .join(" - ") + "\n")
+                .collect(),
+            Output::Table(fields) => table(fields, records),
+            Output::Json(fields) => json(fields, records),
+            Output::Delimited(fields, separator) => delimited(fields, *separator, records),
+            Output::Template(template) => records.iter()
+                .map(|record| template.render(|name| {
+                    Field::from_name(name).map(|field| join(record, field))
+                }) + "\n")
+                .collect(),
+        }
+    }
+}
+
+/// Parses a comma separated list of field names, e.g. `title,author,year`.
+pub fn parse_fields(names: &str) -> Result<Vec<Field>, String> {
+    names.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Field::from_name(name).ok_or(format!("unknown field '{}'", name)))
+        .collect()
+}
+
+fn join(record: &Record, field: Field) -> String {
+    record.values(field).join("; ")
+}
+
+fn table(fields: &[Field], records: &[Record]) -> String {
+    let header = fields.iter().map(|field| field.name().to_uppercase()).collect::<Vec<String>>();
+    let rows = records.iter()
+        .map(|record| fields.iter().map(|&field| cell(&join(record, field))).collect::<Vec<String>>())
+        .collect::<Vec<Vec<String>>>();
+    let widths = (0..fields.len())
+        .map(|i| std::iter::once(&header)
+            .chain(rows.iter())
+            .map(|row| row[i].chars().count())
+            .max()
+            .unwrap_or(0))
+        .collect::<Vec<usize>>();
+    std::iter::once(&header)
+        .chain(rows.iter())
+        .map(|row| row.iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string() + "\n")
+        .collect()
+}
+
+fn cell(value: &str) -> String {
+    let value = value.replace(['\n', '\t'], " ");
+    if value.chars().count() > CELL_WIDTH {
+        format!("{}...", value.chars().take(CELL_WIDTH - 3).collect::<String>())
+    } else {
+        value
+    }
+}
+
+fn json(fields: &[Field], records: &[Record]) -> String {
+    let books = records.iter()
+        .map(|record| {
+            let object = fields.iter()
+                .map(|&field| {
+                    let values = record.values(field);
+                    // a book may have several files, so file fields are lists too
+                    let many = field.kind() == FieldKind::List
+                        || matches!(field, Field::Path | Field::Format | Field::Hash | Field::Imported);
+                    let value = if many {
+                        Value::from(values)
+                    } else {
+                        values.into_iter().next().map(Value::from).unwrap_or(Value::Null)
+                    };
+                    (field.name().to_string(), value)
+                })
+                .collect::<Map<String, Value>>();
+            Value::Object(object)
+        })
+        .collect::<Vec<Value>>();
+    serde_json::to_string_pretty(&books).unwrap_or_default() + "\n"
+}
+
+fn delimited(fields: &[Field], separator: char, records: &[Record]) -> String {
+    let line = |values: Vec<String>| -> String {
+        values.iter()
+            .map(|value| escape(value, separator))
+            .collect::<Vec<String>>()
+            .join(&separator.to_string()) + "\n"
+    };
+    std::iter::once(line(fields.iter().map(|field| field.name().to_string()).collect()))
+        .chain(records.iter().map(|record| line(fields.iter().map(|&field| join(record, field)).collect())))
+        .collect()
+}
+
+/// Quotes CSV values when needed; TSV has no quoting so tabs and newlines become spaces.
+fn escape(value: &str, separator: char) -> String {
+    if separator == '\t' {
+        value.replace(['\t', '\n', '\r'], " ")
+    } else if value.contains([separator, '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::application::book::Book;
+
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        vec![Record {
+            id: 1,
+            book: Book {
+                title: Some("Howards End".to_string()),
+                author: Some(vec!["Forster, E. M.".to_string()]),
+                description: Some("Only \"connect\"".to_string()),
+                ..Default::default()
+            },
+            files: Vec::new(),
+        }]
+    }
+
+    #[test]
+    fn csv_values_are_quoted() {
+        let output = Output::Delimited(vec![Field::Title, Field::Author, Field::Description], ',');
+        assert_eq!(
+            "title,author,description\nHowards End,\"Forster, E. M.\",\"Only \"\"connect\"\"\"\n",
+            output.render(&records())
+        );
+    }
+
+    #[test]
+    fn table_columns_are_aligned() {
+        let output = Output::Table(vec![Field::Title, Field::Author]);
+        assert_eq!("TITLE        AUTHOR\nHowards End  Forster, E. M.\n", output.render(&records()));
+    }
+
+    #[test]
+    fn json_lists_are_arrays() {
+        let output = Output::Json(vec![Field::Author, Field::Isbn]);
+        let value: Value = serde_json::from_str(&output.render(&records())).unwrap();
+        assert_eq!(serde_json::json!([{"author": ["Forster, E. M."], "isbn": null}]), value);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert_eq!(Ok(vec![Field::Title, Field::Year]), parse_fields("title, year"));
+        assert!(parse_fields("title,colour").is_err());
+    }
+}
@@ -1 +1,2 @@
 pub mod cli;
+mod format;
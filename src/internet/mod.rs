@@ -10,7 +10,9 @@
 // https://www.googleapis.com/books/v1/volumes?q=a+tale+of+two+cities
 // https://www.googleapis.com/books/v1/volumes/5EIPAAAAQAAJ
 
-use chrono::{DateTime, Utc};
+use crate::application::book::date::PartialDate;
+use crate::application::book::identifier::Identifier;
+use crate::application::book::language;
 use crate::application::book::Book;
 use crate::internet::metadata::Volume;
 
@@ -20,26 +22,48 @@ impl From<&Volume> for Book {
         Book {
             title: info.title,
             author: info.authors,
+            author_sort: None,
+            contributors: None,
             publisher: info.publisher,
-            publication_date:
-            info.published_date
-                .and_then(|s| DateTime::parse_from_rfc3339(s.as_str()).ok())
-                .map(|dt| dt.with_timezone(&Utc)),
+            publication_date: info.published_date.and_then(|s| PartialDate::parse(&s)),
             imprint: None,
             description: info.description,
+            language: info.language.and_then(|language| language::normalise(&language)),
             subject: None,
+            series: None,
+            series_index: None,
             asin: None,
-            isbn: None,
+            isbn: info.industry_identifiers
+                .and_then(|identifiers| {
+                    let isbn = |kind: &str| identifiers.iter()
+                        .find(|i| i.kind == kind)
+                        .map(|i| i.identifier.clone());
+                    isbn("ISBN_13").or_else(|| isbn("ISBN_10"))
+                }),
+            identifiers: Some(identifiers(volume)).filter(|identifiers| !identifiers.is_empty()),
         }
     }
 }
 
+/// The volume's industry identifiers and its Google Books id.
+fn identifiers(volume: &Volume) -> Vec<Identifier> {
+    volume.volume_info.industry_identifiers.iter()
+        .flatten()
+        .map(|i| match i.kind.as_str() {
+            "ISBN_10" | "ISBN_13" => Identifier::new("isbn", &i.identifier),
+            _ => Identifier::parse(None, &i.identifier),
+        })
+        .chain(volume.id.iter().map(|id| Identifier::new("google", id)))
+        .collect()
+}
+
 pub mod metadata {
     use std::vec::Vec;
     use serde::Deserialize;
     use url::Url;
 
     use crate::application::book::Book;
+    use crate::error::RootsError;
 
     #[derive(Debug, Deserialize, Clone)]
     pub struct VolumeIdentifier {
@@ -63,6 +87,7 @@ pub mod metadata {
 
     #[derive(Debug, Deserialize, Clone)]
     pub struct Volume {
+        pub id: Option<String>,
         #[serde(rename = "volumeInfo")]
         pub volume_info: VolumeInfo,
 
@@ -73,28 +98,80 @@ pub mod metadata {
         kind: String,
         #[serde(rename = "totalItems")]
         total_items: u64,
+        #[serde(default)]
         pub items: Vec<Volume>,
     }
 
-    pub fn request(book: &Book) -> Result<VolumeResponse, Box<dyn std::error::Error>> {
+    const VOLUMES: &str = "https://www.googleapis.com/books/v1/volumes";
+
+    /// Searches for candidate volumes, by ISBN and then by title and authors.
+    pub fn search(book: &Book) -> Result<Vec<Volume>, RootsError> {
+        let mut volumes: Vec<Volume> = Vec::new();
+        for terms in search_terms(book) {
+            for volume in query(&terms)?.items {
+                if volume.id.is_none() || !volumes.iter().any(|v| v.id == volume.id) {
+                    volumes.push(volume);
+                }
+            }
+        }
+        Ok(volumes)
+    }
+
+    /// Search terms in the order they should be tried.
+    fn search_terms(book: &Book) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(isbn) = book.isbn.as_ref() {
+            terms.push(format!("isbn:{}", isbn.replace('-', "")));
+        }
+        if let Some(title) = book.title.as_ref() {
+            let authors = book.author.iter()
+                .flatten()
+                .map(|author| format!(" inauthor:\"{}\"", author))
+                .collect::<String>();
+            terms.push(format!("intitle:\"{}\"{}", title, authors));
+        }
+        terms
+    }
+
+    fn query(terms: &str) -> Result<VolumeResponse, RootsError> {
         println!("Making request");
-        let book_title = match book.title.as_ref() {
-            Some(title) => title,
-            None => panic!("Book is not identifiable! {:?}", book)
-        };
-        let url = match Url::parse(format!(
-            "https://www.googleapis.com/books/v1/volumes?q={title}",
-            title = book_title).as_str()) {
-            Ok(url) => url,
-            Err(error) => panic!("Problem parsing title {:?}", error)
-        };
+        let url = Url::parse_with_params(VOLUMES, &[("q", terms)])
+            .map_err(|error| RootsError::Network(format!("cannot build query, {}", error)))?;
         println!("Requesting {}", url);
-        let resp = match reqwest::blocking::get(url)?
-            .json::<VolumeResponse>() {
-            Ok(resp) => resp,
-            Err(error) => panic!("Problem making request {:?}", error)
-        };
-        Ok(resp)
+        Ok(reqwest::blocking::get(url)?.error_for_status()?.json::<VolumeResponse>()?)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn isbn_is_searched_first() {
+            let book = Book {
+                title: Some("A Tale of Two Cities".to_string()),
+                author: Some(vec!["Charles Dickens".to_string()]),
+                author_sort: None,
+                contributors: None,
+                publisher: None,
+                publication_date: None,
+                imprint: None,
+                description: None,
+                language: None,
+                subject: None,
+                series: None,
+                series_index: None,
+                asin: None,
+                isbn: Some("978-0-14-143960-0".to_string()),
+                identifiers: None,
+            };
+            assert_eq!(
+                vec![
+                    "isbn:9780141439600".to_string(),
+                    "intitle:\"A Tale of Two Cities\" inauthor:\"Charles Dickens\"".to_string(),
+                ],
+                search_terms(&book)
+            );
+        }
     }
 }
 
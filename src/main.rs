@@ -3,29 +3,48 @@ extern crate clap;
 extern crate chrono;
 extern crate config;
 extern crate core;
+extern crate encoding_rs;
 extern crate epub;
+extern crate image;
+extern crate lopdf;
 extern crate maplit;
 extern crate mobi;
 extern crate regex;
 extern crate reqwest;
+extern crate roxmltree;
+extern crate rusqlite;
 extern crate serde;
 extern crate serde_yaml;
 extern crate serde_json;
+extern crate sevenz_rust;
+extern crate sha2;
 extern crate url;
+extern crate walkdir;
+extern crate zip;
 
+use std::process;
 
 use configuration::Configuration;
+use error::RootsError;
 use interface::cli;
 
 mod application;
 mod configuration;
 mod database;
+mod error;
 mod filesystem;
 mod interface;
 mod internet;
 
-fn main() -> Result<(), ()> {
-    let cfg = Configuration::new();
-    let app = cli::Application::new(cfg);
-    app.run()
+fn main() {
+    let result = Configuration::new()
+        .and_then(|cfg| cli::Application::new(cfg).run());
+    if let Err(error) = result {
+        exit(error);
+    }
+}
+
+fn exit(error: RootsError) -> ! {
+    eprintln!("root: {}", error);
+    process::exit(error.exit_code())
 }